@@ -1,22 +1,168 @@
 mod modules;
 
-use clap::Parser;
-use modules::cli::{Cli, Commands, IssueCertArgs, WriteProxyArgs};
+use clap::{CommandFactory, FromArgMatches};
+use modules::cli::{
+    CheckRenewalArgs, Cli, Commands, IssueCertArgs, RollbackArgs, WriteDefaultArgs, WriteProxyArgs,
+};
 use modules::commands::{
-    issue_cert, print_params_table, setup_system, write_nginx_default, write_proxy_config,
+    check_configs, check_renewal, combine_pem, export_account, import_account, issue_cert,
+    list_presets, migrate_env_file, print_params_table, probe_dns, prune_certs, reload_upstream,
+    restore_config, rollback_cert, set_no_clobber_system, set_reload_timeout, setup_system,
+    show_status, show_template, test_backend, verify_cert_domain, wait_propagation,
+    write_nginx_default, write_proxy_config,
 };
+use modules::config::load_config;
+use modules::env::{apply_env_files, check_env_keys};
+use modules::error::AppError;
+use modules::log::{debug, finish_step, set_fail_fast, set_verbose, warning_count};
+use modules::runner::SystemRunner;
+use modules::summary::{change_count, start_trace, trace_event, write_summary};
+use serde_json::json;
+use std::{env, path::Path, process};
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (command, dry_run) = match matches.subcommand() {
+        Some((name, sub_matches)) => (
+            name.to_string(),
+            sub_matches
+                .try_get_one::<bool>("dry_run")
+                .ok()
+                .flatten()
+                .copied()
+                .unwrap_or(false),
+        ),
+        None => (String::new(), false),
+    };
+    let summary_json = cli.summary_json.clone();
+    set_verbose(cli.verbose);
+    set_reload_timeout(cli.reload_timeout);
+    set_no_clobber_system(cli.no_clobber_system);
+    if let Err(err) = change_working_dir(cli.working_dir.as_deref()) {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+    if let Some(path) = &cli.trace {
+        if let Err(err) = start_trace(path) {
+            eprintln!("Error: {}", err);
+            process::exit(err.exit_code());
+        }
+        trace_event(json!({ "event": "start", "command": command, "dry_run": dry_run }));
+    }
+
+    let detect_drift = cli.detect_drift;
+    let fail_on_warn = cli.fail_on_warn;
+    if cli.fail_fast {
+        let command = command.clone();
+        let summary_json = summary_json.clone();
+        set_fail_fast(move |message| {
+            finish_step();
+            let result = Err(AppError::Warnings(format!(
+                "--fail-fast: stopped at warning: {}",
+                message
+            )));
+            finish_run(&command, dry_run, summary_json.as_deref(), result);
+        });
+    }
+    let result = if detect_drift && !dry_run {
+        Err(AppError::Validation(
+            "--detect-drift requires --dry-run".to_string(),
+        ))
+    } else {
+        run(cli)
+    };
+    finish_step();
+    let result = result.and_then(|()| match change_count() {
+        count if detect_drift && count > 0 => Err(AppError::Drift(format!(
+            "Drift detected: {} planned change(s)",
+            count
+        ))),
+        _ => Ok(()),
+    });
+    // Warnings only fail a run that otherwise succeeded; real errors keep their own exit code.
+    let result = result.and_then(|()| match warning_count() {
+        count if fail_on_warn && count > 0 => Err(AppError::Warnings(format!(
+            "--fail-on-warn: {} warning(s) printed",
+            count
+        ))),
+        _ => Ok(()),
+    });
+    finish_run(&command, dry_run, summary_json.as_deref(), result);
+}
+
+fn finish_run(
+    command: &str,
+    dry_run: bool,
+    summary_json: Option<&Path>,
+    result: Result<(), AppError>,
+) {
+    trace_event(json!({
+        "event": "end",
+        "status": if result.is_ok() { "success" } else { "failed" },
+        "exit_code": result.as_ref().err().map_or(0, AppError::exit_code),
+        "error": result.as_ref().err().map(ToString::to_string),
+    }));
+    if let Some(path) = summary_json
+        && let Err(err) = write_summary(path, command, dry_run, &result)
+    {
+        eprintln!("Error: {}", err);
+    }
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn change_working_dir(path: Option<&Path>) -> Result<(), AppError> {
+    if let Some(path) = path {
+        env::set_current_dir(path).map_err(|e| {
+            AppError::Io(format!(
+                "Failed to change working directory to {}: {e}",
+                path.display()
+            ))
+        })?;
+    }
+    if let Ok(current) = env::current_dir() {
+        debug(&format!("Working directory: {}", current.display()));
+    }
+    Ok(())
+}
 
-fn main() -> Result<(), String> {
-    let cli = Cli::parse();
-    let env_overrides = modules::env::to_env_map(&cli.env_overrides);
+fn run(cli: Cli) -> Result<(), AppError> {
+    let mut env_overrides = modules::env::to_env_map(&cli.env_overrides);
+    apply_env_files(&mut env_overrides, &cli.env_files, cli.env_file_override)?;
+    if let Some(path) = &cli.config {
+        for (key, value) in load_config(path)? {
+            env_overrides.entry(key).or_insert(value);
+        }
+    }
+    if cli.strict_env {
+        check_env_keys(&env_overrides)?;
+    }
 
     match cli.command {
         Commands::Setup {
             install_zsh,
             install_cron,
             install_nginx,
+            mirror_nginx,
+            mirror_apt,
+            download_retries,
+            nginx_key_sha256,
+            dry_run,
+        } => setup_system(
+            &SystemRunner,
+            &env_overrides,
+            install_zsh,
+            install_cron,
+            install_nginx,
+            mirror_nginx,
+            mirror_apt,
+            download_retries,
+            nginx_key_sha256,
             dry_run,
-        } => setup_system(install_zsh, install_cron, install_nginx, dry_run),
+        ),
         Commands::IssueCert {
             cf_token,
             cf_account_id,
@@ -33,8 +179,40 @@ fn main() -> Result<(), String> {
             key_output_path,
             nginx_bin,
             reload_nginx,
+            offline,
+            wait_for_propagation,
+            propagation_timeout,
+            dns_sleep,
+            cron_deploy,
+            dual_cert,
+            acme_config_home,
+            acme_cert_home,
+            propagation_check,
+            wildcard_only,
+            skip_cache_removal,
+            cert_owner,
+            cert_owner_dir,
+            renew_threshold_days,
+            force_reissue,
+            challenge_alias,
+            domain_alias,
+            verify_cf_token,
+            cert_base_dir,
+            confirm_reload,
+            yes,
+            purge_on_fail,
+            no_cron,
+            validate_all,
+            acme_args,
+            preferred_chain,
+            cf_purge_cache,
+            must_staple,
+            cert_name_scheme,
+            cert_filename,
+            key_filename,
             dry_run,
         } => issue_cert(
+            &SystemRunner,
             &env_overrides,
             IssueCertArgs {
                 cf_token,
@@ -51,6 +229,37 @@ fn main() -> Result<(), String> {
                 cert_output_path,
                 key_output_path,
                 nginx_bin,
+                offline,
+                wait_for_propagation,
+                propagation_timeout,
+                dns_sleep,
+                cron_deploy,
+                dual_cert,
+                acme_config_home,
+                acme_cert_home,
+                propagation_check,
+                wildcard_only,
+                skip_cache_removal,
+                cert_owner,
+                cert_owner_dir,
+                renew_threshold_days,
+                force_reissue,
+                challenge_alias,
+                domain_alias,
+                verify_cf_token,
+                cert_base_dir,
+                confirm_reload,
+                yes,
+                purge_on_fail,
+                no_cron,
+                validate_all,
+                acme_args,
+                preferred_chain,
+                cf_purge_cache,
+                must_staple,
+                cert_name_scheme,
+                cert_filename,
+                key_filename,
             },
             reload_nginx,
             dry_run,
@@ -61,14 +270,71 @@ fn main() -> Result<(), String> {
             cert_dir_name,
             domain,
             output_path,
+            template,
+            watch,
+            watch_interval,
+            test_config,
+            dual_cert,
+            bind,
+            default_action,
+            hsts,
+            hsts_max_age,
+            hsts_include_subdomains,
+            hsts_preload,
+            post_write_cmd,
+            ipv6,
+            ipv6only,
+            cert_base_dir,
+            set_values,
+            server_tokens,
+            output_owner,
+            inline_certs,
+            cert_name_scheme,
+            cert_filename,
+            key_filename,
+            min_tls_version,
+            backup,
+            keep_backups,
+            stub_status,
+            stub_status_allow,
             dry_run,
         } => write_nginx_default(
+            &SystemRunner,
             &env_overrides,
-            cert_path,
-            key_path,
-            cert_dir_name,
-            domain,
-            output_path,
+            WriteDefaultArgs {
+                cert_path,
+                key_path,
+                cert_dir_name,
+                domain,
+                output_path,
+                template,
+                watch,
+                watch_interval,
+                test_config,
+                dual_cert,
+                bind,
+                default_action,
+                hsts,
+                hsts_max_age,
+                hsts_include_subdomains,
+                hsts_preload,
+                post_write_cmd,
+                ipv6,
+                ipv6only,
+                cert_base_dir,
+                set_values,
+                server_tokens,
+                output_owner,
+                inline_certs,
+                cert_name_scheme,
+                cert_filename,
+                key_filename,
+                min_tls_version,
+                backup,
+                keep_backups,
+                stub_status,
+                stub_status_allow,
+            },
             dry_run,
         ),
         Commands::WriteProxyConfig {
@@ -80,8 +346,52 @@ fn main() -> Result<(), String> {
             cert_dir,
             output_dir,
             resolver,
+            force,
+            host_header,
+            no_backend_sni,
+            backend_ssl_verify,
+            routes_file,
+            emby_preset,
+            template,
+            watch,
+            watch_interval,
+            test_config,
+            dual_cert,
+            hsts,
+            hsts_max_age,
+            hsts_include_subdomains,
+            hsts_preload,
+            backends_file,
+            post_write_cmd,
+            ipv6,
+            ipv6only,
+            server,
+            cert_base_dir,
+            active_backend,
+            set_values,
+            geo_allow,
+            geoip_db,
+            strip_prefix,
+            server_tokens,
+            output_owner,
+            derive_domain_from_proxy,
+            inline_certs,
+            passthrough,
+            stub_status,
+            stub_status_allow,
+            rewrites,
+            cert_name_scheme,
+            cert_filename,
+            key_filename,
+            proxy_redirects,
+            min_tls_version,
+            forward_headers,
+            no_forward_headers,
+            backup,
+            keep_backups,
             dry_run,
         } => write_proxy_config(
+            &SystemRunner,
             &env_overrides,
             WriteProxyArgs {
                 proxy_domain,
@@ -92,9 +402,241 @@ fn main() -> Result<(), String> {
                 cert_dir,
                 output_dir,
                 resolvers: resolver,
+                host_header,
+                no_backend_sni,
+                backend_ssl_verify,
+                routes_file,
+                emby_preset,
+                template,
+                watch,
+                watch_interval,
+                test_config,
+                dual_cert,
+                hsts,
+                hsts_max_age,
+                hsts_include_subdomains,
+                hsts_preload,
+                backends_file,
+                post_write_cmd,
+                ipv6,
+                ipv6only,
+                server,
+                cert_base_dir,
+                active_backend,
+                set_values,
+                geo_allow,
+                geoip_db,
+                strip_prefix,
+                server_tokens,
+                output_owner,
+                derive_domain_from_proxy,
+                inline_certs,
+                passthrough,
+                stub_status,
+                stub_status_allow,
+                rewrites,
+                cert_name_scheme,
+                cert_filename,
+                key_filename,
+                proxy_redirects,
+                min_tls_version,
+                forward_headers,
+                no_forward_headers,
+                backup,
+                keep_backups,
             },
+            force,
+            dry_run,
+        ),
+        Commands::ReloadUpstream {
+            proxy_domain,
+            backend_url,
+            output_dir,
+            nginx_bin,
+            dry_run,
+        } => reload_upstream(
+            &SystemRunner,
+            &env_overrides,
+            proxy_domain,
+            backend_url,
+            output_dir,
+            nginx_bin,
             dry_run,
         ),
         Commands::PrintParams => print_params_table(),
+        Commands::ListPresets { format } => list_presets(&env_overrides, &format),
+        Commands::ShowTemplate { kind } => show_template(&kind),
+        Commands::Check {
+            nginx_bin,
+            output_dir,
+            lint,
+            since,
+        } => check_configs(
+            &SystemRunner,
+            &env_overrides,
+            nginx_bin,
+            output_dir,
+            lint,
+            since,
+        ),
+        Commands::TestBackend {
+            backend_url,
+            timeout,
+        } => test_backend(&SystemRunner, &env_overrides, backend_url, timeout),
+        Commands::ProbeDns {
+            resolver,
+            name,
+            timeout,
+        } => probe_dns(&env_overrides, resolver, name, timeout),
+        Commands::VerifyCertDomain { cert_path, domain } => {
+            verify_cert_domain(&SystemRunner, &env_overrides, cert_path, domain)
+        }
+        Commands::CombinePem {
+            cert_path,
+            key_path,
+            output,
+            order,
+            dry_run,
+        } => combine_pem(
+            &SystemRunner,
+            &env_overrides,
+            cert_path,
+            key_path,
+            &output,
+            &order,
+            dry_run,
+        ),
+        Commands::Rollback {
+            domain,
+            cert_dir,
+            cert_dir_name,
+            cert_output_path,
+            key_output_path,
+            nginx_bin,
+            to_backup,
+            list,
+            cert_base_dir,
+            confirm_reload,
+            yes,
+            cert_name_scheme,
+            cert_filename,
+            key_filename,
+            dry_run,
+        } => rollback_cert(
+            &SystemRunner,
+            &env_overrides,
+            RollbackArgs {
+                domain,
+                cert_dir,
+                cert_dir_name,
+                cert_output_path,
+                key_output_path,
+                nginx_bin,
+                to_backup,
+                list,
+                cert_base_dir,
+                confirm_reload,
+                yes,
+                cert_name_scheme,
+                cert_filename,
+                key_filename,
+            },
+            dry_run,
+        ),
+        Commands::RestoreConfig {
+            file,
+            to_backup,
+            list,
+            nginx_bin,
+            no_reload,
+            dry_run,
+        } => restore_config(
+            &SystemRunner,
+            &env_overrides,
+            &file,
+            to_backup,
+            list,
+            nginx_bin,
+            no_reload,
+            dry_run,
+        ),
+        Commands::CheckRenewal {
+            domain,
+            cert_dir,
+            cert_dir_name,
+            cert_base_dir,
+            threshold_days,
+            cert_name_scheme,
+            cert_filename,
+            key_filename,
+            dual_cert,
+        } => check_renewal(
+            &SystemRunner,
+            &env_overrides,
+            CheckRenewalArgs {
+                domain,
+                cert_dir,
+                cert_dir_name,
+                cert_base_dir,
+                threshold_days,
+                cert_name_scheme,
+                cert_filename,
+                key_filename,
+                dual_cert,
+            },
+        ),
+        Commands::Status {
+            acme_home,
+            warn_days,
+        } => show_status(&SystemRunner, &env_overrides, acme_home, warn_days),
+        Commands::PruneCerts {
+            acme_home,
+            keep,
+            dry_run,
+        } => prune_certs(&SystemRunner, &env_overrides, acme_home, &keep, dry_run),
+        Commands::ExportAccount {
+            acme_home,
+            acme_config_home,
+            output,
+            force,
+            dry_run,
+        } => export_account(
+            &SystemRunner,
+            &env_overrides,
+            acme_home,
+            acme_config_home,
+            &output,
+            force,
+            dry_run,
+        ),
+        Commands::ImportAccount {
+            acme_home,
+            acme_config_home,
+            input,
+            force,
+            dry_run,
+        } => import_account(
+            &SystemRunner,
+            &env_overrides,
+            acme_home,
+            acme_config_home,
+            &input,
+            force,
+            dry_run,
+        ),
+        Commands::Migrate { file, dry_run } => migrate_env_file(&file, dry_run),
+        Commands::WaitPropagation {
+            record,
+            value,
+            timeout,
+            check,
+        } => wait_propagation(
+            &SystemRunner,
+            &env_overrides,
+            &record,
+            value.as_deref(),
+            timeout,
+            &check,
+        ),
     }
 }
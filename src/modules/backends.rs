@@ -0,0 +1,367 @@
+use crate::modules::{env::resolve_from_envs, error::AppError};
+use std::{collections::HashMap, fs, path::Path};
+
+pub const BACKEND_URL_PREFIX: &str = "BACKEND_URL_";
+
+#[derive(Debug, Clone)]
+pub struct Backend {
+    pub url: String,
+    pub scheme: String,
+    pub address: String,
+    pub params: Vec<String>,
+}
+
+pub fn load_backends(path: &Path) -> Result<Vec<Backend>, AppError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+    let backends = parse_backends(&content)?;
+    if backends.is_empty() {
+        return Err(AppError::Validation(format!(
+            "No backends defined in {}",
+            path.display()
+        )));
+    }
+    check_backends(&backends)?;
+    Ok(backends)
+}
+
+pub fn env_backends(
+    env_overrides: &HashMap<String, String>,
+) -> Result<Option<Vec<Backend>>, AppError> {
+    let mut values = Vec::new();
+    for index in 1.. {
+        let key = match index {
+            1 => "BACKEND_URL".to_string(),
+            _ => format!("{}{}", BACKEND_URL_PREFIX, index),
+        };
+        let Some(value) = resolve_from_envs(env_overrides, &[key.as_str()]) else {
+            break;
+        };
+        values.push((key, value));
+    }
+    // A lone BACKEND_URL may carry a path, so it stays on the single-backend path.
+    if values.len() < 2 {
+        return Ok(None);
+    }
+    let backends = values
+        .iter()
+        .map(|(key, value)| {
+            parse_backend(value, |reason| {
+                AppError::Validation(format!("Invalid backend in {}: {}", key, reason))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    check_backends(&backends)?;
+    Ok(Some(backends))
+}
+
+fn check_backends(backends: &[Backend]) -> Result<(), AppError> {
+    let first = &backends[0];
+    if backends
+        .iter()
+        .any(|backend| backend.scheme != first.scheme)
+    {
+        return Err(AppError::Validation(
+            "All backends must use the same scheme (http or https)".to_string(),
+        ));
+    }
+    if backends
+        .iter()
+        .all(|backend| backend.params.iter().any(|p| p == "backup" || p == "down"))
+    {
+        return Err(AppError::Validation(
+            "At least one backend must be active (not backup or down)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn upstream_name(proxy_domain: &str) -> String {
+    format!("emby_{}", proxy_domain.replace(['.', '-'], "_"))
+}
+
+pub fn render_upstream(name: &str, backends: &[Backend]) -> String {
+    let servers = backends
+        .iter()
+        .map(|backend| {
+            let mut line = format!("    server {}", backend.address);
+            for param in &backend.params {
+                line.push(' ');
+                line.push_str(param);
+            }
+            line.push(';');
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("upstream {} {{\n{}\n}}\n\n", name, servers)
+}
+
+pub fn render_balancer(name: &str, backends: &[Backend]) -> String {
+    let members = backends
+        .iter()
+        .map(|backend| {
+            let mut line = format!("        BalancerMember {}", backend.url);
+            for param in &backend.params {
+                match param.as_str() {
+                    "backup" => line.push_str(" status=+H"),
+                    "down" => line.push_str(" status=+D"),
+                    weight => line.push_str(&weight.replace("weight=", " loadfactor=")),
+                }
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "    <Proxy \"balancer://{}\">\n{}\n    </Proxy>\n",
+        name, members
+    )
+}
+
+fn parse_backends(content: &str) -> Result<Vec<Backend>, AppError> {
+    let mut backends = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        backends.push(parse_backend(line, |reason| {
+            AppError::Validation(format!("Invalid backend on line {}: {}", index + 1, reason))
+        })?);
+    }
+    Ok(backends)
+}
+
+fn parse_backend(line: &str, invalid: impl Fn(String) -> AppError) -> Result<Backend, AppError> {
+    let mut tokens = line.split_whitespace();
+    let url = tokens.next().unwrap_or_default();
+    let (scheme, rest) = url
+        .split_once("://")
+        .filter(|(scheme, _)| *scheme == "http" || *scheme == "https")
+        .ok_or_else(|| invalid(format!("{} must start with http:// or https://", url)))?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    if authority.is_empty() || !path.is_empty() {
+        return Err(invalid(format!("{} must be scheme://host[:port]", url)));
+    }
+    let has_port = match authority.rsplit_once(':') {
+        Some((host, port)) => {
+            if port.parse::<u16>().is_err() || host.is_empty() {
+                return Err(invalid(format!("{} has an invalid port", url)));
+            }
+            !host.ends_with(':') && (!host.starts_with('[') || host.ends_with(']'))
+        }
+        None => false,
+    };
+    let address = if has_port {
+        authority.to_string()
+    } else if scheme == "https" {
+        format!("{}:443", authority)
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut params = Vec::new();
+    for token in tokens {
+        match token.split_once('=') {
+            Some(("weight", value)) => match value.parse::<u32>() {
+                Ok(weight) if weight > 0 => params.push(format!("weight={}", weight)),
+                _ => {
+                    return Err(invalid(format!(
+                        "weight must be a positive integer, got {}",
+                        value
+                    )));
+                }
+            },
+            None if token == "backup" || token == "down" => params.push(token.to_string()),
+            _ => return Err(invalid(format!("unknown marker {}", token))),
+        }
+    }
+    Ok(Backend {
+        url: url.to_string(),
+        scheme: scheme.to_string(),
+        address,
+        params,
+    })
+}
+
+pub fn passthrough_address(backend_url: &str) -> Result<String, AppError> {
+    let backend = parse_backend(backend_url, |reason| {
+        AppError::Validation(format!(
+            "Invalid --backend-url for --passthrough: {}",
+            reason
+        ))
+    })?;
+    if backend.scheme != "https" {
+        return Err(AppError::Validation(
+            "--passthrough requires an https:// backend URL".to_string(),
+        ));
+    }
+    Ok(backend.address)
+}
+
+pub fn render_sni_map(name: &str, proxy_domains: &[String], address: &str) -> String {
+    let entries = proxy_domains
+        .iter()
+        .map(|domain| format!("    {} {};", domain, address))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "map $ssl_preread_server_name ${} {{\n{}\n}}\n\n",
+        name, entries
+    )
+}
+
+// Swaps the single-backend origin in a generated nginx vhost, touching only the
+// `set $website` lines and the matching `proxy_ssl_name`.
+pub fn replace_backend_url(config: &str, backend_url: &str) -> Result<(String, String), AppError> {
+    let backend = parse_backend(backend_url, |reason| {
+        AppError::Validation(format!("Invalid --backend-url: {}", reason))
+    })?;
+    if !backend.params.is_empty() {
+        return Err(AppError::Validation(format!(
+            "--backend-url must be a plain URL, got {}",
+            backend_url
+        )));
+    }
+    let targets = config
+        .lines()
+        .filter_map(website_target)
+        .filter(|target| *target != "$1://$2")
+        .collect::<Vec<_>>();
+    let Some(current) = targets.first().map(|target| target.to_string()) else {
+        return Err(AppError::Validation(
+            "No `set $website` backend found in the config".to_string(),
+        ));
+    };
+    if targets.iter().any(|target| *target != current) {
+        return Err(AppError::Validation(format!(
+            "The config proxies to more than one backend ({}), rewrite it with write-proxy-config",
+            targets.join(", ")
+        )));
+    }
+    let old = parse_backend(&current, |reason| {
+        AppError::Validation(format!(
+            "Cannot replace backend {} in the config: {}",
+            current, reason
+        ))
+    })?;
+    let old_host = address_host(&old.address);
+    if config
+        .lines()
+        .any(|line| line.trim() == format!("upstream {} {{", old_host))
+    {
+        return Err(AppError::Validation(format!(
+            "The config load-balances via upstream {}, update the backends and rerun write-proxy-config",
+            old_host
+        )));
+    }
+    if old.scheme != backend.scheme {
+        return Err(AppError::Validation(format!(
+            "Switching the backend from {} to {} changes the TLS settings, rerun write-proxy-config",
+            old.scheme, backend.scheme
+        )));
+    }
+
+    let new_url = backend.url.trim_end_matches('/');
+    let old_ssl_name = format!("proxy_ssl_name {};", old_host);
+    let mut content = config
+        .lines()
+        .map(|line| {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            if website_target(line) == Some(current.as_str()) {
+                format!("{}set $website {};", indent, new_url)
+            } else if line.trim() == old_ssl_name {
+                format!(
+                    "{}proxy_ssl_name {};",
+                    indent,
+                    address_host(&backend.address)
+                )
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if config.ends_with('\n') {
+        content.push('\n');
+    }
+    Ok((content, current))
+}
+
+fn website_target(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("set $website ")?
+        .strip_suffix(';')
+        .map(str::trim)
+}
+
+fn address_host(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# managed by emby-proxy-cli
+# backend: set $website https://emby.example.com;
+server {
+    location ~ ^/stream/(https?)/([^/]+) {
+        set $website $1://$2;
+        proxy_pass $website;
+    }
+
+    location / {
+        set $website https://emby.example.com;  
+        proxy_pass $website;
+        proxy_ssl_name emby.example.com;
+        # proxy_ssl_name emby.example.com;
+    }
+
+    location @handle_redirect {
+        set $saved_redirect_location '$upstream_http_location';
+        proxy_pass $saved_redirect_location;
+    }
+}
+";
+
+    #[test]
+    fn replace_backend_url_round_trips() {
+        let (content, previous) =
+            replace_backend_url(CONFIG, "https://media.example.org:8443/").unwrap();
+        assert_eq!(previous, "https://emby.example.com");
+        assert_eq!(
+            content,
+            CONFIG
+                .replace(
+                    "        set $website https://emby.example.com;  \n",
+                    "        set $website https://media.example.org:8443;\n",
+                )
+                .replace(
+                    "        proxy_ssl_name emby.example.com;\n        #",
+                    "        proxy_ssl_name media.example.org;\n        #",
+                )
+        );
+
+        let (restored, previous) =
+            replace_backend_url(&content, "https://emby.example.com").unwrap();
+        assert_eq!(previous, "https://media.example.org:8443");
+        assert_eq!(restored, CONFIG.replace(";  \n", ";\n"));
+    }
+
+    #[test]
+    fn replace_backend_url_keeps_a_missing_trailing_newline() {
+        let config = CONFIG.trim_end();
+        let (content, _) = replace_backend_url(config, "https://media.example.org").unwrap();
+        assert!(content.ends_with("\n}"));
+        assert_eq!(content.lines().count(), config.lines().count());
+    }
+
+    #[test]
+    fn replace_backend_url_rejects_scheme_changes() {
+        let err = replace_backend_url(CONFIG, "http://media.example.org").unwrap_err();
+        assert!(matches!(err, AppError::Validation(message) if message.contains("TLS settings")));
+    }
+}
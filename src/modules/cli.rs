@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -11,11 +11,106 @@ pub struct Cli {
     )]
     pub env_overrides: Vec<(String, String)>,
 
+    #[arg(
+        long = "env-file",
+        global = true,
+        value_name = "PATH",
+        help = "Load KEY=VALUE lines from an env file (repeatable, later files win)"
+    )]
+    pub env_files: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Let variables already set in the process environment override --env-file values"
+    )]
+    pub env_file_override: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Load KEY = value settings and [resolvers] presets from a TOML file (`-` reads stdin)"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Write a JSON summary of the run to this path"
+    )]
+    pub summary_json: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Log extra detail, including per-step timing"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Write a JSONL trace of parameters, commands and file operations"
+    )]
+    pub trace: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Reject --env and config file keys that no command recognizes"
+    )]
+    pub strict_env: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Change to this directory before running so relative paths resolve against it"
+    )]
+    pub working_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 60,
+        value_name = "SECONDS",
+        help = "Kill nginx -t and nginx -s reload if they run longer than this"
+    )]
+    pub reload_timeout: u64,
+
+    #[arg(
+        long,
+        global = true,
+        help = "With --dry-run, exit 9 if any change is planned (drift detection)"
+    )]
+    pub detect_drift: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Refuse to overwrite existing config files that lack the emby-proxy-cli header"
+    )]
+    pub no_clobber_system: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Exit 10 at the end of the run if any warning was printed"
+    )]
+    pub fail_on_warn: bool,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "fail_on_warn",
+        help = "With --fail-on-warn, stop at the first warning instead of the end of the run"
+    )]
+    pub fail_fast: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct IssueCertArgs {
     pub cf_token: Option<String>,
     pub cf_account_id: Option<String>,
@@ -31,11 +126,109 @@ pub struct IssueCertArgs {
     pub cert_output_path: Option<PathBuf>,
     pub key_output_path: Option<PathBuf>,
     pub nginx_bin: Option<PathBuf>,
+    pub offline: bool,
+    pub wait_for_propagation: bool,
+    pub propagation_timeout: u64,
+    pub dns_sleep: Option<u64>,
+    pub cron_deploy: bool,
+    pub dual_cert: bool,
+    pub acme_config_home: Option<PathBuf>,
+    pub acme_cert_home: Option<PathBuf>,
+    pub propagation_check: Option<String>,
+    pub wildcard_only: bool,
+    pub skip_cache_removal: bool,
+    pub cert_owner: Option<String>,
+    pub cert_owner_dir: bool,
+    pub renew_threshold_days: Option<u64>,
+    pub force_reissue: bool,
+    pub challenge_alias: Option<String>,
+    pub domain_alias: Option<String>,
+    pub verify_cf_token: bool,
+    pub cert_base_dir: Option<PathBuf>,
+    pub confirm_reload: bool,
+    pub yes: bool,
+    pub purge_on_fail: bool,
+    pub no_cron: bool,
+    pub validate_all: bool,
+    pub acme_args: Vec<String>,
+    pub preferred_chain: Option<String>,
+    pub cf_purge_cache: bool,
+    pub must_staple: bool,
+    pub cert_name_scheme: Option<String>,
+    pub cert_filename: Option<String>,
+    pub key_filename: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct RollbackArgs {
+    pub domain: Option<String>,
+    pub cert_dir: Option<PathBuf>,
+    pub cert_dir_name: Option<String>,
+    pub cert_output_path: Option<PathBuf>,
+    pub key_output_path: Option<PathBuf>,
+    pub nginx_bin: Option<PathBuf>,
+    pub to_backup: Option<u64>,
+    pub list: bool,
+    pub cert_base_dir: Option<PathBuf>,
+    pub confirm_reload: bool,
+    pub yes: bool,
+    pub cert_name_scheme: Option<String>,
+    pub cert_filename: Option<String>,
+    pub key_filename: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CheckRenewalArgs {
+    pub domain: Vec<String>,
+    pub cert_dir: Vec<PathBuf>,
+    pub cert_dir_name: Vec<String>,
+    pub cert_base_dir: Option<PathBuf>,
+    pub threshold_days: u64,
+    pub cert_name_scheme: Option<String>,
+    pub cert_filename: Option<String>,
+    pub key_filename: Option<String>,
+    pub dual_cert: bool,
+}
+
+#[derive(Debug)]
+pub struct WriteDefaultArgs {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub cert_dir_name: Option<String>,
+    pub domain: Option<String>,
+    pub output_path: Option<PathBuf>,
+    pub template: Option<PathBuf>,
+    pub watch: bool,
+    pub watch_interval: u64,
+    pub test_config: bool,
+    pub dual_cert: bool,
+    pub bind: Option<String>,
+    pub default_action: Vec<String>,
+    pub hsts: bool,
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
+    pub post_write_cmd: Option<String>,
+    pub ipv6: Option<bool>,
+    pub ipv6only: Option<String>,
+    pub cert_base_dir: Option<PathBuf>,
+    pub set_values: Vec<(String, String)>,
+    pub server_tokens: String,
+    pub output_owner: Option<String>,
+    pub inline_certs: bool,
+    pub cert_name_scheme: Option<String>,
+    pub cert_filename: Option<String>,
+    pub key_filename: Option<String>,
+    pub min_tls_version: Option<String>,
+    pub backup: bool,
+    pub keep_backups: Option<u64>,
+    pub stub_status: Option<String>,
+    pub stub_status_allow: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct WriteProxyArgs {
-    pub proxy_domain: Option<String>,
+    pub proxy_domain: Vec<String>,
     pub backend_url: Option<String>,
     pub cert_path: Option<PathBuf>,
     pub key_path: Option<PathBuf>,
@@ -43,17 +236,67 @@ pub struct WriteProxyArgs {
     pub cert_dir: Option<PathBuf>,
     pub output_dir: Option<PathBuf>,
     pub resolvers: Vec<String>,
+    pub host_header: Option<String>,
+    pub no_backend_sni: bool,
+    pub backend_ssl_verify: Option<String>,
+    pub routes_file: Option<PathBuf>,
+    pub emby_preset: bool,
+    pub template: Option<PathBuf>,
+    pub watch: bool,
+    pub watch_interval: u64,
+    pub test_config: bool,
+    pub dual_cert: bool,
+    pub hsts: bool,
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
+    pub backends_file: Option<PathBuf>,
+    pub post_write_cmd: Option<String>,
+    pub ipv6: Option<bool>,
+    pub ipv6only: Option<String>,
+    pub server: String,
+    pub cert_base_dir: Option<PathBuf>,
+    pub active_backend: Option<String>,
+    pub set_values: Vec<(String, String)>,
+    pub geo_allow: Vec<String>,
+    pub geoip_db: Option<PathBuf>,
+    pub strip_prefix: Option<String>,
+    pub server_tokens: String,
+    pub output_owner: Option<String>,
+    pub derive_domain_from_proxy: Option<bool>,
+    pub inline_certs: bool,
+    pub passthrough: bool,
+    pub stub_status: Option<String>,
+    pub stub_status_allow: Vec<String>,
+    pub rewrites: Vec<String>,
+    pub cert_name_scheme: Option<String>,
+    pub cert_filename: Option<String>,
+    pub key_filename: Option<String>,
+    pub proxy_redirects: Vec<String>,
+    pub min_tls_version: Option<String>,
+    pub forward_headers: Vec<(String, String)>,
+    pub no_forward_headers: Vec<String>,
+    pub backup: bool,
+    pub keep_backups: Option<u64>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Setup {
-        #[arg(long, default_value_t = true)]
-        install_zsh: bool,
-        #[arg(long, default_value_t = true)]
-        install_cron: bool,
-        #[arg(long, default_value_t = true)]
-        install_nginx: bool,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        install_zsh: Option<bool>,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        install_cron: Option<bool>,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        install_nginx: Option<bool>,
+        #[arg(long)]
+        mirror_nginx: Option<String>,
+        #[arg(long)]
+        mirror_apt: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        download_retries: u32,
+        #[arg(long)]
+        nginx_key_sha256: Option<String>,
         #[arg(long)]
         dry_run: bool,
     },
@@ -89,6 +332,68 @@ pub enum Commands {
         #[arg(long, default_value_t = true)]
         reload_nginx: bool,
         #[arg(long)]
+        offline: bool,
+        #[arg(long)]
+        wait_for_propagation: bool,
+        #[arg(long, default_value_t = 120)]
+        propagation_timeout: u64,
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        dns_sleep: Option<u64>,
+        #[arg(long)]
+        cron_deploy: bool,
+        #[arg(long)]
+        dual_cert: bool,
+        #[arg(long)]
+        acme_config_home: Option<PathBuf>,
+        #[arg(long)]
+        acme_cert_home: Option<PathBuf>,
+        #[arg(long, value_parser = ["resolvers", "cloudflare"])]
+        propagation_check: Option<String>,
+        #[arg(long)]
+        wildcard_only: bool,
+        #[arg(long)]
+        skip_cache_removal: bool,
+        #[arg(long, value_name = "USER:GROUP")]
+        cert_owner: Option<String>,
+        #[arg(long)]
+        cert_owner_dir: bool,
+        #[arg(long)]
+        renew_threshold_days: Option<u64>,
+        #[arg(long)]
+        force_reissue: bool,
+        #[arg(long)]
+        challenge_alias: Option<String>,
+        #[arg(long)]
+        domain_alias: Option<String>,
+        #[arg(long)]
+        verify_cf_token: bool,
+        #[arg(long)]
+        cert_base_dir: Option<PathBuf>,
+        #[arg(long)]
+        confirm_reload: bool,
+        #[arg(long)]
+        yes: bool,
+        #[arg(long)]
+        purge_on_fail: bool,
+        #[arg(long, conflicts_with = "cron_deploy")]
+        no_cron: bool,
+        #[arg(long)]
+        validate_all: bool,
+        #[arg(long = "acme-arg", value_name = "ARG", allow_hyphen_values = true)]
+        acme_args: Vec<String>,
+        #[arg(long)]
+        preferred_chain: Option<String>,
+        #[arg(long, conflicts_with = "offline")]
+        cf_purge_cache: bool,
+        #[arg(long)]
+        must_staple: bool,
+        #[arg(long, value_parser = ["domain", "certbot", "custom"])]
+        cert_name_scheme: Option<String>,
+        #[arg(long)]
+        cert_filename: Option<String>,
+        #[arg(long)]
+        key_filename: Option<String>,
+        #[arg(long)]
         dry_run: bool,
     },
     WriteNginxDefault {
@@ -103,11 +408,65 @@ pub enum Commands {
         #[arg(long)]
         output_path: Option<PathBuf>,
         #[arg(long)]
+        template: Option<PathBuf>,
+        #[arg(long)]
+        watch: bool,
+        #[arg(long, default_value_t = 2)]
+        watch_interval: u64,
+        #[arg(long)]
+        test_config: bool,
+        #[arg(long)]
+        dual_cert: bool,
+        #[arg(long)]
+        bind: Option<String>,
+        #[arg(long, num_args = 1..=2, value_names = ["ACTION", "VALUE"])]
+        default_action: Vec<String>,
+        #[arg(long)]
+        hsts: bool,
+        #[arg(long, default_value_t = 31536000, requires = "hsts")]
+        hsts_max_age: u64,
+        #[arg(long, requires = "hsts")]
+        hsts_include_subdomains: bool,
+        #[arg(long, requires = "hsts")]
+        hsts_preload: bool,
+        #[arg(long)]
+        post_write_cmd: Option<String>,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        ipv6: Option<bool>,
+        #[arg(long, value_parser = ["on", "off"])]
+        ipv6only: Option<String>,
+        #[arg(long)]
+        cert_base_dir: Option<PathBuf>,
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = crate::modules::env::parse_key_val)]
+        set_values: Vec<(String, String)>,
+        #[arg(long, value_parser = ["on", "off"], default_value = "off")]
+        server_tokens: String,
+        #[arg(long)]
+        output_owner: Option<String>,
+        #[arg(long)]
+        inline_certs: bool,
+        #[arg(long, value_parser = ["domain", "certbot", "custom"])]
+        cert_name_scheme: Option<String>,
+        #[arg(long)]
+        cert_filename: Option<String>,
+        #[arg(long)]
+        key_filename: Option<String>,
+        #[arg(long, value_parser = ["1.2", "1.3"])]
+        min_tls_version: Option<String>,
+        #[arg(long)]
+        backup: bool,
+        #[arg(long, requires = "backup", value_parser = clap::value_parser!(u64).range(1..))]
+        keep_backups: Option<u64>,
+        #[arg(long, value_name = "PATH")]
+        stub_status: Option<String>,
+        #[arg(long, value_name = "CIDR", value_delimiter = ',')]
+        stub_status_allow: Vec<String>,
+        #[arg(long)]
         dry_run: bool,
     },
     WriteProxyConfig {
         #[arg(long)]
-        proxy_domain: Option<String>,
+        proxy_domain: Vec<String>,
         #[arg(long)]
         backend_url: Option<String>,
         #[arg(long)]
@@ -123,7 +482,304 @@ pub enum Commands {
         #[arg(long)]
         resolver: Vec<String>,
         #[arg(long)]
+        force: bool,
+        #[arg(long)]
+        host_header: Option<String>,
+        #[arg(long)]
+        no_backend_sni: bool,
+        #[arg(long, value_parser = ["on", "off"])]
+        backend_ssl_verify: Option<String>,
+        #[arg(long)]
+        routes_file: Option<PathBuf>,
+        #[arg(long)]
+        emby_preset: bool,
+        #[arg(long)]
+        template: Option<PathBuf>,
+        #[arg(long)]
+        watch: bool,
+        #[arg(long, default_value_t = 2)]
+        watch_interval: u64,
+        #[arg(long)]
+        test_config: bool,
+        #[arg(long)]
+        dual_cert: bool,
+        #[arg(long)]
+        hsts: bool,
+        #[arg(long, default_value_t = 31536000, requires = "hsts")]
+        hsts_max_age: u64,
+        #[arg(long, requires = "hsts")]
+        hsts_include_subdomains: bool,
+        #[arg(long, requires = "hsts")]
+        hsts_preload: bool,
+        #[arg(long)]
+        backends_file: Option<PathBuf>,
+        #[arg(long)]
+        post_write_cmd: Option<String>,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        ipv6: Option<bool>,
+        #[arg(long, value_parser = ["on", "off"])]
+        ipv6only: Option<String>,
+        #[arg(long, value_parser = ["nginx", "caddy", "apache"], default_value = "nginx")]
+        server: String,
+        #[arg(long)]
+        cert_base_dir: Option<PathBuf>,
+        #[arg(long, value_parser = ["blue", "green"], conflicts_with_all = ["backend_url", "backends_file"])]
+        active_backend: Option<String>,
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = crate::modules::env::parse_key_val)]
+        set_values: Vec<(String, String)>,
+        #[arg(long, value_name = "CC", value_delimiter = ',')]
+        geo_allow: Vec<String>,
+        #[arg(long)]
+        geoip_db: Option<PathBuf>,
+        #[arg(long)]
+        strip_prefix: Option<String>,
+        #[arg(long, value_parser = ["on", "off"], default_value = "off")]
+        server_tokens: String,
+        #[arg(long)]
+        output_owner: Option<String>,
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        derive_domain_from_proxy: Option<bool>,
+        #[arg(long)]
+        inline_certs: bool,
+        #[arg(long, conflicts_with_all = ["cert_path", "key_path", "dual_cert", "inline_certs", "hsts", "host_header", "no_backend_sni", "backend_ssl_verify", "routes_file", "emby_preset", "geo_allow", "geoip_db", "strip_prefix", "backends_file", "stub_status", "rewrites", "proxy_redirects", "min_tls_version", "forward_headers", "no_forward_headers", "cert_name_scheme", "cert_filename", "key_filename"])]
+        passthrough: bool,
+        #[arg(long, value_name = "PATH")]
+        stub_status: Option<String>,
+        #[arg(long, value_name = "CIDR", value_delimiter = ',')]
+        stub_status_allow: Vec<String>,
+        #[arg(long = "rewrite", value_name = "PATTERN REPLACEMENT")]
+        rewrites: Vec<String>,
+        #[arg(long, value_parser = ["domain", "certbot", "custom"])]
+        cert_name_scheme: Option<String>,
+        #[arg(long)]
+        cert_filename: Option<String>,
+        #[arg(long)]
+        key_filename: Option<String>,
+        #[arg(long = "proxy-redirect", value_name = "FROM TO|off|default")]
+        proxy_redirects: Vec<String>,
+        #[arg(long, value_parser = ["1.2", "1.3"])]
+        min_tls_version: Option<String>,
+        #[arg(long = "forward-header", value_name = "NAME=VALUE", value_parser = crate::modules::env::parse_key_val)]
+        forward_headers: Vec<(String, String)>,
+        #[arg(long = "no-forward-header", value_name = "NAME")]
+        no_forward_headers: Vec<String>,
+        #[arg(long)]
+        backup: bool,
+        #[arg(long, requires = "backup", value_parser = clap::value_parser!(u64).range(1..))]
+        keep_backups: Option<u64>,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ReloadUpstream {
+        #[arg(long)]
+        proxy_domain: Option<String>,
+        #[arg(long)]
+        backend_url: Option<String>,
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        #[arg(long)]
+        nginx_bin: Option<PathBuf>,
+        #[arg(long)]
         dry_run: bool,
     },
     PrintParams,
+    ListPresets {
+        #[arg(long, value_parser = ["text", "json"], default_value = "text")]
+        format: String,
+    },
+    ShowTemplate {
+        #[arg(value_parser = ["proxy", "default", "caddy", "apache", "stream"])]
+        kind: String,
+    },
+    Check {
+        #[arg(long)]
+        nginx_bin: Option<PathBuf>,
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        #[arg(long)]
+        lint: bool,
+        #[arg(long, requires = "lint", value_name = "DURATION", value_parser = crate::modules::env::parse_duration)]
+        since: Option<Duration>,
+    },
+    TestBackend {
+        #[arg(long)]
+        backend_url: Option<String>,
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    ProbeDns {
+        #[arg(long)]
+        resolver: Vec<String>,
+        #[arg(long, default_value = crate::modules::dns::DEFAULT_PROBE_NAME)]
+        name: String,
+        #[arg(long, default_value_t = 2)]
+        timeout: u64,
+    },
+    VerifyCertDomain {
+        #[arg(long)]
+        cert_path: Option<PathBuf>,
+        #[arg(long)]
+        domain: Option<String>,
+    },
+    CombinePem {
+        #[arg(long)]
+        cert_path: Option<PathBuf>,
+        #[arg(long)]
+        key_path: Option<PathBuf>,
+        #[arg(long)]
+        output: PathBuf,
+        #[arg(long, value_parser = ["cert-first", "key-first"], default_value = "cert-first")]
+        order: String,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    Rollback {
+        #[arg(long)]
+        domain: Option<String>,
+        #[arg(long)]
+        cert_dir: Option<PathBuf>,
+        #[arg(long)]
+        cert_dir_name: Option<String>,
+        #[arg(long)]
+        cert_output_path: Option<PathBuf>,
+        #[arg(long)]
+        key_output_path: Option<PathBuf>,
+        #[arg(long)]
+        nginx_bin: Option<PathBuf>,
+        #[arg(long)]
+        to_backup: Option<u64>,
+        #[arg(long)]
+        list: bool,
+        #[arg(long)]
+        cert_base_dir: Option<PathBuf>,
+        #[arg(long)]
+        confirm_reload: bool,
+        #[arg(long)]
+        yes: bool,
+        #[arg(long, value_parser = ["domain", "certbot", "custom"])]
+        cert_name_scheme: Option<String>,
+        #[arg(long)]
+        cert_filename: Option<String>,
+        #[arg(long)]
+        key_filename: Option<String>,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    RestoreConfig {
+        #[arg(long)]
+        file: PathBuf,
+        #[arg(long)]
+        to_backup: Option<u64>,
+        #[arg(long)]
+        list: bool,
+        #[arg(long)]
+        nginx_bin: Option<PathBuf>,
+        #[arg(long)]
+        no_reload: bool,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    CheckRenewal {
+        #[arg(long)]
+        domain: Vec<String>,
+        #[arg(long)]
+        cert_dir: Vec<PathBuf>,
+        #[arg(long)]
+        cert_dir_name: Vec<String>,
+        #[arg(long)]
+        cert_base_dir: Option<PathBuf>,
+        #[arg(long, default_value_t = 14)]
+        threshold_days: u64,
+        #[arg(long, value_parser = ["domain", "certbot", "custom"])]
+        cert_name_scheme: Option<String>,
+        #[arg(long)]
+        cert_filename: Option<String>,
+        #[arg(long)]
+        key_filename: Option<String>,
+        #[arg(long)]
+        dual_cert: bool,
+    },
+    Status {
+        #[arg(long)]
+        acme_home: Option<PathBuf>,
+        #[arg(long, default_value_t = 30)]
+        warn_days: u64,
+    },
+    PruneCerts {
+        #[arg(long)]
+        acme_home: Option<PathBuf>,
+        #[arg(long)]
+        keep: Vec<String>,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ExportAccount {
+        #[arg(long)]
+        acme_home: Option<PathBuf>,
+        #[arg(long)]
+        acme_config_home: Option<PathBuf>,
+        #[arg(long)]
+        output: PathBuf,
+        #[arg(long)]
+        force: bool,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ImportAccount {
+        #[arg(long)]
+        acme_home: Option<PathBuf>,
+        #[arg(long)]
+        acme_config_home: Option<PathBuf>,
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        force: bool,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    Migrate {
+        #[arg(long, default_value = ".env")]
+        file: PathBuf,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    // Called by the acme.sh DNS hook that issue-cert --wait-for-propagation installs.
+    #[command(hide = true)]
+    WaitPropagation {
+        #[arg(long)]
+        record: String,
+        #[arg(long)]
+        value: Option<String>,
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+        #[arg(long, value_parser = ["resolvers", "cloudflare"], default_value = "resolvers")]
+        check: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Commands {
+        Cli::try_parse_from(std::iter::once("emby-proxy-cli").chain(args.iter().copied()))
+            .unwrap()
+            .command
+    }
+
+    #[test]
+    fn server_tokens_default_to_off() {
+        match parse(&["write-proxy-config"]) {
+            Commands::WriteProxyConfig { server_tokens, .. } => assert_eq!(server_tokens, "off"),
+            other => panic!("unexpected command {:?}", other),
+        }
+        match parse(&["write-nginx-default"]) {
+            Commands::WriteNginxDefault { server_tokens, .. } => assert_eq!(server_tokens, "off"),
+            other => panic!("unexpected command {:?}", other),
+        }
+        match parse(&["write-proxy-config", "--server-tokens", "on"]) {
+            Commands::WriteProxyConfig { server_tokens, .. } => assert_eq!(server_tokens, "on"),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
 }
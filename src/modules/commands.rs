@@ -1,62 +1,201 @@
 use crate::modules::{
-    cli::{IssueCertArgs, WriteProxyArgs},
+    backends::{
+        env_backends, load_backends, passthrough_address, render_balancer, render_sni_map,
+        render_upstream, replace_backend_url, upstream_name,
+    },
+    cli::{CheckRenewalArgs, IssueCertArgs, RollbackArgs, WriteDefaultArgs, WriteProxyArgs},
+    config::PROXY_DOMAIN_PREFIX,
+    dns::{probe_all, resolver_addresses},
     env::{
-        resolve_cert_dir, resolve_optional_path, resolve_optional_value, resolve_path,
-        resolve_resolvers, resolve_value,
+        apex_domain, resolve_cert_dir, resolve_from_envs, resolve_optional_path,
+        resolve_optional_value, resolve_path, resolve_resolvers, resolve_value, resolver_presets,
+        select_many_with_timeout,
+    },
+    error::AppError,
+    log::{debug, info, print_table, step, success, warn},
+    render::{TemplateContext, render},
+    routes::{Route, emby_preset_routes, render_routes, resolve_routes},
+    runner::CommandRunner,
+    summary::{record_change, record_param},
+    templates::{
+        APACHE_PROXY_TEMPLATE, CADDY_PROXY_TEMPLATE, NGINX_DEFAULT_TEMPLATE, NGINX_PROXY_TEMPLATE,
+        NGINX_STREAM_TEMPLATE, check_custom_values, leftover_placeholders,
     },
-    log::{info, step, success},
-    templates::{NGINX_DEFAULT_TEMPLATE, NGINX_PROXY_TEMPLATE},
 };
 use std::{
     collections::HashMap,
-    env, fs,
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    net::IpAddr,
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
     path::{Path, PathBuf},
     process::{Command, Stdio},
-    sync::mpsc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const DEFAULT_RESOLVER: &str = "1.1.1.1 1.0.0.1 [2606:4700:4700::1111] [2606:4700:4700::1064]";
+const DEFAULT_STUB_STATUS_ALLOW: [&str; 2] = ["127.0.0.1", "::1"];
+const ENV_SCHEMA_VERSION: u32 = 1;
+const RENAMED_ENV_KEYS: [(&str, &str); 3] = [
+    ("CF_Token", "CF_TOKEN"),
+    ("CF_Account_ID", "CF_ACCOUNT_ID"),
+    ("CF_Zone_ID", "CF_ZONE_ID"),
+];
+const DEFAULT_HOST_HEADER: &str = "$proxy_host";
+const DEFAULT_FORWARD_HEADERS: [(&str, &str); 9] = [
+    ("Upgrade", "$http_upgrade"),
+    ("Connection", "$connection_upgrade"),
+    ("X-Real-IP", "$remote_addr"),
+    (
+        "Forwarded",
+        "\"for=$proxy_add_x_forwarded_for; proto=$scheme; by=$server_addr; host=$host\"",
+    ),
+    ("X-Forwarded-For", "$proxy_add_x_forwarded_for"),
+    ("X-Forwarded-Proto", "$scheme"),
+    ("X-Forwarded-Host", "$host"),
+    ("X-Forwarded-Port", "$server_port"),
+    ("Early-Data", "$ssl_early_data"),
+];
+const ACME_SKIPPED_EXIT_CODE: i32 = 2;
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const TLS_DETAIL_LABELS: [(&str, &str); 4] = [
+    ("SSL connection using", "TLS"),
+    ("subject:", "Certificate subject"),
+    ("issuer:", "Certificate issuer"),
+    ("expire date:", "Certificate expires"),
+];
+const DEFAULT_CERT_ROOT: &str = "/etc/ca-certificates";
+const ALPINE_CERT_ROOT: &str = "/etc/nginx/certs";
+const ECDSA_SSL_CIPHERS: &str = "TLS13_AES_128_GCM_SHA256:TLS13_AES_256_GCM_SHA384:TLS13_CHACHA20_POLY1305_SHA256:ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-ECDSA-CHACHA20-POLY1305";
+const DUAL_SSL_CIPHERS: &str = "TLS13_AES_128_GCM_SHA256:TLS13_AES_256_GCM_SHA384:TLS13_CHACHA20_POLY1305_SHA256:ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-AES128-GCM-SHA256:ECDHE-RSA-AES256-GCM-SHA384:ECDHE-RSA-CHACHA20-POLY1305";
+const HSTS_PRELOAD_MIN_AGE: u64 = 31536000;
+const SYSTEM_CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+const PROPAGATION_RESOLVERS: [&str; 2] = ["1.1.1.1", "8.8.8.8"];
+const CLOUDFLARE_API_LABEL: &str = "Cloudflare API";
+const PROPAGATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const PROPAGATION_HOOK: &str = "dns_emby_gate";
+const CONTAINER_MARKERS: [&str; 2] = ["/.dockerenv", "/run/.containerenv"];
+const CONTAINER_CGROUP_HINTS: [&str; 4] = ["docker", "kubepods", "containerd", "lxc"];
+// Primary keys nginx.org publishes in nginx_signing.key; any other key in the
+// file means it was tampered with.
+const NGINX_SIGNING_KEY_FINGERPRINTS: [&str; 3] = [
+    "573BFD6B3D8FBC641079A6ABABF5BD827BD9BF62",
+    "8540A6F18833A80E9C1653A42FD21310B49F6B46",
+    "9E9BE90EACBCDE69FE9B204CBCDCD8A38D88A2B3",
+];
+// Digest of nginx.org/keys/nginx_signing.rsa.pub, as pinned by nginx's own
+// Alpine images (nginxinc/docker-nginx).
+const NGINX_SIGNING_RSA_SHA512: &str = "e09fa32f0a0eab2b879ccbbc4d0e4fb9751486eedda75e35fac65802cc9faa266425edf83e261137a2f4d16281ce2c1a5f4502930fe75154723da014214f0655";
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup_system(
-    install_zsh: bool,
-    install_cron: bool,
-    install_nginx: bool,
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    install_zsh: Option<bool>,
+    install_cron: Option<bool>,
+    install_nginx: Option<bool>,
+    mirror_nginx: Option<String>,
+    mirror_apt: Option<String>,
+    download_retries: u32,
+    nginx_key_sha256: Option<String>,
     dry_run: bool,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     step("System setup");
     ensure_linux()?;
-    ensure_root()?;
+    ensure_root(runner)?;
+    let container = container_hint();
+    if let Some(hint) = &container {
+        info(&format!(
+            "Running in a container ({}), systemd service steps are skipped",
+            hint
+        ));
+    }
+    let mirrors = NginxMirrors::resolve(
+        mirror_nginx.or_else(|| resolve_from_envs(env_overrides, &["MIRROR_NGINX"])),
+        mirror_apt.or_else(|| resolve_from_envs(env_overrides, &["MIRROR_APT"])),
+    )?;
+    let nginx_key_sha256 = nginx_key_sha256
+        .or_else(|| resolve_from_envs(env_overrides, &["NGINX_SIGNING_RSA_SHA256"]))
+        .map(|digest| parse_sha256("--nginx-key-sha256", &digest))
+        .transpose()?;
     let start = Instant::now();
-    let mut changes: Vec<String> = Vec::new();
+    let mut changes: Vec<(String, String)> = Vec::new();
+
+    let explicit = install_zsh.is_some() || install_cron.is_some() || install_nginx.is_some();
+    let (install_zsh, install_cron, install_nginx) = if explicit || dry_run {
+        if !explicit {
+            info("[dry-run] Would prompt: Select components to install");
+        }
+        (
+            install_zsh.unwrap_or(true),
+            install_cron.unwrap_or(true),
+            install_nginx.unwrap_or(true),
+        )
+    } else {
+        let selected = select_many_with_timeout(
+            "Select components to install",
+            &SETUP_COMPONENTS,
+            DEFAULT_CONFIRM_TIMEOUT,
+        )?;
+        (selected[0], selected[1], selected[2])
+    };
 
     if install_zsh {
         if command_exists("zsh") {
             info("zsh is already installed");
-        } else if confirm_with_timeout("Install zsh?", DEFAULT_CONFIRM_TIMEOUT, dry_run)? {
+            changes.push(("zsh".to_string(), "skipped".to_string()));
+        } else if !explicit
+            || confirm_with_timeout("Install zsh?", DEFAULT_CONFIRM_TIMEOUT, dry_run)?
+        {
             install_if_missing("zsh", &mut changes, dry_run, |dry| {
-                run_cmd("apt-get", &["update", "-qq"], dry)?;
-                run_cmd("apt-get", &["install", "-y", "zsh"], dry)
+                run_cmd(runner, "apt-get", &["update", "-qq"], dry)?;
+                run_cmd(runner, "apt-get", &["install", "-y", "zsh"], dry)
             })?;
         } else {
             info("zsh install skipped");
+            changes.push(("zsh".to_string(), "skipped".to_string()));
         }
+    } else {
+        changes.push(("zsh".to_string(), "skipped".to_string()));
     }
 
     if install_cron {
         install_if_missing("crontab", &mut changes, dry_run, |dry| {
-            run_cmd("apt-get", &["update", "-qq"], dry)?;
-            run_cmd("apt-get", &["install", "-y", "cron"], dry)?;
-            run_cmd("systemctl", &["enable", "cron"], dry)?;
-            run_cmd("systemctl", &["start", "cron"], dry)
+            run_cmd(runner, "apt-get", &["update", "-qq"], dry)?;
+            run_cmd(runner, "apt-get", &["install", "-y", "cron"], dry)?;
+            if container.is_some() {
+                warn(
+                    "Start cron from the container entrypoint (e.g. `cron`), nothing runs it here",
+                );
+                return Ok(());
+            }
+            run_cmd(runner, "systemctl", &["enable", "cron"], dry)?;
+            run_cmd(runner, "systemctl", &["start", "cron"], dry)
         })?;
+    } else {
+        changes.push(("crontab".to_string(), "skipped".to_string()));
     }
 
     if install_nginx {
         install_if_missing("nginx", &mut changes, dry_run, |dry| {
-            install_nginx_official(dry)
+            install_nginx_official(
+                runner,
+                &mirrors,
+                nginx_key_sha256.as_deref(),
+                download_retries,
+                dry,
+            )
         })?;
+    } else {
+        changes.push(("nginx".to_string(), "skipped".to_string()));
     }
 
     print_summary(&changes, start.elapsed());
@@ -64,29 +203,70 @@ pub fn setup_system(
 }
 
 pub fn issue_cert(
+    runner: &dyn CommandRunner,
     env_overrides: &HashMap<String, String>,
     args: IssueCertArgs,
     reload_nginx: bool,
     dry_run: bool,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     step("Issuing certificate");
-    ensure_root()?;
+    if args.validate_all {
+        validate_issue_cert_args(&args, env_overrides)?;
+    }
+    ensure_root(runner)?;
+    let preferred_chain = args
+        .preferred_chain
+        .clone()
+        .or_else(|| resolve_from_envs(env_overrides, &["PREFERRED_CHAIN"]));
+    if preferred_chain
+        .as_deref()
+        .is_some_and(|chain| chain.trim().is_empty())
+    {
+        return Err(AppError::Validation(
+            "--preferred-chain must not be empty".to_string(),
+        ));
+    }
     let cert_output_path =
         resolve_optional_path(args.cert_output_path, env_overrides, "CERT_OUTPUT_PATH");
     let key_output_path =
         resolve_optional_path(args.key_output_path, env_overrides, "KEY_OUTPUT_PATH");
     if cert_output_path.is_some() ^ key_output_path.is_some() {
-        return Err("Both CERT_OUTPUT_PATH and KEY_OUTPUT_PATH must be set together".to_string());
+        return Err(AppError::Validation(
+            "Both CERT_OUTPUT_PATH and KEY_OUTPUT_PATH must be set together".to_string(),
+        ));
     }
     let cert_input_path =
         resolve_optional_path(args.cert_input_path, env_overrides, "CERT_INPUT_PATH");
     let key_input_path =
         resolve_optional_path(args.key_input_path, env_overrides, "KEY_INPUT_PATH");
     if cert_input_path.is_some() ^ key_input_path.is_some() {
-        return Err("Both CERT_INPUT_PATH and KEY_INPUT_PATH must be set together".to_string());
+        return Err(AppError::Validation(
+            "Both CERT_INPUT_PATH and KEY_INPUT_PATH must be set together".to_string(),
+        ));
     }
     let using_input = cert_input_path.is_some();
+    if args.dual_cert && using_input {
+        return Err(AppError::Validation(
+            "--dual-cert cannot be combined with CERT_INPUT_PATH/KEY_INPUT_PATH".to_string(),
+        ));
+    }
+    let cert_owner = args
+        .cert_owner
+        .or_else(|| resolve_from_envs(env_overrides, &["CERT_OWNER"]))
+        .map(|spec| parse_owner("--cert-owner", &spec))
+        .transpose()?;
+    if args.offline && !using_input {
+        return Err(AppError::MissingInput(
+            "Offline mode requires CERT_INPUT_PATH and KEY_INPUT_PATH to be set".to_string(),
+        ));
+    }
 
+    let scheme = resolve_cert_name_scheme(
+        args.cert_name_scheme,
+        args.cert_filename,
+        args.key_filename,
+        env_overrides,
+    )?;
     let needs_domain_for_output = cert_output_path.is_none() || key_output_path.is_none();
     let domain = if using_input && !needs_domain_for_output {
         None
@@ -106,12 +286,19 @@ pub fn issue_cert(
             env_overrides,
             &["CERT_DIR_NAME"],
             "custom",
+            &cert_root(args.cert_base_dir, env_overrides),
         )?)
     } else {
         None
     };
-    let (cert_dst, key_dst) =
-        resolve_cert_paths(cert_output_path, key_output_path, cert_dir, domain.clone())?;
+    let (cert_dst, key_dst) = resolve_cert_paths(
+        cert_output_path,
+        key_output_path,
+        cert_dir,
+        domain.clone(),
+        &scheme,
+    )?;
+    let confirm_reload = args.confirm_reload && !args.yes;
     let nginx_bin = if reload_nginx {
         Some(resolve_path(
             args.nginx_bin,
@@ -124,12 +311,35 @@ pub fn issue_cert(
         None
     };
 
+    if let Some(threshold) = args.renew_threshold_days
+        && !args.force_reissue
+        && !certs_need_renewal(
+            runner,
+            &cert_variants(&cert_dst, &key_dst, args.dual_cert),
+            threshold,
+        )
+    {
+        success(&format!(
+            "Certificate has more than {} days left, skipping issuance",
+            threshold
+        ));
+        return Ok(());
+    }
+
     if using_input {
-        let cert_src = cert_input_path.ok_or("CERT_INPUT_PATH is required".to_string())?;
-        let key_src = key_input_path.ok_or("KEY_INPUT_PATH is required".to_string())?;
+        let cert_src = cert_input_path.ok_or(AppError::MissingInput(
+            "CERT_INPUT_PATH is required".to_string(),
+        ))?;
+        let key_src = key_input_path.ok_or(AppError::MissingInput(
+            "KEY_INPUT_PATH is required".to_string(),
+        ))?;
+        step("Copying certificate files");
         copy_cert_files(&cert_src, &key_src, &cert_dst, &key_dst, dry_run)?;
+        if let Some(owner) = &cert_owner {
+            chown_cert_files(owner, &cert_dst, &key_dst, args.cert_owner_dir, dry_run)?;
+        }
         if reload_nginx {
-            reload_nginx_binary(nginx_bin.as_ref(), dry_run)?;
+            reload_nginx_binary(runner, nginx_bin.as_ref(), confirm_reload, dry_run)?;
         }
         return Ok(());
     }
@@ -141,21 +351,22 @@ pub fn issue_cert(
         "Cloudflare token",
         true,
     )?;
-    let cf_account_id = resolve_value(
+    // Account-scoped API tokens need neither ID; acme.sh only sees the ones set.
+    let cf_account_id = resolve_optional_value(
         args.cf_account_id,
         env_overrides,
         "CF_ACCOUNT_ID",
-        "Cloudflare account ID",
+        "Cloudflare account ID (optional)",
         false,
     )?;
-    let cf_zone_id = resolve_value(
+    let cf_zone_id = resolve_optional_value(
         args.cf_zone_id,
         env_overrides,
         "CF_ZONE_ID",
-        "Cloudflare zone ID",
+        "Cloudflare zone ID (optional)",
         false,
     )?;
-    let domain = domain.ok_or("DOMAIN is required".to_string())?;
+    let domain = domain.ok_or(AppError::MissingInput("DOMAIN is required".to_string()))?;
     let wildcard_domain = resolve_optional_value(
         args.wildcard_domain,
         env_overrides,
@@ -163,7 +374,12 @@ pub fn issue_cert(
         "Wildcard domain (e.g., *.example.com)",
         false,
     )?
-    .unwrap_or_else(|| format!("*.{}", domain));
+    .unwrap_or_else(|| format!("*.{}", apex_domain(&domain)));
+    let acme_domain = if args.wildcard_only {
+        wildcard_domain.clone()
+    } else {
+        domain.clone()
+    };
 
     let acme_bin = resolve_path(
         args.acme_bin,
@@ -179,78 +395,282 @@ pub fn issue_cert(
         "/root/.acme.sh",
         "acme home directory",
     )?;
+    let acme_homes = AcmeHomes {
+        config_home: resolve_optional_path(
+            args.acme_config_home,
+            env_overrides,
+            "ACME_CONFIG_HOME",
+        ),
+        cert_home: resolve_optional_path(args.acme_cert_home, env_overrides, "ACME_CERT_HOME"),
+        home: acme_home,
+    };
 
-    let cache_dir = acme_home.join(format!("{}_ecc", domain));
-    if dry_run {
-        info(&format!(
-            "[dry-run] Would remove cache dir if exists: {}",
-            cache_dir.display()
-        ));
-    } else if cache_dir.exists() {
-        fs::remove_dir_all(&cache_dir)
-            .map_err(|e| format!("Failed to remove cache dir {}: {e}", cache_dir.display()))?;
+    let challenge_alias = args
+        .challenge_alias
+        .or_else(|| resolve_from_envs(env_overrides, &["CHALLENGE_ALIAS"]))
+        .map(|alias| validate_hostname("--challenge-alias", &alias))
+        .transpose()?;
+    let domain_alias = args
+        .domain_alias
+        .or_else(|| resolve_from_envs(env_overrides, &["DOMAIN_ALIAS"]))
+        .map(|alias| validate_hostname("--domain-alias", &alias))
+        .transpose()?;
+    let (alias_flag, challenge_record) = match (&challenge_alias, &domain_alias) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::Validation(
+                "--challenge-alias and --domain-alias cannot be combined".to_string(),
+            ));
+        }
+        (Some(alias), None) => (
+            Some(("--challenge-alias", alias)),
+            format!("_acme-challenge.{}", alias),
+        ),
+        (None, Some(alias)) => (Some(("--domain-alias", alias)), alias.clone()),
+        (None, None) => (None, format!("_acme-challenge.{}", domain)),
+    };
+
+    let dns_api = if args.wait_for_propagation {
+        install_propagation_hook(
+            &acme_bin,
+            args.propagation_check.as_deref().unwrap_or("resolvers"),
+            args.propagation_timeout,
+            dry_run,
+        )?;
+        PROPAGATION_HOOK
+    } else {
+        "dns_cf"
+    };
+
+    if args.verify_cf_token {
+        // The token has to edit the zone the TXT record lands in, which is the
+        // alias zone when the challenge is delegated.
+        verify_cf_token(
+            runner,
+            &cf_token,
+            cf_zone_id.as_deref().unwrap_or_default(),
+            &challenge_zone(&challenge_record),
+            dry_run,
+        )?;
     }
 
-    let mut acme_cmd = Command::new(&acme_bin);
-    acme_cmd
-        .env("CF_Token", cf_token)
-        .env("CF_Account_ID", cf_account_id)
-        .env("CF_Zone_ID", cf_zone_id)
-        .arg("--issue")
-        .arg("--force")
-        .arg("-d")
-        .arg(&domain)
-        .arg("-d")
-        .arg(&wildcard_domain)
-        .arg("--dns")
-        .arg("dns_cf")
-        .arg("--keylength")
-        .arg("ec-256")
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    if args.must_staple {
+        info(
+            "--must-staple: clients will reject this cert unless nginx staples OCSP responses; \
+             add `ssl_stapling on; ssl_stapling_verify on;` to the server block before deploying",
+        );
+    }
+    let variants = cert_variants(&cert_dst, &key_dst, args.dual_cert);
+    for variant in &variants {
+        step(&format!("Requesting {} certificate", variant.keylength));
+        let cache_dir = acme_homes
+            .cert_root()
+            .join(format!("{}{}", acme_domain, variant.cache_suffix));
+        if args.skip_cache_removal {
+            info(&format!("Keeping acme cache dir: {}", cache_dir.display()));
+        } else if dry_run {
+            info(&format!(
+                "[dry-run] Would remove cache dir if exists: {}",
+                cache_dir.display()
+            ));
+        } else if cache_dir.exists() {
+            fs::remove_dir_all(&cache_dir).map_err(|e| {
+                AppError::Io(format!(
+                    "Failed to remove cache dir {}: {e}",
+                    cache_dir.display()
+                ))
+            })?;
+        }
 
-    if dry_run {
-        info("[dry-run] Would run acme.sh to issue certificate");
-    } else {
-        let status = acme_cmd
-            .status()
-            .map_err(|e| format!("Failed to run acme.sh: {e}"))?;
-        if !status.success() {
-            return Err("Certificate issuance failed".to_string());
+        let mut acme_cmd = Command::new(&acme_bin);
+        acme_cmd.env("CF_Token", &cf_token).arg("--issue");
+        if let Some(account_id) = &cf_account_id {
+            acme_cmd.env("CF_Account_ID", account_id);
+        }
+        if let Some(zone_id) = &cf_zone_id {
+            acme_cmd.env("CF_Zone_ID", zone_id);
+        }
+        if !args.skip_cache_removal {
+            acme_cmd.arg("--force");
+        }
+        let mut issue_domains = vec![&acme_domain];
+        if !args.wildcard_only {
+            issue_domains.push(&wildcard_domain);
+        }
+        for issue_domain in issue_domains {
+            acme_cmd.arg("-d").arg(issue_domain);
+            if let Some((flag, alias)) = alias_flag {
+                acme_cmd.arg(flag).arg(alias);
+            }
+        }
+        acme_cmd
+            .arg("--dns")
+            .arg(dns_api)
+            .arg("--keylength")
+            .arg(variant.keylength)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        if let Some(seconds) = args.dns_sleep {
+            acme_cmd.arg("--dnssleep").arg(seconds.to_string());
+        }
+        if let Some(chain) = &preferred_chain {
+            acme_cmd.arg("--preferred-chain").arg(chain.trim());
+        }
+        if args.must_staple {
+            acme_cmd.arg("--ocsp-must-staple");
+        }
+        acme_homes.apply(&mut acme_cmd);
+        acme_cmd.args(&args.acme_args);
+        let command_line = std::iter::once(acme_cmd.get_program())
+            .chain(acme_cmd.get_args())
+            .map(|arg| shell_quote(&arg.to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let issued = (|| -> Result<(), AppError> {
+            if dry_run {
+                info(&format!(
+                    "[dry-run] Would run acme.sh to issue {} certificate: {}",
+                    variant.keylength, command_line
+                ));
+                if args.wait_for_propagation {
+                    info(&format!(
+                        "[dry-run] acme.sh would wait up to {}s for {} to propagate before validation",
+                        args.propagation_timeout, challenge_record
+                    ));
+                }
+            } else {
+                debug(&format!("Running {}", command_line));
+                let status = runner.status(&mut acme_cmd).map_err(|e| {
+                    AppError::ExternalCommand(format!("Failed to run acme.sh: {e}"))
+                })?;
+                if args.skip_cache_removal && status.code() == Some(ACME_SKIPPED_EXIT_CODE) {
+                    info(&format!(
+                        "acme.sh reused the cached {} certificate",
+                        variant.keylength
+                    ));
+                } else if !status.success() {
+                    return Err(AppError::ExternalCommand(
+                        "Certificate issuance failed".to_string(),
+                    ));
+                }
+                success(&format!(
+                    "{} certificate issuance completed",
+                    variant.keylength
+                ));
+            }
+
+            step(&format!("Installing {} certificate", variant.keylength));
+            install_acme_cert(
+                runner,
+                &acme_bin,
+                &acme_homes,
+                &acme_domain,
+                variant,
+                nginx_bin.as_ref().filter(|_| !confirm_reload),
+                dry_run,
+            )?;
+            if let Some(owner) = &cert_owner {
+                chown_cert_files(
+                    owner,
+                    &variant.cert,
+                    &variant.key,
+                    args.cert_owner_dir,
+                    dry_run,
+                )?;
+            }
+            Ok(())
+        })();
+        if let Err(err) = issued {
+            if args.purge_on_fail {
+                purge_cache_dir(&cache_dir, dry_run);
+            }
+            return Err(err);
         }
-        success("Certificate issuance completed");
     }
 
-    install_acme_cert(
-        &acme_bin,
-        &domain,
-        &cert_dst,
-        &key_dst,
-        reload_nginx,
-        nginx_bin.as_ref(),
-        dry_run,
-    )?;
+    if confirm_reload && nginx_bin.is_some() {
+        reload_nginx_binary(runner, nginx_bin.as_ref(), true, dry_run)?;
+    }
+
+    if args.cf_purge_cache {
+        purge_cf_cache(
+            runner,
+            &cf_token,
+            cf_zone_id.as_deref().unwrap_or_default(),
+            &domain,
+            dry_run,
+        );
+    }
+
+    if args.no_cron {
+        info("Skipping acme renew cron setup (--no-cron)");
+    } else {
+        setup_acme_renew_cron(runner, &acme_bin, &acme_homes, dry_run)?;
+    }
 
-    setup_acme_renew_cron(&acme_bin, &acme_home, dry_run)?;
+    if args.cron_deploy {
+        for variant in &variants {
+            let cache_dir = acme_homes
+                .cert_root()
+                .join(format!("{}{}", acme_domain, variant.cache_suffix));
+            let mut deploy_args = vec![
+                "issue-cert".to_string(),
+                "--offline".to_string(),
+                "--cert-input-path".to_string(),
+                cache_dir.join("fullchain.cer").display().to_string(),
+                "--key-input-path".to_string(),
+                cache_dir
+                    .join(format!("{}.key", acme_domain))
+                    .display()
+                    .to_string(),
+                "--cert-output-path".to_string(),
+                variant.cert.display().to_string(),
+                "--key-output-path".to_string(),
+                variant.key.display().to_string(),
+            ];
+            if let Some(owner) = &cert_owner {
+                deploy_args.push("--cert-owner".to_string());
+                deploy_args.push(owner.spec.clone());
+                if args.cert_owner_dir {
+                    deploy_args.push("--cert-owner-dir".to_string());
+                }
+            }
+            if let Some(nginx_bin) = &nginx_bin {
+                deploy_args.push("--reload-nginx".to_string());
+                deploy_args.push("--nginx-bin".to_string());
+                deploy_args.push(nginx_bin.display().to_string());
+            }
+            let label = if args.dual_cert {
+                format!("{} {}", domain, variant.keylength)
+            } else {
+                domain.clone()
+            };
+            setup_cert_deploy_cron(runner, &label, &deploy_args, dry_run)?;
+        }
+    }
 
     Ok(())
 }
 
 pub fn write_nginx_default(
+    runner: &dyn CommandRunner,
     env_overrides: &HashMap<String, String>,
-    cert_path: Option<PathBuf>,
-    key_path: Option<PathBuf>,
-    cert_dir_name: Option<String>,
-    domain: Option<String>,
-    output_path: Option<PathBuf>,
+    args: WriteDefaultArgs,
     dry_run: bool,
-) -> Result<(), String> {
-    let cert_path = resolve_optional_path(cert_path, env_overrides, "NGINX_CERT_PATH");
-    let key_path = resolve_optional_path(key_path, env_overrides, "NGINX_KEY_PATH");
+) -> Result<(), AppError> {
+    check_custom_values(&args.set_values)?;
+    let cert_path = resolve_optional_path(args.cert_path, env_overrides, "NGINX_CERT_PATH");
+    let key_path = resolve_optional_path(args.key_path, env_overrides, "NGINX_KEY_PATH");
+    let scheme = resolve_cert_name_scheme(
+        args.cert_name_scheme,
+        args.cert_filename,
+        args.key_filename,
+        env_overrides,
+    )?;
     let needs_domain = cert_path.is_none() || key_path.is_none();
     let domain = if needs_domain {
         Some(resolve_value(
-            domain,
+            args.domain,
             env_overrides,
             "DOMAIN",
             "Primary domain (e.g., example.com)",
@@ -262,731 +682,6771 @@ pub fn write_nginx_default(
     let cert_dir = if needs_domain {
         Some(resolve_cert_dir(
             None,
-            cert_dir_name,
+            args.cert_dir_name,
             env_overrides,
             &["NGINX_CERT_DIR_NAME", "CERT_DIR_NAME"],
             "custom",
+            &cert_root(args.cert_base_dir, env_overrides),
         )?)
     } else {
         None
     };
-    let (cert_path, key_path) = resolve_cert_paths(cert_path, key_path, cert_dir, domain)?;
+    let (cert_path, key_path) = resolve_cert_paths(cert_path, key_path, cert_dir, domain, &scheme)?;
+    let variants = cert_variants(&cert_path, &key_path, args.dual_cert);
+    let certificates = if args.inline_certs {
+        render_inline_certificates(&variants)?
+    } else {
+        render_certificates(&variants)
+    };
+    let hsts = render_hsts(
+        args.hsts,
+        args.hsts_max_age,
+        args.hsts_include_subdomains,
+        args.hsts_preload,
+    )?;
+    let ssl_protocols = ssl_protocols(
+        args.min_tls_version
+            .or_else(|| resolve_from_envs(env_overrides, &["MIN_TLS_VERSION"]))
+            .as_deref(),
+    )?;
+    let (cert_path, key_path) = (variants[0].cert.clone(), variants[0].key.clone());
     let output_path = resolve_path(
-        output_path,
+        args.output_path,
         env_overrides,
         "NGINX_DEFAULT_OUTPUT",
         "/etc/nginx/conf.d/default/00-default.conf",
         "nginx default output path",
     )?;
+    let bind = args
+        .bind
+        .or_else(|| resolve_from_envs(env_overrides, &["NGINX_DEFAULT_BIND"]));
+    let listen_addrs = resolve_listen_addrs(bind, args.ipv6, args.ipv6only.as_deref())?;
+    let default_action = if args.default_action.is_empty() {
+        resolve_from_envs(env_overrides, &["NGINX_DEFAULT_ACTION"])
+            .map(|value| value.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    } else {
+        args.default_action
+    };
+    let default_return = render_default_return(&default_action)?;
+    let stub_status = resolve_stub_status(args.stub_status, args.stub_status_allow, env_overrides)?;
+    let template_path =
+        resolve_optional_path(args.template, env_overrides, "NGINX_DEFAULT_TEMPLATE");
+    if args.watch && template_path.is_none() {
+        return Err(AppError::MissingInput(
+            "--watch requires --template".to_string(),
+        ));
+    }
+    let nginx_bin = args.test_config.then(|| resolve_nginx_bin(env_overrides));
+    let post_write_cmd = args
+        .post_write_cmd
+        .or_else(|| resolve_from_envs(env_overrides, &["POST_WRITE_CMD"]));
+    let output_owner = args
+        .output_owner
+        .or_else(|| resolve_from_envs(env_overrides, &["OUTPUT_OWNER"]))
+        .map(|spec| parse_owner("--output-owner", &spec))
+        .transpose()?;
+
+    let write = |template: &str| -> Result<(), AppError> {
+        step("Writing nginx default config");
+        let created_dirs = output_path.parent().map(missing_dirs).unwrap_or_default();
+        if let Some(parent) = output_path.parent() {
+            if dry_run {
+                info(&format!(
+                    "[dry-run] Would create directory: {}",
+                    parent.display()
+                ));
+            } else {
+                fs::create_dir_all(parent).map_err(|e| {
+                    AppError::Io(format!("Failed to create {}: {e}", parent.display()))
+                })?;
+            }
+        }
+
+        let content = render(
+            template,
+            &TemplateContext {
+                http_listen: Some(render_listen(&listen_addrs, "80 default_server")),
+                https_listen: Some(render_listen(&listen_addrs, "443 ssl default_server")),
+                ssl_certificates: Some(certificates.clone()),
+                hsts: Some(hsts.clone()),
+                default_return: Some(default_return.clone()),
+                stub_status: Some(stub_status.clone()),
+                server_tokens: Some(args.server_tokens.clone()),
+                ssl_protocols: Some(ssl_protocols.to_string()),
+                cert_path: Some(cert_path.display().to_string()),
+                key_path: Some(key_path.display().to_string()),
+                custom_values: args.set_values.clone(),
+                ..TemplateContext::default()
+            },
+        )?;
+        let content = stamp_provenance(content);
+
+        if config_unchanged(&output_path, &content) {
+            info("config unchanged, skipping");
+            return Ok(());
+        }
 
-    step("Writing nginx default config");
-    if let Some(parent) = output_path.parent() {
+        ensure_clobber_allowed(&output_path)?;
+        if args.backup {
+            backup_config(&output_path, args.keep_backups, dry_run)?;
+        }
+        record_change(output_path.display().to_string());
         if dry_run {
             info(&format!(
-                "[dry-run] Would create directory: {}",
-                parent.display()
+                "[dry-run] Would write nginx default config to: {}",
+                output_path.display()
             ));
         } else {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+            let mode = args.inline_certs.then_some(0o600);
+            write_atomic_mode(&output_path, content, mode).map_err(|e| {
+                AppError::Io(format!("Failed to write {}: {e}", output_path.display()))
+            })?;
+            success("nginx default config written");
         }
-    }
-
-    let content = NGINX_DEFAULT_TEMPLATE
-        .replace("{{CERT_PATH}}", &cert_path.display().to_string())
-        .replace("{{KEY_PATH}}", &key_path.display().to_string());
+        if let Some(owner) = &output_owner {
+            chown_output(owner, &output_path, &created_dirs, dry_run)?;
+        }
+        if let Some(command) = &post_write_cmd {
+            run_post_write_cmd(runner, command, &output_path, dry_run)?;
+        }
+        if let Some(nginx_bin) = &nginx_bin {
+            test_nginx_config(runner, nginx_bin, dry_run)?;
+        }
+        Ok(())
+    };
 
-    if dry_run {
-        info(&format!(
-            "[dry-run] Would write nginx default config to: {}",
-            output_path.display()
-        ));
-    } else {
-        fs::write(&output_path, content)
-            .map_err(|e| format!("Failed to write {}: {e}", output_path.display()))?;
-        success("nginx default config written");
+    write(&load_template(
+        template_path.as_deref(),
+        NGINX_DEFAULT_TEMPLATE,
+    )?)?;
+    match template_path {
+        Some(path) if args.watch => watch_template(&path, args.watch_interval, write),
+        _ => Ok(()),
     }
-    Ok(())
 }
 
 pub fn write_proxy_config(
+    runner: &dyn CommandRunner,
     env_overrides: &HashMap<String, String>,
     args: WriteProxyArgs,
+    force: bool,
     dry_run: bool,
-) -> Result<(), String> {
-    step("Writing reverse proxy config");
-    let proxy_domain = resolve_value(
-        args.proxy_domain,
-        env_overrides,
-        "PROXY_DOMAIN",
-        "Proxy domain (e.g., proxy.example.com)",
-        false,
-    )?;
-    let backend_url = resolve_value(
-        args.backend_url,
-        env_overrides,
-        "BACKEND_URL",
-        "Backend URL (e.g., https://emby.example.com:443)",
-        false,
-    )?;
+) -> Result<(), AppError> {
+    check_custom_values(&args.set_values)?;
+    let proxy_domains = resolve_proxy_domains(args.proxy_domain, env_overrides)?;
+    let server = args.server.as_str();
+    let caddy = server == "caddy";
+    if caddy && args.dual_cert {
+        return Err(AppError::Validation(
+            "--dual-cert is not supported with --server caddy".to_string(),
+        ));
+    }
+    if args.passthrough && server != "nginx" {
+        return Err(AppError::Validation(format!(
+            "--passthrough is not supported with --server {}",
+            server
+        )));
+    }
+    let backends_file = resolve_optional_path(args.backends_file, env_overrides, "BACKENDS_FILE");
+    let active_backend = args
+        .active_backend
+        .or_else(|| resolve_from_envs(env_overrides, &["ACTIVE_BACKEND"]));
+    let backends = match (backends_file, &active_backend) {
+        (Some(path), _) => Some(load_backends(&path)?),
+        (None, None) if args.backend_url.is_none() => env_backends(env_overrides)?,
+        _ => None,
+    };
+    if args.passthrough && backends.is_some() {
+        return Err(AppError::Validation(
+            "--passthrough supports a single backend URL".to_string(),
+        ));
+    }
+    let single_backend_url = match (&backends, active_backend.as_deref()) {
+        (Some(_), _) => String::new(),
+        (None, Some(color)) => resolve_active_backend(env_overrides, color)?,
+        (None, None) => resolve_value(
+            args.backend_url,
+            env_overrides,
+            "BACKEND_URL",
+            "Backend URL (e.g., https://emby.example.com:443)",
+            false,
+        )?,
+    };
+    let passthrough = args
+        .passthrough
+        .then(|| passthrough_address(&single_backend_url))
+        .transpose()?;
+    if caddy
+        && backends.as_ref().is_some_and(|backends| {
+            backends
+                .iter()
+                .any(|backend| backend.params.iter().any(|param| param != "down"))
+        })
+    {
+        warn("weight/backup markers are ignored with --server caddy");
+    }
+    let site_backend = |proxy_domain: &str| match &backends {
+        Some(backends) => {
+            if caddy {
+                let urls = backends
+                    .iter()
+                    .filter(|backend| !backend.params.iter().any(|param| param == "down"))
+                    .map(|backend| backend.url.clone())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (urls, backends[0].url.clone(), String::new())
+            } else if server == "apache" {
+                let name = upstream_name(proxy_domain);
+                (
+                    format!("balancer://{}", name),
+                    backends[0].url.clone(),
+                    render_balancer(&name, backends),
+                )
+            } else {
+                let name = upstream_name(proxy_domain);
+                (
+                    format!("{}://{}", backends[0].scheme, name),
+                    backends[0].url.clone(),
+                    render_upstream(&name, backends),
+                )
+            }
+        }
+        None => (
+            single_backend_url.clone(),
+            single_backend_url.clone(),
+            String::new(),
+        ),
+    };
+
+    let strip_prefix = args
+        .strip_prefix
+        .or_else(|| resolve_from_envs(env_overrides, &["STRIP_PREFIX"]))
+        .map(|prefix| parse_uri_prefix("--strip-prefix", &prefix))
+        .transpose()?;
+    if server != "nginx" && strip_prefix.is_some() {
+        return Err(AppError::Validation(format!(
+            "--strip-prefix is not supported with --server {}",
+            server
+        )));
+    }
+    let rewrites = render_rewrites(&args.rewrites)?;
+    if server != "nginx" && !rewrites.is_empty() {
+        return Err(AppError::Validation(format!(
+            "--rewrite is not supported with --server {}",
+            server
+        )));
+    }
+
+    let min_tls_version = args
+        .min_tls_version
+        .or_else(|| resolve_from_envs(env_overrides, &["MIN_TLS_VERSION"]));
+    if server != "nginx" && min_tls_version.is_some() {
+        return Err(AppError::Validation(format!(
+            "--min-tls-version is not supported with --server {}",
+            server
+        )));
+    }
+    let ssl_protocols = ssl_protocols(min_tls_version.as_deref())?;
+    let proxy_redirects = render_proxy_redirects(&args.proxy_redirects)?;
+    if server != "nginx" && !proxy_redirects.is_empty() {
+        return Err(AppError::Validation(format!(
+            "--proxy-redirect is not supported with --server {}",
+            server
+        )));
+    }
+    if server != "nginx" && !(args.forward_headers.is_empty() && args.no_forward_headers.is_empty())
+    {
+        return Err(AppError::Validation(format!(
+            "--forward-header and --no-forward-header are not supported with --server {}",
+            server
+        )));
+    }
+    let forward_headers = render_forward_headers(&args.forward_headers, &args.no_forward_headers)?;
+
+    let stub_status = resolve_stub_status(args.stub_status, args.stub_status_allow, env_overrides)?;
+    if !stub_status.is_empty() && (server != "nginx" || args.passthrough) {
+        return Err(AppError::Validation(
+            "--stub-status is only supported for nginx reverse proxy configs".to_string(),
+        ));
+    }
 
     let resolver = resolve_resolvers(&args.resolvers, env_overrides, "RESOLVER", DEFAULT_RESOLVER)?;
+    let host_header = args
+        .host_header
+        .or_else(|| resolve_from_envs(env_overrides, &["HOST_HEADER"]))
+        .unwrap_or_else(|| DEFAULT_HOST_HEADER.to_string());
+    let routes_file = resolve_optional_path(args.routes_file, env_overrides, "ROUTES_FILE");
+    let geo_allow = if args.geo_allow.is_empty() {
+        resolve_from_envs(env_overrides, &["GEO_ALLOW"])
+            .map(|value| split_list(&value))
+            .unwrap_or_default()
+    } else {
+        args.geo_allow
+    };
+    let geoip_db = resolve_optional_path(args.geoip_db, env_overrides, "GEOIP_DB");
 
-    let cert_path = resolve_optional_path(args.cert_path, env_overrides, "NGINX_CERT_PATH");
-    let key_path = resolve_optional_path(args.key_path, env_overrides, "NGINX_KEY_PATH");
-    let needs_domain = cert_path.is_none() || key_path.is_none();
-    let domain = if needs_domain {
-        Some(resolve_value(
-            Some(proxy_domain.clone()),
+    // Stream configs only load from the stream {} context, so they get their own dir.
+    let output_dir = if args.passthrough {
+        resolve_path(
+            args.output_dir,
             env_overrides,
-            "DOMAIN",
-            "Primary domain (e.g., example.com)",
-            false,
-        )?)
+            "STREAM_OUTPUT_DIR",
+            "/etc/nginx/stream.d",
+            "stream config output dir",
+        )?
     } else {
-        None
-    };
-    let cert_dir = if needs_domain {
-        Some(resolve_cert_dir(
-            resolve_optional_path(args.cert_dir, env_overrides, "CERT_DIR"),
-            args.cert_dir_name,
+        resolve_path(
+            args.output_dir,
             env_overrides,
-            &["NGINX_CERT_DIR_NAME", "CERT_DIR_NAME"],
-            "custom",
-        )?)
+            "PROXY_OUTPUT_DIR",
+            match server {
+                "caddy" => "/etc/caddy/sites",
+                "apache" => "/etc/apache2/sites-available",
+                _ => "/etc/nginx/conf.d/proxy",
+            },
+            "proxy config output dir",
+        )?
+    };
+    let mut sites = Vec::new();
+    if let Some(address) = &passthrough {
+        let name = upstream_name(&proxy_domains[0]);
+        sites.push(ProxySite {
+            domain: proxy_domains.join(", "),
+            output_path: output_dir.join(format!("{}.conf", proxy_domains[0].replace('.', "-"))),
+            backend_url: format!("${}", name),
+            upstream: render_sni_map(&name, &proxy_domains, address),
+            routes: Vec::new(),
+            path_rewrite: String::new(),
+            geoip: String::new(),
+            geo_gate: String::new(),
+        });
     } else {
-        None
+        for (index, proxy_domain) in proxy_domains.iter().enumerate() {
+            let site_override = |key: &str| {
+                resolve_from_envs(
+                    env_overrides,
+                    &[&format!("{}{}_{}", PROXY_DOMAIN_PREFIX, index + 1, key)],
+                )
+            };
+            let (backend_url, _, upstream) = site_backend(proxy_domain);
+            let (origin, backend_path) = split_backend_path(&backend_url)?;
+            let (backend_url, backend_path) = match server {
+                "nginx" => (origin, backend_path),
+                "caddy" if backend_path.is_some() => {
+                    return Err(AppError::Validation(
+                        "A backend URL path is not supported with --server caddy".to_string(),
+                    ));
+                }
+                _ => (backend_url, None),
+            };
+            let path_rewrite = format!(
+                "{}{}{}",
+                rewrites,
+                render_path_rewrite(strip_prefix.as_deref(), backend_path.as_deref()),
+                proxy_redirects
+            );
+
+            let routes_file = site_override("ROUTES_FILE")
+                .map(PathBuf::from)
+                .or_else(|| routes_file.clone());
+            let routes = resolve_routes(routes_file.as_deref(), args.emby_preset)?;
+            if server != "nginx" && !routes.is_empty() {
+                return Err(AppError::Validation(format!(
+                    "--routes-file and --emby-preset are not supported with --server {}",
+                    server
+                )));
+            }
+
+            let geo_allow = match site_override("GEO_ALLOW") {
+                Some(value) => split_list(&value),
+                None => geo_allow.clone(),
+            };
+            let geo_allow = parse_country_codes(&geo_allow)?;
+            if server != "nginx" && !geo_allow.is_empty() {
+                return Err(AppError::Validation(format!(
+                    "--geo-allow is not supported with --server {}",
+                    server
+                )));
+            }
+            let (geoip, geo_gate) = match (geo_allow.is_empty(), &geoip_db) {
+                (true, _) => (String::new(), String::new()),
+                (false, None) => {
+                    warn(
+                        "--geo-allow requires --geoip-db, writing the config without the GeoIP gate",
+                    );
+                    (String::new(), String::new())
+                }
+                (false, Some(db)) => {
+                    if !db.exists() {
+                        warn(&format!(
+                            "GeoIP database {} not found, nginx -t will fail until it exists",
+                            db.display()
+                        ));
+                    }
+                    render_geoip(db, &geo_allow, &upstream_name(proxy_domain))
+                }
+            };
+
+            let output_path = output_dir.join(format!(
+                "{}.{}",
+                proxy_domain.replace('.', "-"),
+                if caddy { "caddy" } else { "conf" }
+            ));
+            sites.push(ProxySite {
+                domain: proxy_domain.clone(),
+                output_path,
+                backend_url,
+                upstream,
+                routes,
+                path_rewrite,
+                geoip,
+                geo_gate,
+            });
+        }
+    }
+
+    let (_, tls_backend_url, _) = site_backend(&proxy_domains[0]);
+    let ssl_verify = args.backend_ssl_verify.as_deref();
+    let (host_header, backend_tls) = match server {
+        "caddy" => (
+            caddy_host_header(&host_header),
+            render_caddy_transport(&tls_backend_url, args.no_backend_sni, ssl_verify, &resolver)?,
+        ),
+        "apache" => (
+            apache_host_header(&host_header),
+            render_apache_backend_tls(&tls_backend_url, ssl_verify),
+        ),
+        _ => {
+            let backend_tls =
+                render_backend_tls(&tls_backend_url, args.no_backend_sni, ssl_verify)?;
+            (host_header, backend_tls)
+        }
     };
-    let (cert_path, key_path) = resolve_cert_paths(cert_path, key_path, cert_dir, domain)?;
 
-    let output_dir = resolve_path(
-        args.output_dir,
-        env_overrides,
-        "PROXY_OUTPUT_DIR",
-        "/etc/nginx/conf.d/proxy",
-        "proxy config output dir",
+    let (certificates, cert_path, key_path) = if args.passthrough {
+        (String::new(), PathBuf::new(), PathBuf::new())
+    } else {
+        let cert_path = resolve_optional_path(args.cert_path, env_overrides, "NGINX_CERT_PATH");
+        let key_path = resolve_optional_path(args.key_path, env_overrides, "NGINX_KEY_PATH");
+        let scheme = resolve_cert_name_scheme(
+            args.cert_name_scheme,
+            args.cert_filename,
+            args.key_filename,
+            env_overrides,
+        )?;
+        let needs_domain = cert_path.is_none() || key_path.is_none();
+        let domain = if !needs_domain {
+            None
+        } else if args.derive_domain_from_proxy.unwrap_or(true) {
+            let apex = apex_domain(&proxy_domains[0]);
+            info(&format!(
+                "Using {} for cert paths (from {})",
+                apex, proxy_domains[0]
+            ));
+            record_param("DOMAIN", &apex, false);
+            Some(apex)
+        } else {
+            Some(resolve_value(
+                Some(proxy_domains[0].clone()),
+                env_overrides,
+                "DOMAIN",
+                "Primary domain (e.g., example.com)",
+                false,
+            )?)
+        };
+        let cert_dir = if needs_domain {
+            Some(resolve_cert_dir(
+                resolve_optional_path(args.cert_dir, env_overrides, "CERT_DIR"),
+                args.cert_dir_name,
+                env_overrides,
+                &["NGINX_CERT_DIR_NAME", "CERT_DIR_NAME"],
+                "custom",
+                &cert_root(args.cert_base_dir, env_overrides),
+            )?)
+        } else {
+            None
+        };
+        let (cert_path, key_path) =
+            resolve_cert_paths(cert_path, key_path, cert_dir, domain, &scheme)?;
+        let variants = cert_variants(&cert_path, &key_path, args.dual_cert);
+        if args.inline_certs && server != "nginx" {
+            return Err(AppError::Validation(format!(
+                "--inline-certs is not supported with --server {}",
+                server
+            )));
+        }
+        let certificates = match server {
+            "caddy" => String::new(),
+            "apache" => render_apache_certificates(&variants),
+            _ if args.inline_certs => render_inline_certificates(&variants)?,
+            _ => render_certificates(&variants),
+        };
+        (
+            certificates,
+            variants[0].cert.clone(),
+            variants[0].key.clone(),
+        )
+    };
+    let render_server_hsts = match server {
+        "caddy" => render_caddy_hsts,
+        "apache" => render_apache_hsts,
+        _ => render_hsts,
+    };
+    let hsts = render_server_hsts(
+        args.hsts,
+        args.hsts_max_age,
+        args.hsts_include_subdomains,
+        args.hsts_preload,
     )?;
-    let output_path = output_dir.join(format!("{}.conf", proxy_domain.replace('.', "-")));
-
-    let content = NGINX_PROXY_TEMPLATE
-        .replace("{{PROXY_DOMAIN}}", &proxy_domain)
-        .replace("{{BACKEND_URL}}", &backend_url)
-        .replace("{{CERT_PATH}}", &cert_path.display().to_string())
-        .replace("{{KEY_PATH}}", &key_path.display().to_string())
-        .replace("{{RESOLVER}}", &resolver);
 
-    if dry_run {
-        info(&format!(
-            "[dry-run] Would write proxy config to: {}",
-            output_path.display()
+    let template_path = resolve_optional_path(args.template, env_overrides, "PROXY_TEMPLATE");
+    if args.watch && template_path.is_none() {
+        return Err(AppError::MissingInput(
+            "--watch requires --template".to_string(),
         ));
-        return Ok(());
     }
+    let nginx_bin =
+        (args.test_config && server == "nginx").then(|| resolve_nginx_bin(env_overrides));
+    let post_write_cmd = args
+        .post_write_cmd
+        .or_else(|| resolve_from_envs(env_overrides, &["POST_WRITE_CMD"]));
+    let output_owner = args
+        .output_owner
+        .or_else(|| resolve_from_envs(env_overrides, &["OUTPUT_OWNER"]))
+        .map(|spec| parse_owner("--output-owner", &spec))
+        .transpose()?;
 
-    fs::create_dir_all(&output_dir)
-        .map_err(|e| format!("Failed to create {}: {e}", output_dir.display()))?;
-    fs::write(&output_path, content)
-        .map_err(|e| format!("Failed to write {}: {e}", output_path.display()))?;
-    success("reverse proxy config written");
-    Ok(())
+    let listen_addrs = resolve_listen_addrs(None, args.ipv6, args.ipv6only.as_deref())?;
+    let ssl_ciphers = if args.dual_cert {
+        DUAL_SSL_CIPHERS
+    } else {
+        ECDSA_SSL_CIPHERS
+    };
+    let (kind, https_suffix) = if args.passthrough {
+        ("passthrough", "443")
+    } else {
+        ("reverse proxy", "443 ssl")
+    };
+    let write_site = |template: &str, site: &ProxySite| -> Result<(), AppError> {
+        step(&format!("Writing {} config for {}", kind, site.domain));
+        let output_path = &site.output_path;
+        let content = render(
+            template,
+            &TemplateContext {
+                geoip: Some(site.geoip.clone()),
+                upstream: Some(site.upstream.clone()),
+                http_listen: Some(render_listen(&listen_addrs, "80")),
+                https_listen: Some(render_listen(&listen_addrs, https_suffix)),
+                routes: Some(render_routes(&site.routes)),
+                path_rewrite: Some(site.path_rewrite.clone()),
+                forward_headers: Some(forward_headers.clone()),
+                ssl_certificates: Some(certificates.clone()),
+                hsts: Some(hsts.clone()),
+                stub_status: Some(stub_status.clone()),
+                geo_gate: Some(site.geo_gate.clone()),
+                transport: Some(backend_tls.clone()),
+                ssl_ciphers: Some(ssl_ciphers.to_string()),
+                ssl_protocols: Some(ssl_protocols.to_string()),
+                proxy_domain: Some(site.domain.clone()),
+                server_tokens: Some(args.server_tokens.clone()),
+                backend_url: Some(site.backend_url.clone()),
+                cert_path: Some(cert_path.display().to_string()),
+                key_path: Some(key_path.display().to_string()),
+                resolver: Some(resolver.clone()),
+                host_header: Some(host_header.clone()),
+                backend_tls: Some(backend_tls.clone()),
+                upstreams: Some(site.backend_url.clone()),
+                custom_values: args.set_values.clone(),
+                ..TemplateContext::default()
+            },
+        )?;
+        let content = stamp_provenance(content);
+
+        if !force && config_unchanged(output_path, &content) {
+            info("config unchanged, skipping");
+            return Ok(());
+        }
+
+        ensure_clobber_allowed(output_path)?;
+        if args.backup {
+            backup_config(output_path, args.keep_backups, dry_run)?;
+        }
+        let created_dirs = missing_dirs(&output_dir);
+        record_change(output_path.display().to_string());
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would write proxy config to: {}",
+                output_path.display()
+            ));
+        } else {
+            fs::create_dir_all(&output_dir).map_err(|e| {
+                AppError::Io(format!("Failed to create {}: {e}", output_dir.display()))
+            })?;
+            let mode = args.inline_certs.then_some(0o600);
+            write_atomic_mode(output_path, content, mode).map_err(|e| {
+                AppError::Io(format!("Failed to write {}: {e}", output_path.display()))
+            })?;
+            success(&format!(
+                "{} config written to {}",
+                kind,
+                output_path.display()
+            ));
+        }
+        if let Some(owner) = &output_owner {
+            chown_output(owner, output_path, &created_dirs, dry_run)?;
+        }
+        if let Some(command) = &post_write_cmd {
+            run_post_write_cmd(runner, command, output_path, dry_run)?;
+        }
+        if let Some(nginx_bin) = &nginx_bin {
+            test_nginx_config(runner, nginx_bin, dry_run)?;
+        }
+        if args.test_config {
+            match server {
+                "caddy" => test_caddy_config(runner, output_path, dry_run)?,
+                "apache" => test_apache_config(runner, dry_run)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+    let write = |template: &str| -> Result<(), AppError> {
+        for site in &sites {
+            write_site(template, site)?;
+        }
+        Ok(())
+    };
+
+    write(&load_template(
+        template_path.as_deref(),
+        match server {
+            "caddy" => CADDY_PROXY_TEMPLATE,
+            "apache" => APACHE_PROXY_TEMPLATE,
+            _ if args.passthrough => NGINX_STREAM_TEMPLATE,
+            _ => NGINX_PROXY_TEMPLATE,
+        },
+    )?)?;
+    match template_path {
+        Some(path) if args.watch => watch_template(&path, args.watch_interval, write),
+        _ => Ok(()),
+    }
 }
 
-pub fn print_params_table() -> Result<(), String> {
-    step("Supported parameters");
-    let rows = vec![
-        (
-            "--env KEY=VALUE",
-            "Override environment values (repeatable)",
-        ),
-        ("setup", "Install zsh/cron/nginx if missing"),
-        ("--install-zsh", "Install zsh if missing"),
-        ("--install-cron", "Install cron if missing"),
-        ("--install-nginx", "Install nginx if missing"),
-        ("--dry-run", "Simulate actions without changes"),
-        ("issue-cert", "Issue certs and optionally reload nginx"),
-        ("--cf-token", "Cloudflare token"),
-        ("CF_TOKEN", "Cloudflare token (env)"),
-        ("--cf-account-id", "Cloudflare account ID"),
-        ("CF_ACCOUNT_ID", "Cloudflare account ID (env)"),
-        ("--cf-zone-id", "Cloudflare zone ID"),
-        ("CF_ZONE_ID", "Cloudflare zone ID (env)"),
-        ("--domain", "Primary domain"),
-        ("DOMAIN", "Primary domain (env)"),
-        ("--wildcard-domain", "Wildcard domain"),
-        ("WILDCARD_DOMAIN", "Wildcard domain (env)"),
-        ("--acme-bin", "acme.sh path"),
-        ("ACME_BIN", "acme.sh path (env)"),
-        ("--acme-home", "acme home directory"),
-        ("ACME_HOME", "acme home directory (env)"),
-        ("--cert-dir", "Certificate directory (absolute path)"),
-        ("CERT_DIR", "Certificate directory (env)"),
-        ("--cert-dir-name", "Certificate directory name"),
-        ("CERT_DIR_NAME", "Certificate directory name (env)"),
-        ("--cert-input-path", "Certificate input path"),
-        ("CERT_INPUT_PATH", "Certificate input path (env)"),
-        ("--key-input-path", "Key input path"),
-        ("KEY_INPUT_PATH", "Key input path (env)"),
-        ("--cert-output-path", "Certificate output path"),
-        ("CERT_OUTPUT_PATH", "Certificate output path (env)"),
-        ("--key-output-path", "Key output path"),
-        ("KEY_OUTPUT_PATH", "Key output path (env)"),
-        ("--nginx-bin", "nginx binary"),
-        ("NGINX_BIN", "nginx binary (env)"),
-        ("--reload-nginx", "Reload nginx after issuance"),
-        ("--dry-run", "Simulate actions without changes"),
-        ("write-nginx-default", "Write default nginx 444 config"),
-        ("--cert-path", "Nginx cert path (absolute)"),
-        ("NGINX_CERT_PATH", "Nginx cert path (env)"),
-        ("--key-path", "Nginx key path (absolute)"),
-        ("NGINX_KEY_PATH", "Nginx key path (env)"),
-        ("--cert-dir-name", "Certificate directory name"),
-        ("NGINX_CERT_DIR_NAME", "Certificate dir name (env)"),
-        ("--domain", "Primary domain (used for default cert/key)"),
-        ("DOMAIN", "Primary domain (env)"),
-        ("--output-path", "Output path for default config"),
-        (
-            "NGINX_DEFAULT_OUTPUT",
-            "Output path for default config (env)",
-        ),
-        ("--dry-run", "Simulate actions without changes"),
-        ("write-proxy-config", "Write reverse proxy config"),
-        ("--proxy-domain", "Proxy domain"),
-        ("PROXY_DOMAIN", "Proxy domain (env)"),
-        ("--backend-url", "Backend URL"),
-        ("BACKEND_URL", "Backend URL (env)"),
-        ("--resolver", "DNS resolver (repeatable)"),
-        ("RESOLVER", "DNS resolver list (env or interactive)"),
-        ("--cert-path", "Nginx cert path (absolute)"),
-        ("NGINX_CERT_PATH", "Nginx cert path (env)"),
-        ("--key-path", "Nginx key path (absolute)"),
-        ("NGINX_KEY_PATH", "Nginx key path (env)"),
-        ("--cert-dir", "Certificate directory (absolute path)"),
-        ("CERT_DIR", "Certificate directory (env)"),
-        ("--cert-dir-name", "Certificate directory name"),
-        ("CERT_DIR_NAME", "Certificate directory name (env)"),
-        ("--output-dir", "Proxy config output dir"),
-        ("PROXY_OUTPUT_DIR", "Proxy config output dir (env)"),
-        ("--dry-run", "Simulate actions without changes"),
-    ];
-
-    let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
-    let desc_width = rows.iter().map(|(_, desc)| desc.len()).max().unwrap_or(0);
-    let name_width = name_width.max("Parameter/ENV".len());
-    let desc_width = desc_width.max("Description".len());
+struct ProxySite {
+    domain: String,
+    output_path: PathBuf,
+    backend_url: String,
+    upstream: String,
+    routes: Vec<Route>,
+    path_rewrite: String,
+    geoip: String,
+    geo_gate: String,
+}
 
-    let border = format!(
-        "+-{}-+-{}-+",
-        "-".repeat(name_width),
-        "-".repeat(desc_width)
-    );
-    println!("{}", border);
-    println!(
-        "| {:width$} | {:desc_width$} |",
-        "Parameter/ENV",
-        "Description",
-        width = name_width,
-        desc_width = desc_width
-    );
-    println!("{}", border);
-    for (name, desc) in rows {
-        println!(
-            "| {:width$} | {:desc_width$} |",
-            name,
-            desc,
-            width = name_width,
-            desc_width = desc_width
-        );
+fn resolve_proxy_domains(
+    proxy_domains: Vec<String>,
+    env_overrides: &HashMap<String, String>,
+) -> Result<Vec<String>, AppError> {
+    let proxy_domains = if proxy_domains.is_empty() {
+        split_list(&resolve_value(
+            None,
+            env_overrides,
+            "PROXY_DOMAIN",
+            "Proxy domain (e.g., proxy.example.com)",
+            false,
+        )?)
+    } else {
+        proxy_domains
+            .iter()
+            .map(|domain| domain.trim().to_string())
+            .collect()
+    };
+    let mut unique: Vec<String> = Vec::new();
+    for domain in proxy_domains {
+        if unique.contains(&domain) {
+            return Err(AppError::Validation(format!(
+                "--proxy-domain {} is given more than once",
+                domain
+            )));
+        }
+        unique.push(domain);
     }
-    println!("{}", border);
-    Ok(())
+    if unique.is_empty() {
+        return Err(AppError::MissingInput(
+            "PROXY_DOMAIN is required".to_string(),
+        ));
+    }
+    Ok(unique)
 }
 
-fn copy_cert_files(
-    cert_src: &Path,
-    key_src: &Path,
-    cert_dst: &Path,
-    key_dst: &Path,
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn reload_upstream(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    proxy_domain: Option<String>,
+    backend_url: Option<String>,
+    output_dir: Option<PathBuf>,
+    nginx_bin: Option<PathBuf>,
     dry_run: bool,
-) -> Result<(), String> {
-    let cert_parent_display = cert_dst
-        .parent()
-        .map(|p| p.display().to_string())
-        .unwrap_or_else(|| "/".to_string());
-    if dry_run {
-        info(&format!(
-            "[dry-run] Would create cert dir: {}",
-            cert_parent_display
-        ));
-    } else if let Some(parent) = cert_dst.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+) -> Result<(), AppError> {
+    step("Switching proxy backend");
+    ensure_root(runner)?;
+    let proxy_domain = resolve_value(
+        proxy_domain,
+        env_overrides,
+        "PROXY_DOMAIN",
+        "Proxy domain (e.g., proxy.example.com)",
+        false,
+    )?;
+    let backend_url = resolve_value(
+        backend_url,
+        env_overrides,
+        "BACKEND_URL",
+        "Backend URL (e.g., https://emby.example.com:443)",
+        false,
+    )?;
+    let output_dir = resolve_optional_path(output_dir, env_overrides, "PROXY_OUTPUT_DIR")
+        .unwrap_or_else(|| PathBuf::from("/etc/nginx/conf.d/proxy"));
+    let nginx_bin = nginx_bin.unwrap_or_else(|| resolve_nginx_bin(env_overrides));
+    let config_path = output_dir.join(format!("{}.conf", proxy_domain.replace('.', "-")));
+    let original = fs::read_to_string(&config_path).map_err(|e| {
+        AppError::Io(format!(
+            "Failed to read {} (write it first with write-proxy-config): {e}",
+            config_path.display()
+        ))
+    })?;
+    let (content, previous) = replace_backend_url(&original, &backend_url)
+        .map_err(|e| AppError::Validation(format!("{}: {}", config_path.display(), e)))?;
+    let content = refresh_provenance(content);
+    if config_unchanged(&config_path, &content) {
+        info(&format!("{} already proxies to {}", proxy_domain, previous));
+        return Ok(());
     }
 
+    ensure_clobber_allowed(&config_path)?;
+    record_change(config_path.display().to_string());
     if dry_run {
         info(&format!(
-            "[dry-run] Would copy cert: {} -> {}",
-            cert_src.display(),
-            cert_dst.display()
+            "[dry-run] Would switch {} from {} to {} in {}",
+            proxy_domain,
+            previous,
+            backend_url,
+            config_path.display()
         ));
+    } else {
+        write_atomic(&config_path, &content)
+            .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", config_path.display())))?;
         info(&format!(
-            "[dry-run] Would copy key: {} -> {}",
-            key_src.display(),
-            key_dst.display()
+            "{}: {} -> {}",
+            config_path.display(),
+            previous,
+            backend_url
         ));
-    } else {
-        fs::copy(cert_src, cert_dst)
-            .map_err(|e| format!("Failed to copy cert from {}: {e}", cert_src.display()))?;
-        fs::copy(key_src, key_dst)
-            .map_err(|e| format!("Failed to copy key from {}: {e}", key_src.display()))?;
-        success("Certificate files updated");
+    }
+    if let Err(err) = reload_nginx_binary(runner, Some(&nginx_bin), false, dry_run) {
+        if !dry_run {
+            warn(&format!(
+                "Restoring {} after the failed reload",
+                config_path.display()
+            ));
+            write_atomic(&config_path, &original).map_err(|e| {
+                AppError::Io(format!("Failed to restore {}: {e}", config_path.display()))
+            })?;
+        }
+        return Err(err);
     }
     Ok(())
 }
 
-fn install_acme_cert(
-    acme_bin: &Path,
-    domain: &str,
-    cert_dst: &Path,
-    key_dst: &Path,
-    reload_nginx: bool,
-    nginx_bin: Option<&PathBuf>,
-    dry_run: bool,
-) -> Result<(), String> {
-    if let Some(parent) = cert_dst.parent() {
-        if dry_run {
-            info(&format!(
-                "[dry-run] Would create cert dir: {}",
-                parent.display()
-            ));
-        } else {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+pub fn check_configs(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    nginx_bin: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    lint: bool,
+    since: Option<Duration>,
+) -> Result<(), AppError> {
+    step("Checking nginx configs");
+    let nginx_bin = match nginx_bin {
+        Some(bin) => bin,
+        None => resolve_nginx_bin(env_overrides),
+    };
+    let test_result = test_nginx_config(runner, &nginx_bin, false);
+    if let Err(err) = &test_result {
+        warn(&err.to_string());
+    }
+
+    let mut problems = Vec::new();
+    if lint {
+        let output_dir = resolve_optional_path(output_dir, env_overrides, "PROXY_OUTPUT_DIR")
+            .unwrap_or_else(|| PathBuf::from("/etc/nginx/conf.d/proxy"));
+        problems = lint_proxy_configs(&output_dir, since)?;
+        for problem in &problems {
+            warn(problem);
+        }
+        if problems.is_empty() {
+            success(&format!("No issues found in {}", output_dir.display()));
         }
     }
 
-    let mut cmd = Command::new(acme_bin);
-    cmd.arg("--install-cert")
-        .arg("-d")
-        .arg(domain)
-        .arg("--fullchain-file")
-        .arg(cert_dst)
-        .arg("--key-file")
-        .arg(key_dst)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    test_result?;
+    if !problems.is_empty() {
+        return Err(AppError::Validation(format!(
+            "Lint found {} issue(s)",
+            problems.len()
+        )));
+    }
+    Ok(())
+}
 
-    if reload_nginx {
-        let nginx_bin = nginx_bin.ok_or("nginx binary is required for reload".to_string())?;
-        let reload_cmd = format!("{} -s reload", nginx_bin.display());
-        cmd.arg("--reloadcmd").arg(reload_cmd);
+pub fn test_backend(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    backend_url: Option<String>,
+    timeout: u64,
+) -> Result<(), AppError> {
+    let backend_url = resolve_value(
+        backend_url,
+        env_overrides,
+        "BACKEND_URL",
+        "Backend URL (e.g., https://emby.example.com:443)",
+        false,
+    )?;
+    if !(backend_url.starts_with("http://") || backend_url.starts_with("https://")) {
+        return Err(AppError::Validation(format!(
+            "Backend URL must start with http:// or https://: {}",
+            backend_url
+        )));
+    }
+    step(&format!("Testing backend {}", backend_url));
+    let output = runner
+        .output(
+            Command::new("curl")
+                .args(["-sS", "-v", "-L", "-k", "-o", "/dev/null"])
+                .arg("--max-time")
+                .arg(timeout.to_string())
+                .arg("-w")
+                .arg("%{http_code}\n%{ssl_verify_result}\n%{url_effective}\n%{remote_ip}\n%{time_total}")
+                .arg(&backend_url),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run curl: {e}")))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| line.starts_with("curl:"))
+            .unwrap_or("request failed");
+        return Err(AppError::ExternalCommand(format!(
+            "Backend {} is unreachable: {}",
+            backend_url, reason
+        )));
     }
 
-    if dry_run {
-        info("[dry-run] Would run acme.sh --install-cert");
-        return Ok(());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.lines().collect();
+    let [status, verify_result, effective_url, remote_ip, time_total] = fields[..] else {
+        return Err(AppError::ExternalCommand(format!(
+            "Unexpected curl output: {}",
+            stdout.trim()
+        )));
+    };
+    info(&format!("HTTP status: {}", status));
+    if effective_url.trim_end_matches('/') != backend_url.trim_end_matches('/') {
+        info(&format!("Redirected to: {}", effective_url));
     }
+    info(&format!("Remote address: {}", remote_ip));
+    info(&format!("Response time: {}s", time_total));
 
-    let status = cmd
-        .status()
-        .map_err(|e| format!("Failed to run acme.sh --install-cert: {e}"))?;
-    if !status.success() {
-        return Err("acme.sh --install-cert failed".to_string());
+    if effective_url.starts_with("https://") {
+        for line in stderr.lines() {
+            let line = line.trim_start_matches('*').trim();
+            for (prefix, label) in TLS_DETAIL_LABELS {
+                if let Some(detail) = line.strip_prefix(prefix) {
+                    info(&format!("{}: {}", label, detail.trim()));
+                }
+            }
+        }
+        if verify_result == "0" {
+            success("Backend certificate is trusted; --backend-ssl-verify on is safe");
+        } else {
+            warn(&format!(
+                "Backend certificate failed verification (code {}), e.g. self-signed or expired; keep proxy_ssl_verify off",
+                verify_result
+            ));
+        }
     }
-    success("Certificate files installed");
+    success(&format!("Backend {} is reachable", backend_url));
     Ok(())
 }
 
-fn reload_nginx_binary(nginx_bin: Option<&PathBuf>, dry_run: bool) -> Result<(), String> {
-    let nginx_bin = nginx_bin.ok_or("nginx binary is required for reload".to_string())?;
-    if dry_run {
-        info("[dry-run] Would run nginx -t and reload");
-        return Ok(());
+pub fn probe_dns(
+    env_overrides: &HashMap<String, String>,
+    resolvers: Vec<String>,
+    name: String,
+    timeout: u64,
+) -> Result<(), AppError> {
+    step(&format!(
+        "Probing DNS resolvers with an A query for {}",
+        name
+    ));
+    let presets = resolver_presets(env_overrides);
+    let requested = if resolvers.is_empty() {
+        resolve_from_envs(env_overrides, &["RESOLVER"])
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        resolvers
+    };
+    let preset_targets = |label: &str, addresses: &str| {
+        resolver_addresses(addresses)
+            .into_iter()
+            .map(|address| (label.to_string(), address))
+            .collect::<Vec<_>>()
+    };
+    let targets = if requested.is_empty() {
+        presets
+            .iter()
+            .flat_map(|(_, label, addresses)| preset_targets(label, addresses))
+            .collect::<Vec<_>>()
+    } else {
+        requested
+            .iter()
+            .flat_map(|value| {
+                match presets
+                    .iter()
+                    .find(|(preset, _, _)| preset.eq_ignore_ascii_case(value.trim()))
+                {
+                    Some((_, label, addresses)) => preset_targets(label, addresses),
+                    None => preset_targets("custom", value),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    if targets.is_empty() {
+        return Err(AppError::MissingInput(
+            "No resolver addresses to probe".to_string(),
+        ));
     }
 
-    let status = Command::new(nginx_bin)
-        .arg("-t")
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .map_err(|e| format!("Failed to run nginx -t: {e}"))?;
-    if !status.success() {
-        return Err("nginx -t failed".to_string());
+    let addresses = targets
+        .iter()
+        .map(|(_, address)| address.clone())
+        .collect::<Vec<_>>();
+    let results = probe_all(&addresses, &name, Duration::from_secs(timeout));
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for ((label, address), result) in targets.iter().zip(results) {
+        let (outcome, latency) = match result {
+            Ok(probe) => {
+                if !probe.usable() {
+                    failed += 1;
+                }
+                (
+                    format!("{} ({} answers)", probe.rcode, probe.answers),
+                    format!("{}ms", probe.latency.as_millis()),
+                )
+            }
+            Err(err) => {
+                failed += 1;
+                (err.to_string(), "-".to_string())
+            }
+        };
+        rows.push(vec![label.clone(), address.clone(), outcome, latency]);
     }
+    print_table(&["Resolver", "Address", "Result", "Latency"], &rows);
 
-    let status = Command::new(nginx_bin)
-        .arg("-s")
-        .arg("reload")
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .map_err(|e| format!("Failed to reload nginx: {e}"))?;
-    if !status.success() {
-        return Err("nginx reload failed".to_string());
+    // Probing every preset is for picking one, so only a total outage fails it.
+    if failed == targets.len() || (!requested.is_empty() && failed > 0) {
+        return Err(AppError::ExternalCommand(format!(
+            "{} of {} resolver address(es) did not answer",
+            failed,
+            targets.len()
+        )));
     }
-    success("nginx reloaded");
+    if failed > 0 {
+        warn(&format!(
+            "{} of {} resolver address(es) did not answer",
+            failed,
+            targets.len()
+        ));
+    }
+    success("DNS probe finished");
     Ok(())
 }
 
-fn install_if_missing<F>(
-    command_name: &str,
-    changes: &mut Vec<String>,
-    dry_run: bool,
-    installer: F,
-) -> Result<(), String>
-where
-    F: Fn(bool) -> Result<(), String>,
-{
-    if command_exists(command_name) {
-        info(&format!("{} is already installed", command_name));
+pub fn verify_cert_domain(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    cert_path: Option<PathBuf>,
+    domain: Option<String>,
+) -> Result<(), AppError> {
+    let cert_path = PathBuf::from(resolve_value(
+        cert_path.map(|path| path.display().to_string()),
+        env_overrides,
+        "CERT_OUTPUT_PATH",
+        "Certificate path",
+        false,
+    )?);
+    let domain = validate_hostname(
+        "--domain",
+        &resolve_value(domain, env_overrides, "DOMAIN", "Domain to check", false)?,
+    )?;
+    if !cert_path.is_file() {
+        return Err(AppError::MissingInput(format!(
+            "Certificate not found: {}",
+            cert_path.display()
+        )));
+    }
+    step(&format!(
+        "Checking {} against {}",
+        cert_path.display(),
+        domain
+    ));
+    let output = runner
+        .output(
+            Command::new("openssl")
+                .args(["x509", "-noout", "-text", "-in"])
+                .arg(&cert_path),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run openssl x509: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::ExternalCommand(format!(
+            "Failed to parse certificate {}",
+            cert_path.display()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let sans: Vec<String> = stdout
+        .lines()
+        .skip_while(|line| !line.trim().starts_with("X509v3 Subject Alternative Name"))
+        .nth(1)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.trim().strip_prefix("DNS:"))
+        .map(str::to_string)
+        .collect();
+    if sans.is_empty() {
+        warn("Certificate has no DNS subject alternative names");
+    }
+    for san in &sans {
+        info(&format!("SAN: {}", san));
+    }
+    match sans.iter().find(|san| san_matches(san, &domain)) {
+        Some(san) => {
+            success(&format!("{} is covered by {}", domain, san));
+            Ok(())
+        }
+        None => Err(AppError::Validation(format!(
+            "{} is not covered by {}",
+            domain,
+            cert_path.display()
+        ))),
+    }
+}
+
+fn san_matches(san: &str, domain: &str) -> bool {
+    let san = san.to_ascii_lowercase();
+    match san.strip_prefix("*.") {
+        Some(parent) => domain
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == parent),
+        None => san == domain,
+    }
+}
+
+pub fn list_presets(env_overrides: &HashMap<String, String>, format: &str) -> Result<(), AppError> {
+    let resolvers = resolver_presets(env_overrides);
+    let routes = emby_preset_routes();
+    if format == "json" {
+        let body = serde_json::json!({
+            "resolvers": resolvers
+                .iter()
+                .map(|(name, label, addresses)| {
+                    serde_json::json!({ "name": name, "label": label, "addresses": addresses })
+                })
+                .collect::<Vec<_>>(),
+            "emby_routes": routes
+                .iter()
+                .map(|route| {
+                    serde_json::json!({ "path": route.path, "directives": route_directives(route) })
+                })
+                .collect::<Vec<_>>(),
+        });
+        let body = serde_json::to_string_pretty(&body)
+            .map_err(|e| AppError::Io(format!("Failed to serialize presets: {e}")))?;
+        println!("{}", body);
         return Ok(());
     }
 
-    info(&format!("Installing {}", command_name));
-    installer(dry_run)?;
-    if dry_run {
-        changes.push(format!("Would install {}", command_name));
-    } else {
-        changes.push(format!("Installed {}", command_name));
+    step("Resolver presets (--resolver <name>)");
+    for (name, label, addresses) in &resolvers {
+        info(&format!("{:<12} {:<12} {}", name, label, addresses));
+    }
+    step("Emby route preset (--emby-preset)");
+    for route in &routes {
+        info(&format!("{:<16} {}", route.path, route_directives(route)));
     }
     Ok(())
 }
 
-fn install_nginx_official(dry_run: bool) -> Result<(), String> {
-    let os_id = read_os_id()?;
-    match os_id.as_str() {
-        "debian" => install_nginx_debian_like("debian", dry_run),
-        "ubuntu" => install_nginx_debian_like("ubuntu", dry_run),
-        "alpine" => install_nginx_alpine(dry_run),
-        _ => Err(format!("Unsupported OS for nginx install: {}", os_id)),
+pub fn show_template(kind: &str) -> Result<(), AppError> {
+    let template = match kind {
+        "default" => NGINX_DEFAULT_TEMPLATE,
+        "caddy" => CADDY_PROXY_TEMPLATE,
+        "apache" => APACHE_PROXY_TEMPLATE,
+        "stream" => NGINX_STREAM_TEMPLATE,
+        _ => NGINX_PROXY_TEMPLATE,
+    };
+    print!("{}", template);
+    Ok(())
+}
+
+fn route_directives(route: &Route) -> String {
+    route
+        .directives
+        .iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                format!("{};", key)
+            } else {
+                format!("{} {};", key, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn combine_pem(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+    output: &Path,
+    order: &str,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let cert_path = PathBuf::from(resolve_value(
+        cert_path.map(|path| path.display().to_string()),
+        env_overrides,
+        "CERT_OUTPUT_PATH",
+        "Certificate (fullchain) path",
+        false,
+    )?);
+    let key_path = PathBuf::from(resolve_value(
+        key_path.map(|path| path.display().to_string()),
+        env_overrides,
+        "KEY_OUTPUT_PATH",
+        "Private key path",
+        false,
+    )?);
+    step(&format!(
+        "Combining {} and {}",
+        cert_path.display(),
+        key_path.display()
+    ));
+    let cert = read_pem(&cert_path, "CERTIFICATE")?;
+    let key = read_pem(&key_path, "PRIVATE KEY")?;
+    check_key_matches_cert(runner, &cert_path, &key_path)?;
+    let content = match order {
+        "key-first" => format!("{}{}", key, cert),
+        _ => format!("{}{}", cert, key),
+    };
+
+    if config_unchanged(output, &content) {
+        info("combined PEM unchanged, skipping");
+        return Ok(());
+    }
+    record_change(output.display().to_string());
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would write combined PEM to: {}",
+            output.display()
+        ));
+        return Ok(());
+    }
+    write_atomic_mode(output, content, Some(0o600))
+        .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", output.display())))?;
+    success(&format!("combined PEM written to {}", output.display()));
+    Ok(())
+}
+
+pub fn rollback_cert(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    args: RollbackArgs,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Rolling back certificate");
+    ensure_root(runner)?;
+    let cert_path = resolve_optional_path(args.cert_output_path, env_overrides, "CERT_OUTPUT_PATH");
+    let key_path = resolve_optional_path(args.key_output_path, env_overrides, "KEY_OUTPUT_PATH");
+    let scheme = resolve_cert_name_scheme(
+        args.cert_name_scheme,
+        args.cert_filename,
+        args.key_filename,
+        env_overrides,
+    )?;
+    let needs_domain = cert_path.is_none() || key_path.is_none();
+    let domain = if needs_domain {
+        Some(resolve_value(
+            args.domain,
+            env_overrides,
+            "DOMAIN",
+            "Primary domain (e.g., example.com)",
+            false,
+        )?)
+    } else {
+        None
+    };
+    let cert_dir = if needs_domain {
+        Some(resolve_cert_dir(
+            resolve_optional_path(args.cert_dir, env_overrides, "CERT_DIR"),
+            args.cert_dir_name,
+            env_overrides,
+            &["CERT_DIR_NAME"],
+            "custom",
+            &cert_root(args.cert_base_dir, env_overrides),
+        )?)
+    } else {
+        None
+    };
+    let (cert_path, key_path) = resolve_cert_paths(cert_path, key_path, cert_dir, domain, &scheme)?;
+
+    let backups = list_cert_backups(&cert_path, &key_path);
+    if backups.is_empty() {
+        return Err(AppError::Validation(format!(
+            "No backups found for {}",
+            cert_path.display()
+        )));
+    }
+    let now = unix_now();
+    info("Available backups:");
+    for timestamp in &backups {
+        info(&format!(
+            "  {} ({})",
+            timestamp,
+            format_age(now.saturating_sub(*timestamp))
+        ));
+    }
+    if args.list {
+        return Ok(());
+    }
+
+    let timestamp = match args.to_backup {
+        Some(timestamp) if backups.contains(&timestamp) => timestamp,
+        Some(timestamp) => {
+            return Err(AppError::Validation(format!(
+                "Backup {} not found",
+                timestamp
+            )));
+        }
+        None => backups[0],
+    };
+    for path in [&cert_path, &key_path] {
+        let backup = backup_path_at(path, timestamp);
+        record_change(path.display().to_string());
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would restore: {} -> {}",
+                backup.display(),
+                path.display()
+            ));
+            continue;
+        }
+        copy_atomic(&backup, path)
+            .map_err(|e| AppError::Io(format!("Failed to restore {}: {e}", backup.display())))?;
+    }
+    if !dry_run {
+        success(&format!("Restored backup {}", timestamp));
+    }
+
+    let nginx_bin = args
+        .nginx_bin
+        .unwrap_or_else(|| resolve_nginx_bin(env_overrides));
+    let confirm_reload = args.confirm_reload && !args.yes;
+    reload_nginx_binary(runner, Some(&nginx_bin), confirm_reload, dry_run)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn restore_config(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    file: &Path,
+    to_backup: Option<u64>,
+    list: bool,
+    nginx_bin: Option<PathBuf>,
+    no_reload: bool,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step(&format!("Restoring {}", file.display()));
+    ensure_root(runner)?;
+    let backups = list_backups(file);
+    if backups.is_empty() {
+        return Err(AppError::Validation(format!(
+            "No backups found for {}",
+            file.display()
+        )));
+    }
+    let now = unix_now();
+    info("Available backups:");
+    for timestamp in &backups {
+        info(&format!(
+            "  {} ({})",
+            timestamp,
+            format_age(now.saturating_sub(*timestamp))
+        ));
+    }
+    if list {
+        return Ok(());
+    }
+
+    let timestamp = match to_backup {
+        Some(timestamp) if backups.contains(&timestamp) => timestamp,
+        Some(timestamp) => {
+            return Err(AppError::Validation(format!(
+                "Backup {} not found",
+                timestamp
+            )));
+        }
+        None => backups[0],
+    };
+    let backup = backup_path_at(file, timestamp);
+    record_change(file.display().to_string());
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would restore: {} -> {}",
+            backup.display(),
+            file.display()
+        ));
+    } else {
+        copy_atomic(&backup, file)
+            .map_err(|e| AppError::Io(format!("Failed to restore {}: {e}", backup.display())))?;
+        success(&format!("Restored backup {}", timestamp));
+    }
+
+    if no_reload {
+        return Ok(());
+    }
+    let nginx_bin = nginx_bin.unwrap_or_else(|| resolve_nginx_bin(env_overrides));
+    reload_nginx_binary(runner, Some(&nginx_bin), false, dry_run)
+}
+
+// Plain key=value lines on stdout so healthchecks and textfile collectors can
+// parse them; the exit code carries the overall result.
+pub fn check_renewal(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    args: CheckRenewalArgs,
+) -> Result<(), AppError> {
+    let domains = if args.domain.is_empty() {
+        resolve_from_envs(env_overrides, &["DOMAIN"])
+            .map(|value| split_list(&value))
+            .unwrap_or_default()
+    } else {
+        args.domain
+    };
+    if domains.is_empty() {
+        return Err(AppError::MissingInput(
+            "check-renewal needs --domain or DOMAIN".to_string(),
+        ));
+    }
+    let scheme = resolve_cert_name_scheme(
+        args.cert_name_scheme,
+        args.cert_filename,
+        args.key_filename,
+        env_overrides,
+    )?;
+    let cert_dirs = if args.cert_dir.is_empty() {
+        resolve_optional_path(None, env_overrides, "CERT_DIR")
+            .into_iter()
+            .collect()
+    } else {
+        args.cert_dir
+    };
+    let cert_dir_names = if args.cert_dir_name.is_empty() {
+        resolve_from_envs(env_overrides, &["CERT_DIR_NAME"])
+            .map(|value| split_list(&value))
+            .unwrap_or_default()
+    } else {
+        args.cert_dir_name
+    };
+    let cert_root = cert_root(args.cert_base_dir, env_overrides);
+
+    let mut checks: Vec<(&String, CertVariant)> = Vec::new();
+    for (index, domain) in domains.iter().enumerate() {
+        // Same precedence as issue-cert: CERT_DIR, then CERT_DIR_NAME under the base dir.
+        let cert_dir = match per_domain(&cert_dirs, index, domains.len(), "--cert-dir")? {
+            Some(dir) => dir.clone(),
+            None => cert_root.join(
+                per_domain(&cert_dir_names, index, domains.len(), "--cert-dir-name")?
+                    .map_or("custom", String::as_str),
+            ),
+        };
+        let (cert, key) =
+            resolve_cert_paths(None, None, Some(cert_dir), Some(domain.clone()), &scheme)?;
+        for variant in cert_variants(&cert, &key, args.dual_cert) {
+            if let Some((other, _)) = checks.iter().find(|(_, seen)| seen.cert == variant.cert) {
+                return Err(AppError::Validation(format!(
+                    "{} and {} both resolve to {}; give each domain its own --cert-dir-name",
+                    other,
+                    domain,
+                    variant.cert.display()
+                )));
+            }
+            checks.push((domain, variant));
+        }
+    }
+
+    let mut failing = 0;
+    for (domain, variant) in &checks {
+        let (cert, key) = (&variant.cert, &variant.key);
+        let days = days_until_expiry(runner, cert);
+        let key_match = days.is_some() && check_key_matches_cert(runner, cert, key).is_ok();
+        let status = match days {
+            None => "MISSING",
+            Some(_) if !key_match => "KEY_MISMATCH",
+            Some(days) if days < 0 => "EXPIRED",
+            Some(days) if days <= args.threshold_days as i64 => "EXPIRING",
+            Some(_) => "OK",
+        };
+        if status != "OK" {
+            failing += 1;
+        }
+        println!(
+            "domain={} status={} days_left={} key_match={} cert={}",
+            domain,
+            status,
+            days.map_or_else(|| "-".to_string(), |days| days.to_string()),
+            if key_match { "yes" } else { "no" },
+            cert.display()
+        );
+    }
+    println!(
+        "status={} checked={} failing={}",
+        if failing == 0 { "OK" } else { "CRITICAL" },
+        checks.len(),
+        failing
+    );
+    if failing > 0 {
+        return Err(AppError::Validation(format!(
+            "{} of {} certificates need attention",
+            failing,
+            checks.len()
+        )));
+    }
+    Ok(())
+}
+
+// A per-domain flag is given once for every domain or once per --domain, in order.
+fn per_domain<'a, T>(
+    values: &'a [T],
+    index: usize,
+    domains: usize,
+    flag: &str,
+) -> Result<Option<&'a T>, AppError> {
+    match values.len() {
+        0 => Ok(None),
+        1 => Ok(values.first()),
+        len if len == domains => Ok(values.get(index)),
+        len => Err(AppError::Validation(format!(
+            "{} was given {} times for {} domains; give it once or once per --domain",
+            flag, len, domains
+        ))),
+    }
+}
+
+pub fn show_status(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    acme_home: Option<PathBuf>,
+    warn_days: u64,
+) -> Result<(), AppError> {
+    step("Certificate renewal status");
+    info(&format!(
+        "Environment: {}",
+        container_hint().map_or_else(
+            || "host".to_string(),
+            |hint| format!("container ({})", hint)
+        )
+    ));
+    let acme_home = resolve_path(
+        acme_home,
+        env_overrides,
+        "ACME_HOME",
+        "/root/.acme.sh",
+        "acme home directory",
+    )?;
+    let mut certs = Vec::new();
+    for (domain, dir) in list_acme_domains(&acme_home)? {
+        let label = if dir.to_string_lossy().ends_with("_ecc") {
+            format!("{} (ECC)", domain)
+        } else {
+            domain
+        };
+        certs.push((label, cert_status(runner, &dir.join("fullchain.cer"))));
+    }
+    if certs.is_empty() {
+        info(&format!("No certificates found in {}", acme_home.display()));
+        return Ok(());
+    }
+    certs.sort_by_key(|(_, status)| status.as_ref().map_or(i64::MAX, |status| status.days_left));
+
+    let rows: Vec<Vec<String>> = certs
+        .iter()
+        .map(|(label, status)| match status {
+            Some(status) => vec![
+                label.clone(),
+                status.issued.clone(),
+                status.expires.clone(),
+                status.days_left.to_string(),
+                status.issuer.clone(),
+            ],
+            None => vec![
+                label.clone(),
+                "-".into(),
+                "-".into(),
+                "-".into(),
+                "-".into(),
+            ],
+        })
+        .collect();
+    print_table(&["Domain", "Issued", "Expires", "Days left", "CA"], &rows);
+    for (label, status) in &certs {
+        match status {
+            Some(status) if status.days_left < 0 => {
+                warn(&format!("{} expired {} days ago", label, -status.days_left))
+            }
+            Some(status) if status.days_left <= warn_days as i64 => {
+                warn(&format!("{} expires in {} days", label, status.days_left))
+            }
+            None => warn(&format!(
+                "{}: fullchain.cer is missing or unreadable",
+                label
+            )),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn prune_certs(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    acme_home: Option<PathBuf>,
+    keep: &[String],
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Pruning acme.sh cert cache");
+    ensure_root(runner)?;
+    let acme_home = resolve_path(
+        acme_home,
+        env_overrides,
+        "ACME_HOME",
+        "/root/.acme.sh",
+        "acme home directory",
+    )?;
+    let acme_home = acme_home
+        .canonicalize()
+        .map_err(|e| AppError::Io(format!("Failed to resolve {}: {e}", acme_home.display())))?;
+
+    let mut stale = Vec::new();
+    for (domain, dir) in list_acme_domains(&acme_home)? {
+        if keep.iter().any(|k| k.eq_ignore_ascii_case(&domain)) {
+            info(&format!("Keeping {}", domain));
+        } else {
+            info(&format!("Stale: {} ({})", domain, dir.display()));
+            stale.push(dir);
+        }
+    }
+    if stale.is_empty() {
+        info("No stale cert cache directories");
+        return Ok(());
+    }
+
+    if dry_run {
+        for dir in &stale {
+            record_change(dir.display().to_string());
+            info(&format!("[dry-run] Would remove: {}", dir.display()));
+        }
+        return Ok(());
+    }
+    let prompt = format!("Remove {} stale cache directories?", stale.len());
+    if !confirm_with_timeout(&prompt, DEFAULT_CONFIRM_TIMEOUT, dry_run)? {
+        info("Prune skipped");
+        return Ok(());
+    }
+    for dir in stale {
+        let dir = dir
+            .canonicalize()
+            .map_err(|e| AppError::Io(format!("Failed to resolve {}: {e}", dir.display())))?;
+        if dir == acme_home || !dir.starts_with(&acme_home) {
+            return Err(AppError::Validation(format!(
+                "Refusing to remove {} outside {}",
+                dir.display(),
+                acme_home.display()
+            )));
+        }
+        fs::remove_dir_all(&dir)
+            .map_err(|e| AppError::Io(format!("Failed to remove {}: {e}", dir.display())))?;
+        record_change(dir.display().to_string());
+        success(&format!("Removed {}", dir.display()));
+    }
+    Ok(())
+}
+
+pub fn export_account(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    acme_home: Option<PathBuf>,
+    acme_config_home: Option<PathBuf>,
+    output: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Exporting acme.sh account");
+    ensure_root(runner)?;
+    let ca_dir = acme_ca_dir(env_overrides, acme_home, acme_config_home)?;
+    copy_account_files(&ca_dir, output, force, dry_run)?;
+    warn(&format!(
+        "{} holds the ACME account private key; store it like any other secret",
+        output.display()
+    ));
+    Ok(())
+}
+
+pub fn import_account(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    acme_home: Option<PathBuf>,
+    acme_config_home: Option<PathBuf>,
+    input: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Importing acme.sh account");
+    ensure_root(runner)?;
+    let ca_dir = acme_ca_dir(env_overrides, acme_home, acme_config_home)?;
+    copy_account_files(input, &ca_dir, force, dry_run)?;
+    info("acme.sh reuses the imported account for the matching CA on the next issue");
+    Ok(())
+}
+
+// .toml files are --config files: only top-level keys are renamed, since the
+// [resolvers]/[proxy_domains] tables have their own names.
+pub fn migrate_env_file(path: &Path, dry_run: bool) -> Result<(), AppError> {
+    step(&format!("Migrating {}", path.display()));
+    let original = fs::read_to_string(path)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+    let toml = path.extension().is_some_and(|ext| ext == "toml");
+    let line_key = |line: &str, in_table: bool| -> Option<String> {
+        if toml {
+            toml_line_key(line, in_table)
+        } else {
+            env_line_key(line).map(str::to_string)
+        }
+    };
+    let schema_line = if toml {
+        format!("SCHEMA_VERSION = {}", ENV_SCHEMA_VERSION)
+    } else {
+        format!("SCHEMA_VERSION={}", ENV_SCHEMA_VERSION)
+    };
+
+    let mut keys = Vec::new();
+    let mut in_table = false;
+    for line in original.lines() {
+        in_table |= toml && line.trim_start().starts_with('[');
+        keys.push(line_key(line, in_table));
+    }
+    let mut lines: Vec<String> = Vec::new();
+    let mut has_schema = false;
+    for (line, key) in original.lines().zip(&keys) {
+        let Some(key) = key else {
+            lines.push(line.to_string());
+            continue;
+        };
+        if key == "SCHEMA_VERSION" {
+            has_schema = true;
+            lines.push(schema_line.clone());
+            continue;
+        }
+        match RENAMED_ENV_KEYS.iter().find(|(old, _)| old == key) {
+            Some((_, new)) if keys.iter().flatten().any(|k| k == new) => {}
+            Some((old, new)) => lines.push(line.replacen(old, new, 1)),
+            None => lines.push(line.to_string()),
+        }
+    }
+    if !has_schema {
+        lines.insert(0, schema_line);
+    }
+    let mut migrated = lines.join("\n");
+    migrated.push('\n');
+
+    let old_lines: Vec<&str> = original.lines().collect();
+    let new_lines: Vec<&str> = migrated.lines().collect();
+    let diff = line_diff(&old_lines, &new_lines);
+    if diff.is_empty() {
+        info("Already up to date");
+        return Ok(());
+    }
+    for line in &diff {
+        info(line);
+    }
+
+    let backup = backup_path(path);
+    record_change(backup.display().to_string());
+    record_change(path.display().to_string());
+    if dry_run {
+        info(&format!("[dry-run] Would back up to: {}", backup.display()));
+        info(&format!("[dry-run] Would write: {}", path.display()));
+        return Ok(());
+    }
+    fs::copy(path, &backup)
+        .map_err(|e| AppError::Io(format!("Failed to back up {}: {e}", path.display())))?;
+    write_atomic(path, migrated)
+        .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", path.display())))?;
+    success(&format!("Migrated (backup: {})", backup.display()));
+    Ok(())
+}
+
+fn toml_line_key(line: &str, in_table: bool) -> Option<String> {
+    let trimmed = line.trim_start();
+    if in_table || trimmed.starts_with('#') || trimmed.starts_with('[') {
+        return None;
+    }
+    let (key, _) = trimmed.split_once('=')?;
+    let key = key.trim().trim_matches('"');
+    if key.is_empty() {
+        None
+    } else {
+        Some(key.to_string())
+    }
+}
+
+// Changed lines in file order, from a longest-common-subsequence walk, so
+// repeated or moved lines are reported where they actually changed.
+fn line_diff(old: &[&str], new: &[&str]) -> Vec<String> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            diff.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+pub fn print_params_table() -> Result<(), AppError> {
+    step("Supported parameters");
+    let rows = vec![
+        (
+            "--env KEY=VALUE",
+            "Override environment values (repeatable)",
+        ),
+        (
+            "--config",
+            "TOML settings file, - for stdin ([resolvers] adds presets)",
+        ),
+        (
+            "--env-file",
+            "KEY=VALUE env file (repeatable, later files win)",
+        ),
+        (
+            "--env-file-override",
+            "Process env wins over --env-file values",
+        ),
+        (
+            "--summary-json",
+            "Write a JSON run summary (secrets masked)",
+        ),
+        ("--verbose", "Log extra detail, including per-step timing"),
+        (
+            "--trace",
+            "Write a JSONL trace of params, commands and file operations",
+        ),
+        ("--strict-env", "Reject unknown --env and config file keys"),
+        ("--working-dir", "Anchor relative paths to this directory"),
+        (
+            "--reload-timeout",
+            "Seconds before nginx -t / reload is killed (default: 60)",
+        ),
+        (
+            "--detect-drift",
+            "With --dry-run, exit 9 if any change is planned",
+        ),
+        (
+            "--no-clobber-system",
+            "Refuse to overwrite files without the managed header",
+        ),
+        ("--fail-on-warn", "Exit 10 if any warning was printed"),
+        (
+            "--fail-fast",
+            "With --fail-on-warn, exit at the first warning",
+        ),
+        (
+            "setup",
+            "Install zsh/cron/nginx if missing (checklist when no flags)",
+        ),
+        ("--install-zsh", "Install zsh if missing"),
+        ("--install-cron", "Install cron if missing"),
+        ("--install-nginx", "Install nginx if missing"),
+        ("--mirror-nginx", "nginx.org mirror for signing keys"),
+        ("MIRROR_NGINX", "nginx.org mirror (env)"),
+        ("--mirror-apt", "nginx apt/apk package repo mirror"),
+        ("MIRROR_APT", "nginx package repo mirror (env)"),
+        (
+            "--download-retries",
+            "Retry signing key downloads with backoff (default: 0)",
+        ),
+        (
+            "--nginx-key-sha256",
+            "Override the built-in nginx_signing.rsa.pub digest (Alpine)",
+        ),
+        (
+            "NGINX_SIGNING_RSA_SHA256",
+            "Override nginx apk key SHA-256 (env)",
+        ),
+        ("--dry-run", "Simulate actions without changes"),
+        ("issue-cert", "Issue certs and optionally reload nginx"),
+        ("--cf-token", "Cloudflare token"),
+        ("CF_TOKEN", "Cloudflare token (env)"),
+        ("--cf-account-id", "Cloudflare account ID"),
+        ("CF_ACCOUNT_ID", "Cloudflare account ID (env)"),
+        ("--cf-zone-id", "Cloudflare zone ID"),
+        ("CF_ZONE_ID", "Cloudflare zone ID (env)"),
+        ("--domain", "Primary domain"),
+        ("DOMAIN", "Primary domain (env)"),
+        ("--wildcard-domain", "Wildcard domain"),
+        ("WILDCARD_DOMAIN", "Wildcard domain (env)"),
+        ("--acme-bin", "acme.sh path"),
+        ("ACME_BIN", "acme.sh path (env)"),
+        ("--acme-home", "acme home directory"),
+        ("ACME_HOME", "acme home directory (env)"),
+        ("--cert-dir", "Certificate directory (absolute path)"),
+        ("CERT_DIR", "Certificate directory (env)"),
+        ("--cert-dir-name", "Certificate directory name"),
+        ("CERT_DIR_NAME", "Certificate directory name (env)"),
+        ("--cert-base-dir", "Base dir for --cert-dir-name"),
+        (
+            "--cert-name-scheme",
+            "Cert/key file names: domain (default), certbot or custom",
+        ),
+        ("CERT_NAME_SCHEME", "Cert/key file name scheme (env)"),
+        (
+            "--cert-filename",
+            "Cert file name for --cert-name-scheme custom",
+        ),
+        (
+            "--key-filename",
+            "Key file name for --cert-name-scheme custom",
+        ),
+        ("CERT_BASE_DIR", "Cert base dir (env)"),
+        ("--cert-input-path", "Certificate input path"),
+        ("CERT_INPUT_PATH", "Certificate input path (env)"),
+        ("--key-input-path", "Key input path"),
+        ("KEY_INPUT_PATH", "Key input path (env)"),
+        ("--cert-output-path", "Certificate output path"),
+        ("CERT_OUTPUT_PATH", "Certificate output path (env)"),
+        ("--key-output-path", "Key output path"),
+        ("KEY_OUTPUT_PATH", "Key output path (env)"),
+        ("--nginx-bin", "nginx binary"),
+        ("NGINX_BIN", "nginx binary (env)"),
+        ("--reload-nginx", "Reload nginx after issuance"),
+        (
+            "--confirm-reload",
+            "Ask before reloading nginx (default: no)",
+        ),
+        ("--yes", "Skip the --confirm-reload prompt"),
+        ("--offline", "Only install cert/key from input paths"),
+        (
+            "--verify-cf-token",
+            "Check token status and DNS edit rights before issuing",
+        ),
+        (
+            "--wait-for-propagation",
+            "Wait for the TXT challenge to propagate before validation",
+        ),
+        (
+            "--propagation-timeout",
+            "Propagation wait timeout in seconds",
+        ),
+        (
+            "--dns-sleep",
+            "Seconds acme.sh waits for DNS propagation (--dnssleep)",
+        ),
+        (
+            "--cron-deploy",
+            "Add a cron job that copies renewed certs and reloads nginx",
+        ),
+        ("--no-cron", "Do not install the acme.sh renew cron job"),
+        (
+            "--cf-purge-cache",
+            "Purge the zone's Cloudflare cache after install/reload",
+        ),
+        (
+            "--validate-all",
+            "Check every input up front and report all problems",
+        ),
+        (
+            "--dual-cert",
+            "Issue both ECDSA and RSA certs (<name>.ec.cer / <name>.rsa.cer)",
+        ),
+        ("--acme-config-home", "acme.sh config home (--config-home)"),
+        ("ACME_CONFIG_HOME", "acme.sh config home (env)"),
+        ("--acme-cert-home", "acme.sh cert home (--cert-home)"),
+        ("ACME_CERT_HOME", "acme.sh cert home (env)"),
+        (
+            "--propagation-check",
+            "resolvers (dig, default) or cloudflare (CF API via curl)",
+        ),
+        (
+            "--wildcard-only",
+            "Issue only the wildcard domain (no apex)",
+        ),
+        (
+            "--challenge-alias",
+            "Delegate _acme-challenge via CNAME to this hostname",
+        ),
+        ("CHALLENGE_ALIAS", "Challenge alias hostname (env)"),
+        (
+            "--domain-alias",
+            "Validate via this hostname (acme.sh domain alias)",
+        ),
+        ("DOMAIN_ALIAS", "Domain alias hostname (env)"),
+        (
+            "--renew-threshold-days",
+            "Skip issuance unless the cert expires within N days",
+        ),
+        (
+            "--force-reissue",
+            "Issue even if --renew-threshold-days says skip",
+        ),
+        ("--cert-owner", "chown cert/key to user[:group]"),
+        ("CERT_OWNER", "Cert/key owner (env)"),
+        ("--cert-owner-dir", "Also chown the cert directory"),
+        (
+            "--skip-cache-removal",
+            "Reuse the acme.sh cache dir and skip --force",
+        ),
+        (
+            "--preferred-chain",
+            "Preferred CA chain, e.g. \"ISRG Root X1\"",
+        ),
+        ("PREFERRED_CHAIN", "Preferred CA chain (env)"),
+        (
+            "--must-staple",
+            "Request the OCSP Must-Staple extension (--ocsp-must-staple)",
+        ),
+        (
+            "--acme-arg",
+            "Extra acme.sh --issue argument, verbatim (repeatable)",
+        ),
+        (
+            "--purge-on-fail",
+            "Remove the acme.sh cache dir if issuance fails",
+        ),
+        ("--dry-run", "Simulate actions without changes"),
+        ("write-nginx-default", "Write default nginx 444 config"),
+        ("--cert-path", "Nginx cert path (absolute)"),
+        ("NGINX_CERT_PATH", "Nginx cert path (env)"),
+        ("--key-path", "Nginx key path (absolute)"),
+        ("NGINX_KEY_PATH", "Nginx key path (env)"),
+        ("--cert-dir-name", "Certificate directory name"),
+        ("NGINX_CERT_DIR_NAME", "Certificate dir name (env)"),
+        ("--cert-base-dir", "Base dir for --cert-dir-name"),
+        (
+            "--cert-name-scheme",
+            "Cert/key file names: domain (default), certbot or custom",
+        ),
+        ("CERT_NAME_SCHEME", "Cert/key file name scheme (env)"),
+        (
+            "--cert-filename",
+            "Cert file name for --cert-name-scheme custom",
+        ),
+        (
+            "--key-filename",
+            "Key file name for --cert-name-scheme custom",
+        ),
+        ("CERT_BASE_DIR", "Cert base dir (env)"),
+        ("--domain", "Primary domain (used for default cert/key)"),
+        ("DOMAIN", "Primary domain (env)"),
+        ("--output-path", "Output path for default config"),
+        (
+            "NGINX_DEFAULT_OUTPUT",
+            "Output path for default config (env)",
+        ),
+        (
+            "--template",
+            "External template file (NGINX_DEFAULT_TEMPLATE)",
+        ),
+        (
+            "--set KEY=VALUE",
+            "Fill {{KEY}} in the template (repeatable)",
+        ),
+        (
+            "--server-tokens",
+            "nginx server_tokens: on or off (default: off)",
+        ),
+        (
+            "--min-tls-version",
+            "ssl_protocols floor: 1.2 (TLSv1.2 TLSv1.3, default) or 1.3",
+        ),
+        ("MIN_TLS_VERSION", "Minimum TLS version (env)"),
+        (
+            "--backup",
+            "Copy the existing config to <path>.bak.<ts> first",
+        ),
+        ("--keep-backups", "With --backup, keep only the newest N"),
+        ("--stub-status", "Expose nginx stub_status at this path"),
+        ("STUB_STATUS", "stub_status path (env)"),
+        (
+            "--stub-status-allow",
+            "IPs/CIDRs allowed to read stub_status (default: loopback)",
+        ),
+        ("STUB_STATUS_ALLOW", "stub_status allow list (env)"),
+        (
+            "--watch",
+            "Re-render on template changes (requires --template)",
+        ),
+        (
+            "--watch-interval",
+            "Template polling interval in seconds (default: 2)",
+        ),
+        ("--test-config", "Run nginx -t after each write"),
+        (
+            "--post-write-cmd",
+            "Shell command run after each write ($CONFIG_FILE)",
+        ),
+        ("POST_WRITE_CMD", "Post-write command (env)"),
+        (
+            "--output-owner",
+            "Chown written config and new dirs (user[:group])",
+        ),
+        ("OUTPUT_OWNER", "Output owner (env)"),
+        (
+            "--inline-certs",
+            "Embed cert/key PEM via ssl_certificate data: (nginx)",
+        ),
+        ("--ipv6", "Also listen on [::] (default: detect)"),
+        ("--ipv6only", "Set ipv6only=on|off on [::] listens"),
+        ("--dual-cert", "Reference both ECDSA and RSA cert/key pairs"),
+        (
+            "--bind",
+            "Listen address (IPv4, IPv6 or [::]); default all interfaces",
+        ),
+        ("NGINX_DEFAULT_BIND", "Listen address (env)"),
+        (
+            "--default-action",
+            "close (444), redirect <url> or status <code>",
+        ),
+        ("NGINX_DEFAULT_ACTION", "Default server action (env)"),
+        ("--hsts", "Emit a Strict-Transport-Security header"),
+        (
+            "--hsts-max-age",
+            "HSTS max-age in seconds (default: 31536000)",
+        ),
+        ("--hsts-include-subdomains", "Add includeSubDomains to HSTS"),
+        (
+            "--hsts-preload",
+            "Add preload (needs includeSubDomains, max-age >= 1y)",
+        ),
+        ("--dry-run", "Simulate actions without changes"),
+        ("write-proxy-config", "Write reverse proxy config"),
+        (
+            "--proxy-domain",
+            "Proxy domain (repeatable, one config each)",
+        ),
+        ("PROXY_DOMAIN", "Proxy domain (env)"),
+        ("--backend-url", "Backend URL"),
+        ("BACKEND_URL", "Backend URL (env)"),
+        ("--resolver", "DNS resolver (repeatable)"),
+        ("RESOLVER", "DNS resolver list (env or interactive)"),
+        ("--cert-path", "Nginx cert path (absolute)"),
+        ("NGINX_CERT_PATH", "Nginx cert path (env)"),
+        ("--key-path", "Nginx key path (absolute)"),
+        ("NGINX_KEY_PATH", "Nginx key path (env)"),
+        ("--cert-dir", "Certificate directory (absolute path)"),
+        ("CERT_DIR", "Certificate directory (env)"),
+        ("--cert-dir-name", "Certificate directory name"),
+        ("CERT_DIR_NAME", "Certificate directory name (env)"),
+        ("--cert-base-dir", "Base dir for --cert-dir-name"),
+        (
+            "--cert-name-scheme",
+            "Cert/key file names: domain (default), certbot or custom",
+        ),
+        ("CERT_NAME_SCHEME", "Cert/key file name scheme (env)"),
+        (
+            "--cert-filename",
+            "Cert file name for --cert-name-scheme custom",
+        ),
+        (
+            "--key-filename",
+            "Key file name for --cert-name-scheme custom",
+        ),
+        ("CERT_BASE_DIR", "Cert base dir (env)"),
+        (
+            "--derive-domain-from-proxy",
+            "Name derived cert files after the proxy domain's apex (default: true)",
+        ),
+        ("--output-dir", "Proxy config output dir"),
+        ("PROXY_OUTPUT_DIR", "Proxy config output dir (env)"),
+        ("--force", "Write even if config is unchanged"),
+        ("--host-header", "Host header sent to backend"),
+        ("HOST_HEADER", "Host header sent to backend (env)"),
+        ("--no-backend-sni", "Disable SNI for HTTPS backends"),
+        ("--backend-ssl-verify", "Verify backend cert (on/off)"),
+        ("--routes-file", "Extra location rules file"),
+        ("ROUTES_FILE", "Extra location rules file (env)"),
+        ("--emby-preset", "Add built-in Emby location rules"),
+        ("--template", "External template file (PROXY_TEMPLATE)"),
+        (
+            "--set KEY=VALUE",
+            "Fill {{KEY}} in the template (repeatable)",
+        ),
+        (
+            "--server-tokens",
+            "nginx server_tokens: on or off (default: off)",
+        ),
+        (
+            "--min-tls-version",
+            "ssl_protocols floor: 1.2 (TLSv1.2 TLSv1.3, default) or 1.3",
+        ),
+        ("MIN_TLS_VERSION", "Minimum TLS version (env)"),
+        (
+            "--backup",
+            "Copy the existing config to <path>.bak.<ts> first",
+        ),
+        ("--keep-backups", "With --backup, keep only the newest N"),
+        (
+            "--watch",
+            "Re-render on template changes (requires --template)",
+        ),
+        (
+            "--watch-interval",
+            "Template polling interval in seconds (default: 2)",
+        ),
+        ("--test-config", "Run nginx -t after each write"),
+        (
+            "--post-write-cmd",
+            "Shell command run after each write ($CONFIG_FILE)",
+        ),
+        ("POST_WRITE_CMD", "Post-write command (env)"),
+        (
+            "--output-owner",
+            "Chown written config and new dirs (user[:group])",
+        ),
+        ("OUTPUT_OWNER", "Output owner (env)"),
+        (
+            "--inline-certs",
+            "Embed cert/key PEM via ssl_certificate data: (nginx)",
+        ),
+        (
+            "--passthrough",
+            "Write an nginx stream SNI passthrough config (no certs)",
+        ),
+        ("STREAM_OUTPUT_DIR", "Passthrough config output dir (env)"),
+        (
+            "--stub-status",
+            "Expose nginx stub_status at this path (nginx)",
+        ),
+        ("STUB_STATUS", "stub_status path (env)"),
+        (
+            "--stub-status-allow",
+            "IPs/CIDRs allowed to read stub_status (default: loopback)",
+        ),
+        ("STUB_STATUS_ALLOW", "stub_status allow list (env)"),
+        (
+            "--rewrite",
+            "\"<pattern> <replacement>\" rewrite in location / (repeatable)",
+        ),
+        (
+            "--proxy-redirect",
+            "\"<from> <to>\" (repeatable), off, or default (no directive)",
+        ),
+        (
+            "--forward-header",
+            "NAME=VALUE proxy_set_header to add or override (repeatable)",
+        ),
+        (
+            "--no-forward-header",
+            "Drop a default forwarded header by name (repeatable)",
+        ),
+        ("--ipv6", "Also listen on [::] (default: detect)"),
+        ("--ipv6only", "Set ipv6only=on|off on [::] listens"),
+        ("--dual-cert", "Reference both ECDSA and RSA cert/key pairs"),
+        ("--hsts", "Emit a Strict-Transport-Security header"),
+        (
+            "--hsts-max-age",
+            "HSTS max-age in seconds (default: 31536000)",
+        ),
+        ("--hsts-include-subdomains", "Add includeSubDomains to HSTS"),
+        (
+            "--hsts-preload",
+            "Add preload (needs includeSubDomains, max-age >= 1y)",
+        ),
+        ("--backends-file", "Backend list for an upstream block"),
+        (
+            "--strip-prefix",
+            "Remove this path prefix before proxying (nginx)",
+        ),
+        ("STRIP_PREFIX", "Path prefix to strip (env)"),
+        (
+            "--geo-allow",
+            "Allowed country code, others get 403 (repeatable)",
+        ),
+        ("GEO_ALLOW", "Allowed country codes, comma-separated (env)"),
+        ("--geoip-db", "GeoIP2 country database for --geo-allow"),
+        ("GEOIP_DB", "GeoIP2 country database (env)"),
+        (
+            "BACKEND_URL_2",
+            "Extra backends BACKEND_URL_2, _3, ... (env)",
+        ),
+        (
+            "--active-backend",
+            "Use BACKEND_BLUE or BACKEND_GREEN (blue|green)",
+        ),
+        ("ACTIVE_BACKEND", "Active backend color (env)"),
+        ("BACKEND_BLUE", "Blue backend URL (env)"),
+        ("BACKEND_GREEN", "Green backend URL (env)"),
+        ("--server", "nginx (default), caddy or apache"),
+        (
+            "--server apache",
+            "VirtualHost in /etc/apache2/sites-available (mod_ssl/proxy/headers)",
+        ),
+        (
+            "  --backend-ssl-verify",
+            "apache: SSLProxyVerify require|none",
+        ),
+        (
+            "  --host-header",
+            "apache: ProxyPreserveHost / RequestHeader Host",
+        ),
+        (
+            "  --backends-file",
+            "apache: balancer:// with loadfactor/+H/+D",
+        ),
+        ("  --test-config", "apache: run apachectl configtest"),
+        ("--dry-run", "Simulate actions without changes"),
+        (
+            "reload-upstream",
+            "Point an existing nginx vhost at a new backend and reload",
+        ),
+        ("--proxy-domain", "Proxy domain whose config is edited"),
+        ("PROXY_DOMAIN", "Proxy domain (env)"),
+        ("--backend-url", "New backend (scheme://host[:port])"),
+        ("BACKEND_URL", "New backend URL (env)"),
+        ("--output-dir", "Proxy config output dir"),
+        ("PROXY_OUTPUT_DIR", "Proxy config output dir (env)"),
+        ("--nginx-bin", "nginx binary"),
+        ("NGINX_BIN", "nginx binary (env)"),
+        ("--dry-run", "Simulate actions without changes"),
+        ("check", "Run nginx -t and lint managed configs"),
+        ("--nginx-bin", "nginx binary"),
+        ("NGINX_BIN", "nginx binary (env)"),
+        ("--output-dir", "Proxy config output dir"),
+        ("PROXY_OUTPUT_DIR", "Proxy config output dir (env)"),
+        ("--lint", "Check configs for placeholders and missing certs"),
+        (
+            "--since",
+            "With --lint, only configs modified within e.g. 7d (s/m/h/d)",
+        ),
+        (
+            "list-presets",
+            "List built-in resolver and Emby route presets",
+        ),
+        ("--format", "Output format: text or json (default: text)"),
+        (
+            "show-template",
+            "Print a built-in template (proxy|default|caddy|apache|stream)",
+        ),
+        ("test-backend", "Check that the backend URL is reachable"),
+        ("--backend-url", "Backend URL"),
+        ("BACKEND_URL", "Backend URL (env)"),
+        ("--timeout", "Request timeout in seconds (default: 10)"),
+        (
+            "probe-dns",
+            "Send an A query to each resolver and report latency",
+        ),
+        (
+            "--resolver",
+            "Resolver addresses or preset name (default: all presets)",
+        ),
+        ("RESOLVER", "Resolvers to probe (env)"),
+        ("--name", "Name to query (default: cloudflare.com)"),
+        ("--timeout", "Per-address timeout in seconds (default: 2)"),
+        (
+            "verify-cert-domain",
+            "Check that a certificate's SANs cover a domain",
+        ),
+        ("--cert-path", "Certificate file to inspect"),
+        ("CERT_OUTPUT_PATH", "Certificate file to inspect (env)"),
+        ("--domain", "Domain to check (wildcards match one label)"),
+        ("DOMAIN", "Domain to check (env)"),
+        ("combine-pem", "Write fullchain + key into one PEM (0600)"),
+        ("--cert-path", "Certificate (fullchain) path"),
+        ("CERT_OUTPUT_PATH", "Certificate path (env)"),
+        ("--key-path", "Private key path"),
+        ("KEY_OUTPUT_PATH", "Private key path (env)"),
+        ("--output", "Combined PEM path"),
+        ("--order", "cert-first (default) or key-first"),
+        ("--dry-run", "Simulate actions without changes"),
+        ("rollback", "Restore the most recent cert/key backup"),
+        ("--domain", "Primary domain (used for default cert/key)"),
+        ("--cert-dir", "Certificate directory"),
+        ("--cert-dir-name", "Certificate directory name"),
+        ("--cert-base-dir", "Base dir for --cert-dir-name"),
+        (
+            "--cert-name-scheme",
+            "Cert/key file names: domain (default), certbot or custom",
+        ),
+        ("CERT_NAME_SCHEME", "Cert/key file name scheme (env)"),
+        (
+            "--cert-filename",
+            "Cert file name for --cert-name-scheme custom",
+        ),
+        (
+            "--key-filename",
+            "Key file name for --cert-name-scheme custom",
+        ),
+        ("--cert-output-path", "Live certificate path"),
+        ("--key-output-path", "Live key path"),
+        ("--nginx-bin", "nginx binary"),
+        (
+            "--confirm-reload",
+            "Ask before reloading nginx (default: no)",
+        ),
+        ("--yes", "Skip the --confirm-reload prompt"),
+        (
+            "--to-backup",
+            "Backup timestamp to restore (default latest)",
+        ),
+        ("--list", "Only list available backups"),
+        ("--dry-run", "Simulate actions without changes"),
+        ("restore-config", "Restore a config backup made by --backup"),
+        ("--file", "Live config path whose backups to use"),
+        (
+            "--to-backup",
+            "Backup timestamp to restore (default latest)",
+        ),
+        ("--list", "Only list available backups"),
+        ("--nginx-bin", "nginx binary"),
+        ("--no-reload", "Skip nginx -t and reload after restoring"),
+        ("--dry-run", "Simulate actions without changes"),
+        (
+            "check-renewal",
+            "Exit non-zero unless every installed cert is healthy",
+        ),
+        ("--domain", "Domain to check (repeatable)"),
+        ("DOMAIN", "Domains to check, comma separated (env)"),
+        (
+            "--cert-dir",
+            "Directory holding the cert and key (once or per domain)",
+        ),
+        (
+            "--cert-dir-name",
+            "Cert dir name under the base dir (default custom, once or per domain)",
+        ),
+        ("--cert-base-dir", "Cert base dir"),
+        (
+            "--threshold-days",
+            "Fail certs expiring within N days (default: 14)",
+        ),
+        (
+            "--cert-name-scheme",
+            "Cert/key file names: domain|certbot|custom",
+        ),
+        ("CERT_NAME_SCHEME", "Cert/key file name scheme (env)"),
+        ("--cert-filename", "Cert file name for the custom scheme"),
+        ("--key-filename", "Key file name for the custom scheme"),
+        ("--dual-cert", "Check both the .ec and .rsa cert pairs"),
+        ("status", "Show acme.sh cert expiry, soonest first"),
+        ("--acme-home", "acme home directory"),
+        ("ACME_HOME", "acme home directory (env)"),
+        (
+            "--warn-days",
+            "Warn about certs expiring within N days (default: 30)",
+        ),
+        ("prune-certs", "Remove stale acme.sh cert cache dirs"),
+        ("--acme-home", "acme home directory"),
+        ("ACME_HOME", "acme home directory (env)"),
+        ("--keep", "Domain to keep (repeatable)"),
+        ("--dry-run", "Simulate actions without changes"),
+        (
+            "export-account",
+            "Copy the acme.sh account key/registration out",
+        ),
+        ("--acme-home", "acme home directory"),
+        ("--acme-config-home", "acme.sh config home (holds ca/)"),
+        ("--output", "Directory to export the account to"),
+        ("--force", "Overwrite existing account files"),
+        ("--dry-run", "Simulate actions without changes"),
+        ("import-account", "Install an exported acme.sh account"),
+        ("--acme-home", "acme home directory"),
+        ("--acme-config-home", "acme.sh config home (holds ca/)"),
+        ("--input", "Directory produced by export-account"),
+        ("--force", "Overwrite existing account files"),
+        ("--dry-run", "Simulate actions without changes"),
+        (
+            "migrate",
+            "Upgrade an env or .toml config file to the current schema",
+        ),
+        (
+            "--file",
+            "Env file or .toml config to migrate (default .env)",
+        ),
+        ("--dry-run", "Simulate actions without changes"),
+    ];
+
+    let rows: Vec<Vec<String>> = rows
+        .into_iter()
+        .map(|(name, desc)| vec![name.to_string(), desc.to_string()])
+        .collect();
+    print_table(&["Parameter/ENV", "Description"], &rows);
+    Ok(())
+}
+
+fn resolve_active_backend(
+    env_overrides: &HashMap<String, String>,
+    color: &str,
+) -> Result<String, AppError> {
+    let (env_key, label) = match color.to_ascii_lowercase().as_str() {
+        "blue" => ("BACKEND_BLUE", "Blue backend URL"),
+        "green" => ("BACKEND_GREEN", "Green backend URL"),
+        _ => {
+            return Err(AppError::Validation(format!(
+                "--active-backend must be blue or green, got {}",
+                color
+            )));
+        }
+    };
+    let backend_url = resolve_value(None, env_overrides, env_key, label, false)?;
+    info(&format!("Active backend: {} ({})", color, backend_url));
+    Ok(backend_url)
+}
+
+fn render_backend_tls(
+    backend_url: &str,
+    no_sni: bool,
+    ssl_verify: Option<&str>,
+) -> Result<String, AppError> {
+    let mut lines = Vec::new();
+    if no_sni {
+        lines.push("proxy_ssl_server_name off;".to_string());
+    } else {
+        lines.push("proxy_ssl_server_name on;".to_string());
+        if backend_url.starts_with("https://") {
+            let host = backend_host(backend_url).ok_or(AppError::Validation(format!(
+                "Failed to parse backend host from {}",
+                backend_url
+            )))?;
+            lines.push(format!("proxy_ssl_name {};", host));
+        }
+    }
+    if let Some(verify) = ssl_verify {
+        lines.push(format!("proxy_ssl_verify {};", verify));
+        if verify == "on" {
+            lines.push(format!(
+                "proxy_ssl_trusted_certificate {};",
+                SYSTEM_CA_BUNDLE
+            ));
+        }
+    }
+    Ok(lines.join("\n        "))
+}
+
+fn render_caddy_transport(
+    backend_url: &str,
+    no_sni: bool,
+    ssl_verify: Option<&str>,
+    resolver: &str,
+) -> Result<String, AppError> {
+    let resolvers = resolver
+        .split_whitespace()
+        .map(|addr| addr.trim_start_matches('[').trim_end_matches(']'))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut lines = vec![format!("resolvers {}", resolvers)];
+    if backend_url.starts_with("https://") {
+        if !no_sni {
+            let host = backend_host(backend_url).ok_or(AppError::Validation(format!(
+                "Failed to parse backend host from {}",
+                backend_url
+            )))?;
+            lines.push(format!("tls_server_name {}", host));
+        }
+        // Caddy verifies upstream certs by default; only skip on an explicit off.
+        if ssl_verify == Some("off") {
+            lines.push("tls_insecure_skip_verify".to_string());
+        }
+    }
+    Ok(format!(
+        "\t\ttransport http {{\n{}\t\t}}\n",
+        lines
+            .iter()
+            .map(|line| format!("\t\t\t{}\n", line))
+            .collect::<String>()
+    ))
+}
+
+fn render_apache_certificates(variants: &[CertVariant]) -> String {
+    variants
+        .iter()
+        .map(|variant| {
+            format!(
+                "    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n",
+                variant.cert.display(),
+                variant.key.display()
+            )
+        })
+        .collect()
+}
+
+fn render_apache_backend_tls(backend_url: &str, ssl_verify: Option<&str>) -> String {
+    if !backend_url.starts_with("https://") {
+        return String::new();
+    }
+    let mut lines = vec!["SSLProxyEngine on".to_string()];
+    if ssl_verify == Some("on") {
+        lines.push("SSLProxyVerify require".to_string());
+        lines.push(format!("SSLProxyCACertificateFile {}", SYSTEM_CA_BUNDLE));
+    } else {
+        lines.push("SSLProxyVerify none".to_string());
+        lines.push("SSLProxyCheckPeerName off".to_string());
+        lines.push("SSLProxyCheckPeerExpire off".to_string());
+    }
+    lines.iter().map(|line| format!("    {}\n", line)).collect()
+}
+
+fn apache_host_header(host_header: &str) -> String {
+    match host_header {
+        DEFAULT_HOST_HEADER => "    ProxyPreserveHost Off".to_string(),
+        "$host" | "$http_host" => "    ProxyPreserveHost On".to_string(),
+        other => format!(
+            "    ProxyPreserveHost On\n    RequestHeader set Host \"{}\"",
+            other
+        ),
+    }
+}
+
+fn caddy_host_header(host_header: &str) -> String {
+    match host_header {
+        DEFAULT_HOST_HEADER => "{upstream_hostport}".to_string(),
+        "$host" | "$http_host" => "{host}".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn split_backend_path(backend_url: &str) -> Result<(String, Option<String>), AppError> {
+    let Some((scheme, rest)) = backend_url.split_once("://") else {
+        return Ok((backend_url.to_string(), None));
+    };
+    if rest.contains(['?', '#']) {
+        return Err(AppError::Validation(format!(
+            "Backend URL {} must not contain a query or fragment",
+            backend_url
+        )));
+    }
+    let Some((authority, path)) = rest.split_once('/') else {
+        return Ok((backend_url.to_string(), None));
+    };
+    let origin = format!("{}://{}", scheme, authority);
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Ok((origin, None));
+    }
+    Ok((
+        origin,
+        Some(parse_uri_prefix("--backend-url", &format!("/{}", path))?),
+    ))
+}
+
+// "/emby" and "/emby/" are the same directory prefix; the trailing slash is
+// dropped so nginx's proxy_pass slash rules never apply.
+fn parse_uri_prefix(flag: &str, prefix: &str) -> Result<String, AppError> {
+    let trimmed = prefix.trim().trim_end_matches('/');
+    let valid = trimmed.starts_with('/')
+        && trimmed
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.' | '~'))
+        && !trimmed.contains("//");
+    if !valid {
+        return Err(AppError::Validation(format!(
+            "{} path must look like /segment[/segment], got {}",
+            flag, prefix
+        )));
+    }
+    Ok(trimmed.to_string())
+}
+
+fn render_path_rewrite(strip_prefix: Option<&str>, backend_path: Option<&str>) -> String {
+    let target = backend_path.unwrap_or("");
+    let mut rules = String::new();
+    if let Some(prefix) = strip_prefix {
+        rules.push_str(&format!(
+            "        rewrite ^{}(?:/(.*))?$ {}/$1 break;\n",
+            prefix.replace('.', "\\."),
+            target
+        ));
+    }
+    if !target.is_empty() {
+        rules.push_str(&format!("        rewrite ^/(.*)$ {}/$1 break;\n", target));
+    }
+    rules
+}
+
+// User rewrites carry no flag, so they run in order and the strip-prefix and
+// backend-path rules (which end in break) still apply to their result.
+fn render_rewrites(rewrites: &[String]) -> Result<String, AppError> {
+    let mut rules = String::new();
+    for rewrite in rewrites {
+        let parts: Vec<&str> = rewrite.split_whitespace().collect();
+        let [pattern, replacement] = parts[..] else {
+            return Err(AppError::Validation(format!(
+                "--rewrite expects \"<pattern> <replacement>\", got {:?}",
+                rewrite
+            )));
+        };
+        if rewrite.contains(';') {
+            return Err(AppError::Validation(format!(
+                "--rewrite must not contain ';', got {:?}",
+                rewrite
+            )));
+        }
+        if !pattern.starts_with('^') {
+            warn(&format!(
+                "--rewrite pattern {} is not anchored with ^ and loops if it matches its own replacement",
+                pattern
+            ));
+        }
+        rules.push_str(&format!(
+            "        rewrite {} {};\n",
+            quote_rewrite_arg(pattern),
+            quote_rewrite_arg(replacement)
+        ));
+    }
+    Ok(rules)
+}
+
+// nginx reads an unquoted '{' or '}' as a block delimiter, so regex
+// quantifiers like {1,2} only parse inside quotes.
+fn quote_rewrite_arg(value: &str) -> String {
+    if value.contains(|c: char| c == '{' || c == '}' || c.is_whitespace()) {
+        format!("\"{}\"", value.replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn ssl_protocols(min_tls_version: Option<&str>) -> Result<&'static str, AppError> {
+    match min_tls_version {
+        None | Some("1.2") => Ok("TLSv1.2 TLSv1.3"),
+        Some("1.3") => Ok("TLSv1.3"),
+        Some(other) => Err(AppError::Validation(format!(
+            "Invalid MIN_TLS_VERSION '{}': expected 1.2 or 1.3",
+            other
+        ))),
+    }
+}
+
+// proxy_pass uses $website, and nginx rejects `proxy_redirect default` with a
+// variable proxy_pass, so `default` means emitting nothing.
+fn render_proxy_redirects(redirects: &[String]) -> Result<String, AppError> {
+    match redirects {
+        [] => return Ok(String::new()),
+        [single] if single.trim() == "default" => return Ok(String::new()),
+        [single] if single.trim() == "off" => {
+            return Ok("        proxy_redirect off;\n".to_string());
+        }
+        _ => {}
+    }
+    let mut rules = String::new();
+    for redirect in redirects {
+        let parts: Vec<&str> = redirect.split_whitespace().collect();
+        let [from, to] = parts[..] else {
+            let reason = if matches!(redirect.trim(), "off" | "default") {
+                "cannot be combined with other --proxy-redirect values"
+            } else {
+                "expects \"<from> <to>\", off or default"
+            };
+            return Err(AppError::Validation(format!(
+                "--proxy-redirect {}, got {:?}",
+                reason, redirect
+            )));
+        };
+        if redirect.contains(';') {
+            return Err(AppError::Validation(format!(
+                "--proxy-redirect must not contain ';', got {:?}",
+                redirect
+            )));
+        }
+        rules.push_str(&format!("        proxy_redirect {} {};\n", from, to));
+    }
+    Ok(rules)
+}
+
+// Overrides keep the default header's position and additions follow in the
+// order given, so the rendered block is stable across runs.
+fn render_forward_headers(
+    overrides: &[(String, String)],
+    removed: &[String],
+) -> Result<String, AppError> {
+    let mut headers: Vec<(String, String)> = DEFAULT_FORWARD_HEADERS
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let position = |headers: &[(String, String)], name: &str| {
+        headers
+            .iter()
+            .position(|(existing, _)| existing.eq_ignore_ascii_case(name))
+    };
+    for (name, value) in overrides {
+        check_forward_header_name("--forward-header", name)?;
+        if value.contains(['\n', '\r']) {
+            return Err(AppError::Validation(format!(
+                "--forward-header {} value must be a single line",
+                name
+            )));
+        }
+        let value = quote_header_value(value);
+        match position(&headers, name) {
+            Some(index) => headers[index] = (name.clone(), value),
+            None => headers.push((name.clone(), value)),
+        }
+    }
+    for name in removed {
+        check_forward_header_name("--no-forward-header", name)?;
+        let Some(index) = position(&headers, name) else {
+            return Err(AppError::Validation(format!(
+                "--no-forward-header {} does not match a forwarded header",
+                name
+            )));
+        };
+        headers.remove(index);
+    }
+    Ok(headers
+        .iter()
+        .map(|(name, value)| format!("        proxy_set_header {} {};\n", name, value))
+        .collect())
+}
+
+fn check_forward_header_name(flag: &str, name: &str) -> Result<(), AppError> {
+    if name.eq_ignore_ascii_case("Host") {
+        return Err(AppError::Validation(format!(
+            "{} cannot change Host, use --host-header",
+            flag
+        )));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(AppError::Validation(format!(
+            "{} expects a header name of letters, digits, '-' or '_', got {:?}",
+            flag, name
+        )));
+    }
+    Ok(())
+}
+
+// nginx ends the directive at ';' and splits on whitespace, so such values
+// (and the empty value that stops a client header) need quoting.
+fn quote_header_value(value: &str) -> String {
+    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    if !quoted && (value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ';')) {
+        format!("\"{}\"", value.replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn backend_host(backend_url: &str) -> Option<&str> {
+    let rest = backend_url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(backend_url);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = if let Some(bracketed) = authority.strip_prefix('[') {
+        bracketed.split(']').next()?
+    } else {
+        authority.split(':').next()?
+    };
+    if host.is_empty() { None } else { Some(host) }
+}
+
+fn lint_proxy_configs(output_dir: &Path, since: Option<Duration>) -> Result<Vec<String>, AppError> {
+    let entries = fs::read_dir(output_dir)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", output_dir.display())))?;
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+        .collect();
+    files.sort();
+    if let Some(since) = since {
+        files.retain(|file| modified_age(file).is_some_and(|age| age <= since));
+        info(&format!(
+            "{} config(s) modified since {}",
+            files.len(),
+            format_age(since.as_secs())
+        ));
+        for file in &files {
+            if let Some(age) = modified_age(file) {
+                info(&format!(
+                    "{} (modified {})",
+                    file.display(),
+                    format_age(age.as_secs())
+                ));
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    for file in files {
+        let content = fs::read_to_string(&file)
+            .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", file.display())))?;
+        let leftover = leftover_placeholders(&content);
+        if !leftover.is_empty() {
+            problems.push(format!(
+                "{}: unresolved placeholders: {}",
+                file.display(),
+                leftover.join(", ")
+            ));
+        }
+        for line in content.lines() {
+            let line = line.trim();
+            let Some(value) = line
+                .strip_prefix("ssl_certificate_key ")
+                .or_else(|| line.strip_prefix("ssl_certificate "))
+            else {
+                continue;
+            };
+            let value = value.trim_end_matches(';').trim();
+            if !value.contains('$') && !value.starts_with("\"data:") && !Path::new(value).is_file()
+            {
+                problems.push(format!("{}: missing file {}", file.display(), value));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+fn modified_age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default(),
+    )
+}
+
+fn list_acme_domains(acme_home: &Path) -> Result<Vec<(String, PathBuf)>, AppError> {
+    let entries = fs::read_dir(acme_home)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", acme_home.display())))?;
+    let mut domains = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let domain = name.strip_suffix("_ecc").unwrap_or(&name).to_string();
+        if path.join(format!("{}.conf", domain)).is_file() {
+            domains.push((domain, path));
+        }
+    }
+    domains.sort();
+    Ok(domains)
+}
+
+// acme.sh keeps account.key, account.json and ca.conf per CA under
+// <config home>/ca/<ca host>/<directory path>/.
+fn acme_ca_dir(
+    env_overrides: &HashMap<String, String>,
+    acme_home: Option<PathBuf>,
+    acme_config_home: Option<PathBuf>,
+) -> Result<PathBuf, AppError> {
+    let config_home =
+        match resolve_optional_path(acme_config_home, env_overrides, "ACME_CONFIG_HOME") {
+            Some(config_home) => config_home,
+            None => resolve_path(
+                acme_home,
+                env_overrides,
+                "ACME_HOME",
+                "/root/.acme.sh",
+                "acme home directory",
+            )?,
+        };
+    Ok(config_home.join("ca"))
+}
+
+fn account_files(root: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let dir = root.join(&relative);
+        let entries = fs::read_dir(&dir)
+            .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", dir.display())))?;
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            if entry.path().is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn copy_account_files(src: &Path, dst: &Path, force: bool, dry_run: bool) -> Result<(), AppError> {
+    let files = account_files(src)?;
+    if !files
+        .iter()
+        .any(|file| file.file_name().is_some_and(|name| name == "account.key"))
+    {
+        return Err(AppError::MissingInput(format!(
+            "No acme.sh account.key found under {}",
+            src.display()
+        )));
+    }
+    if !force
+        && let Some(existing) = files
+            .iter()
+            .map(|file| dst.join(file))
+            .find(|path| path.exists())
+    {
+        return Err(AppError::Validation(format!(
+            "{} already exists, pass --force to overwrite it",
+            existing.display()
+        )));
+    }
+
+    for file in &files {
+        let (from, to) = (src.join(file), dst.join(file));
+        record_change(to.display().to_string());
+        // Only paths are logged; account.key contents never reach the output.
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would copy {} -> {}",
+                from.display(),
+                to.display()
+            ));
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .map_err(|e| AppError::Io(format!("Failed to create {}: {e}", parent.display())))?;
+        }
+        fs::copy(&from, &to)
+            .map_err(|e| AppError::Io(format!("Failed to copy {}: {e}", from.display())))?;
+        restrict_permissions(&to)?;
+        info(&format!("Copied {}", file.display()));
+    }
+    if !dry_run {
+        success(&format!(
+            "{} account files copied to {}",
+            files.len(),
+            dst.display()
+        ));
+    }
+    Ok(())
+}
+
+fn env_line_key(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('#') {
+        return None;
+    }
+    let trimmed = trimmed.strip_prefix("export ").unwrap_or(trimmed);
+    let (key, _) = trimmed.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() { None } else { Some(key) }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    backup_path_at(path, unix_now())
+}
+
+fn backup_path_at(path: &Path, timestamp: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".bak.{}", timestamp));
+    PathBuf::from(name)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn backup_cert_pair(cert_path: &Path, key_path: &Path, dry_run: bool) -> Result<(), AppError> {
+    let timestamp = unix_now();
+    for path in [cert_path, key_path] {
+        if !path.is_file() {
+            continue;
+        }
+        let backup = backup_path_at(path, timestamp);
+        record_change(backup.display().to_string());
+        if dry_run {
+            info(&format!("[dry-run] Would back up to: {}", backup.display()));
+            continue;
+        }
+        fs::copy(path, &backup)
+            .map_err(|e| AppError::Io(format!("Failed to back up {}: {e}", path.display())))?;
+        info(&format!("Backed up {}", backup.display()));
+    }
+    Ok(())
+}
+
+// Copied rather than renamed, so the live config stays in place if the new
+// write fails.
+fn backup_config(path: &Path, keep: Option<u64>, dry_run: bool) -> Result<(), AppError> {
+    // Keeping zero would prune the backup just taken along with the rest.
+    if keep == Some(0) {
+        return Err(AppError::Validation(
+            "--keep-backups must be at least 1".to_string(),
+        ));
+    }
+    if !path.is_file() {
+        return Ok(());
+    }
+    let timestamp = unix_now();
+    let backup = backup_path_at(path, timestamp);
+    record_change(backup.display().to_string());
+    if dry_run {
+        info(&format!("[dry-run] Would back up to: {}", backup.display()));
+    } else {
+        fs::copy(path, &backup)
+            .map_err(|e| AppError::Io(format!("Failed to back up {}: {e}", path.display())))?;
+        info(&format!("Backed up {}", backup.display()));
+    }
+
+    let Some(keep) = keep else {
+        return Ok(());
+    };
+    let mut backups = list_backups(path);
+    if !backups.contains(&timestamp) {
+        backups.insert(0, timestamp);
+    }
+    for timestamp in backups.into_iter().skip(keep as usize) {
+        let stale = backup_path_at(path, timestamp);
+        record_change(stale.display().to_string());
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would remove old backup: {}",
+                stale.display()
+            ));
+            continue;
+        }
+        fs::remove_file(&stale)
+            .map_err(|e| AppError::Io(format!("Failed to remove {}: {e}", stale.display())))?;
+        debug(&format!("Removed old backup {}", stale.display()));
+    }
+    Ok(())
+}
+
+fn list_cert_backups(cert_path: &Path, key_path: &Path) -> Vec<u64> {
+    list_backups(cert_path)
+        .into_iter()
+        .filter(|timestamp| backup_path_at(key_path, *timestamp).is_file())
+        .collect()
+}
+
+// Newest first.
+fn list_backups(path: &Path) -> Vec<u64> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.bak.", name.to_string_lossy());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut timestamps: Vec<u64> = entries
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&prefix)?
+                .parse()
+                .ok()
+        })
+        .collect();
+    timestamps.sort_unstable_by(|a, b| b.cmp(a));
+    timestamps
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        s if s >= 86400 => format!("{}d ago", s / 86400),
+        s if s >= 3600 => format!("{}h ago", s / 3600),
+        s if s >= 60 => format!("{}m ago", s / 60),
+        s => format!("{}s ago", s),
+    }
+}
+
+// Readers (nginx reloads, apt) only ever see the old file or the new one: the
+// content goes to a sibling temp file that is renamed over the target.
+fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+    write_atomic_mode(path, content, None)
+}
+
+// With a mode the temp file is restricted before any content is written, so
+// secrets never sit on disk with the default umask.
+fn write_atomic_mode(path: &Path, content: impl AsRef<[u8]>, mode: Option<u32>) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp-{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let result = (|| {
+        let mut file = match mode {
+            Some(mode) => {
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .mode(mode)
+                    .open(&tmp_path)?;
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+                file
+            }
+            None => File::create(&tmp_path)?,
+        };
+        file.write_all(content.as_ref())?;
+        file.sync_all()?;
+        if let Ok(existing) = fs::metadata(path) {
+            if mode.is_none() {
+                fs::set_permissions(&tmp_path, existing.permissions())?;
+            }
+            std::os::unix::fs::chown(&tmp_path, Some(existing.uid()), Some(existing.gid()))?;
+        }
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+// Cert and key copies keep the source mode, so a 0600 key is never exposed
+// and nginx never loads a half-written file.
+fn copy_atomic(from: &Path, to: &Path) -> io::Result<()> {
+    let content = fs::read(from)?;
+    let mode = fs::metadata(from)?.permissions().mode() & 0o7777;
+    write_atomic_mode(to, content, Some(mode))
+}
+
+const MANAGED_MARKER: &str = "# managed by emby-proxy-cli";
+
+static NO_CLOBBER_SYSTEM: AtomicBool = AtomicBool::new(false);
+
+pub fn set_no_clobber_system(enabled: bool) {
+    NO_CLOBBER_SYSTEM.store(enabled, Ordering::Relaxed);
+}
+
+fn is_managed(content: &str) -> bool {
+    content
+        .lines()
+        .take(5)
+        .any(|line| line.trim() == MANAGED_MARKER)
+}
+
+fn stamp_managed(content: String) -> String {
+    if is_managed(&content) {
+        content
+    } else {
+        format!("{}\n{}", MANAGED_MARKER, content)
+    }
+}
+
+// Generated configs carry the tool version, time and invocation below the
+// marker; the time and invocation lines are ignored when checking for changes.
+fn stamp_provenance(content: String) -> String {
+    format!(
+        "{}\n# version: {}\n# generated: {}\n# command: {}\n{}",
+        MANAGED_MARKER,
+        env!("CARGO_PKG_VERSION"),
+        format_utc(unix_now()),
+        invocation(),
+        content
+    )
+}
+
+fn refresh_provenance(content: String) -> String {
+    if !is_managed(&content) {
+        return content;
+    }
+    let trailing_newline = content.ends_with('\n');
+    let mut refreshed = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if index < 4 && line.starts_with("# generated: ") {
+                format!("# generated: {}", format_utc(unix_now()))
+            } else if index < 4 && line.starts_with("# command: ") {
+                format!("# command: {}", invocation())
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if trailing_newline {
+        refreshed.push('\n');
+    }
+    refreshed
+}
+
+fn is_volatile_header_line(line: &str) -> bool {
+    line.starts_with("# generated: ") || line.starts_with("# command: ")
+}
+
+fn invocation() -> String {
+    let mut args = std::env::args();
+    let mut parts = vec![
+        args.next()
+            .map(|program| {
+                Path::new(&program)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or(program)
+            })
+            .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string()),
+    ];
+    let mut mask_next = false;
+    for arg in args {
+        let arg = if mask_next {
+            mask_env_arg(&arg)
+        } else if let Some(value) = arg.strip_prefix("--env=") {
+            format!("--env={}", mask_env_arg(value))
+        } else {
+            arg
+        };
+        mask_next = arg == "--env";
+        parts.push(shell_quote(&arg.replace('\n', " ")));
+    }
+    parts.join(" ")
+}
+
+fn mask_env_arg(value: &str) -> String {
+    match value.split_once('=') {
+        Some((key, _)) => format!("{}=***", key),
+        None => value.to_string(),
+    }
+}
+
+fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// With --no-clobber-system, an existing file is only replaced when an earlier
+// run stamped it; anything else may belong to the distro or the admin.
+fn ensure_clobber_allowed(path: &Path) -> Result<(), AppError> {
+    if !NO_CLOBBER_SYSTEM.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    match fs::read_to_string(path) {
+        Ok(existing) if is_managed(&existing) => Ok(()),
+        Ok(_) => Err(AppError::Validation(format!(
+            "{} exists and was not created by emby-proxy-cli (no \"{}\" header); \
+             refusing to overwrite it with --no-clobber-system. Move it aside, or add the \
+             header to hand it over to this tool",
+            path.display(),
+            MANAGED_MARKER
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(AppError::Io(format!(
+            "Failed to read {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+fn config_unchanged(path: &Path, content: &str) -> bool {
+    let stable = |content: &str| {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(index, line)| !(*index < 4 && is_volatile_header_line(line)))
+            .map(|(_, line)| line.to_string())
+            .collect::<Vec<_>>()
+    };
+    match fs::read_to_string(path) {
+        Ok(existing) => {
+            existing.ends_with('\n') == content.ends_with('\n')
+                && stable(&existing) == stable(content)
+        }
+        Err(_) => false,
+    }
+}
+
+fn copy_cert_files(
+    cert_src: &Path,
+    key_src: &Path,
+    cert_dst: &Path,
+    key_dst: &Path,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let cert_parent_display = cert_dst
+        .parent()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would create cert dir: {}",
+            cert_parent_display
+        ));
+    } else if let Some(parent) = cert_dst.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| AppError::Io(format!("Failed to create {}: {e}", parent.display())))?;
+    }
+
+    backup_cert_pair(cert_dst, key_dst, dry_run)?;
+    record_change(cert_dst.display().to_string());
+    record_change(key_dst.display().to_string());
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would copy cert: {} -> {}",
+            cert_src.display(),
+            cert_dst.display()
+        ));
+        info(&format!(
+            "[dry-run] Would copy key: {} -> {}",
+            key_src.display(),
+            key_dst.display()
+        ));
+    } else {
+        copy_atomic(cert_src, cert_dst).map_err(|e| {
+            AppError::Io(format!(
+                "Failed to copy cert from {}: {e}",
+                cert_src.display()
+            ))
+        })?;
+        copy_atomic(key_src, key_dst).map_err(|e| {
+            AppError::Io(format!(
+                "Failed to copy key from {}: {e}",
+                key_src.display()
+            ))
+        })?;
+        success("Certificate files updated");
+    }
+    Ok(())
+}
+
+fn certs_need_renewal(
+    runner: &dyn CommandRunner,
+    variants: &[CertVariant],
+    threshold: u64,
+) -> bool {
+    let mut needs_renewal = false;
+    for variant in variants {
+        match days_until_expiry(runner, &variant.cert) {
+            Some(days) => {
+                info(&format!(
+                    "{} expires in {} days",
+                    variant.cert.display(),
+                    days
+                ));
+                if days <= threshold as i64 {
+                    needs_renewal = true;
+                }
+            }
+            None => {
+                info(&format!(
+                    "{} is missing or unreadable, issuing",
+                    variant.cert.display()
+                ));
+                needs_renewal = true;
+            }
+        }
+    }
+    needs_renewal
+}
+
+struct CertStatus {
+    issued: String,
+    expires: String,
+    days_left: i64,
+    issuer: String,
+}
+
+fn cert_status(runner: &dyn CommandRunner, cert: &Path) -> Option<CertStatus> {
+    if !cert.is_file() {
+        return None;
+    }
+    // The leaf is the first certificate in fullchain.cer, which is all x509 reads.
+    let output = runner
+        .output(
+            Command::new("openssl")
+                .args(["x509", "-noout", "-startdate", "-enddate", "-issuer", "-in"])
+                .arg(cert),
+        )
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let field = |prefix: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .map(str::trim)
+    };
+    let (not_before, not_after) = (field("notBefore=")?, field("notAfter=")?);
+    Some(CertStatus {
+        issued: openssl_date_ymd(not_before)?,
+        expires: openssl_date_ymd(not_after)?,
+        days_left: days_from_now(parse_openssl_date(not_after)?)?,
+        issuer: issuer_name(field("issuer=")?),
+    })
+}
+
+fn issuer_name(issuer: &str) -> String {
+    let attribute = |key: &str| {
+        issuer.split([',', '/']).find_map(|part| {
+            let (name, value) = part.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+    attribute("O")
+        .or_else(|| attribute("CN"))
+        .unwrap_or_else(|| issuer.to_string())
+}
+
+fn days_until_expiry(runner: &dyn CommandRunner, cert: &Path) -> Option<i64> {
+    if !cert.is_file() {
+        return None;
+    }
+    let output = runner
+        .output(
+            Command::new("openssl")
+                .args(["x509", "-enddate", "-noout", "-in"])
+                .arg(cert),
+        )
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    days_from_now(parse_openssl_date(
+        stdout.trim().strip_prefix("notAfter=")?,
+    )?)
+}
+
+fn days_from_now(timestamp: i64) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    Some((timestamp - now).div_euclid(86400))
+}
+
+fn openssl_date_ymd(value: &str) -> Option<String> {
+    let mut parts = value.split_whitespace();
+    let month_name = parts.next()?;
+    let month = MONTH_NAMES.iter().position(|name| *name == month_name)? + 1;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let year: u32 = parts.nth(1)?.parse().ok()?;
+    Some(format!("{}-{:02}-{:02}", year, month, day))
+}
+
+fn parse_openssl_date(value: &str) -> Option<i64> {
+    let mut parts = value.split_whitespace();
+    let month_name = parts.next()?;
+    let month = MONTH_NAMES.iter().position(|name| *name == month_name)? as i64 + 1;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|part| part.parse::<i64>());
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    let year: i64 = parts.next()?.parse().ok()?;
+
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+struct FileOwner {
+    spec: String,
+    uid: u32,
+    gid: u32,
+}
+
+fn validate_issue_cert_args(
+    args: &IssueCertArgs,
+    env_overrides: &HashMap<String, String>,
+) -> Result<(), AppError> {
+    let value = |cli: &Option<String>, key: &str| {
+        cli.clone()
+            .filter(|value| !value.trim().is_empty())
+            .or_else(|| resolve_from_envs(env_overrides, &[key]))
+    };
+    let path = |cli: &Option<PathBuf>, key: &str| {
+        cli.clone()
+            .or_else(|| resolve_from_envs(env_overrides, &[key]).map(PathBuf::from))
+    };
+    let mut problems = Vec::new();
+
+    let cert_output = path(&args.cert_output_path, "CERT_OUTPUT_PATH");
+    let key_output = path(&args.key_output_path, "KEY_OUTPUT_PATH");
+    if cert_output.is_some() ^ key_output.is_some() {
+        problems.push("CERT_OUTPUT_PATH and KEY_OUTPUT_PATH must be set together".to_string());
+    }
+    if args
+        .preferred_chain
+        .as_deref()
+        .is_some_and(|chain| chain.trim().is_empty())
+    {
+        problems.push("--preferred-chain must not be empty".to_string());
+    }
+    let cert_input = path(&args.cert_input_path, "CERT_INPUT_PATH");
+    let key_input = path(&args.key_input_path, "KEY_INPUT_PATH");
+    if cert_input.is_some() ^ key_input.is_some() {
+        problems.push("CERT_INPUT_PATH and KEY_INPUT_PATH must be set together".to_string());
+    }
+    for input in cert_input.iter().chain(key_input.iter()) {
+        if !input.is_file() {
+            problems.push(format!("Input file {} does not exist", input.display()));
+        }
+    }
+    let using_input = cert_input.is_some() && key_input.is_some();
+    if args.dual_cert && using_input {
+        problems
+            .push("--dual-cert cannot be combined with CERT_INPUT_PATH/KEY_INPUT_PATH".to_string());
+    }
+    if args.offline && !using_input {
+        problems.push("Offline mode requires CERT_INPUT_PATH and KEY_INPUT_PATH".to_string());
+    }
+    if let Some(spec) = value(&args.cert_owner, "CERT_OWNER")
+        && let Err(err) = parse_owner("--cert-owner", &spec)
+    {
+        problems.push(err.to_string());
+    }
+    if let Err(err) = resolve_cert_name_scheme(
+        args.cert_name_scheme.clone(),
+        args.cert_filename.clone(),
+        args.key_filename.clone(),
+        env_overrides,
+    ) {
+        problems.push(err.to_string());
+    }
+
+    let domain = value(&args.domain, "DOMAIN");
+    let needs_domain = !using_input || cert_output.is_none() || key_output.is_none();
+    match &domain {
+        Some(domain) => {
+            if let Err(err) = validate_hostname("DOMAIN", domain) {
+                problems.push(err.to_string());
+            }
+        }
+        None if needs_domain => problems.push("DOMAIN is not set (--domain)".to_string()),
+        None => {}
+    }
+
+    if !using_input {
+        if value(&args.cf_token, "CF_TOKEN").is_none() {
+            problems.push("CF_TOKEN is not set (--cf-token)".to_string());
+        }
+        if let Some(wildcard) = value(&args.wildcard_domain, "WILDCARD_DOMAIN") {
+            let valid = wildcard
+                .strip_prefix("*.")
+                .is_some_and(|base| validate_hostname("WILDCARD_DOMAIN", base).is_ok());
+            if !valid {
+                problems.push(format!(
+                    "WILDCARD_DOMAIN must look like *.example.com, got {}",
+                    wildcard
+                ));
+            }
+        }
+        let challenge_alias = value(&args.challenge_alias, "CHALLENGE_ALIAS");
+        let domain_alias = value(&args.domain_alias, "DOMAIN_ALIAS");
+        if challenge_alias.is_some() && domain_alias.is_some() {
+            problems.push("--challenge-alias and --domain-alias cannot be combined".to_string());
+        }
+        for (flag, alias) in [
+            ("--challenge-alias", challenge_alias),
+            ("--domain-alias", domain_alias),
+        ] {
+            if let Some(alias) = alias
+                && let Err(err) = validate_hostname(flag, &alias)
+            {
+                problems.push(err.to_string());
+            }
+        }
+        let acme_bin = path(&args.acme_bin, "ACME_BIN")
+            .unwrap_or_else(|| PathBuf::from("/root/.acme.sh/acme.sh"));
+        if !acme_bin.is_file() {
+            problems.push(format!("acme.sh not found at {}", acme_bin.display()));
+        }
+    }
+
+    if problems.is_empty() {
+        success("All inputs are valid");
+        return Ok(());
+    }
+    Err(AppError::Validation(format!(
+        "{} problem(s) found:\n  - {}",
+        problems.len(),
+        problems.join("\n  - ")
+    )))
+}
+
+fn parse_owner(flag: &str, spec: &str) -> Result<FileOwner, AppError> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    if user.is_empty() || group.is_some_and(str::is_empty) {
+        return Err(AppError::Validation(format!(
+            "{} expects user[:group], got {}",
+            flag, spec
+        )));
+    }
+    let (uid, primary_gid) = match lookup_account("/etc/passwd", user) {
+        Some((uid, Some(gid))) => (uid, gid),
+        _ => {
+            return Err(AppError::Validation(format!(
+                "User {} does not exist",
+                user
+            )));
+        }
+    };
+    let gid = match group {
+        Some(group) => lookup_account("/etc/group", group)
+            .map(|(gid, _)| gid)
+            .ok_or(AppError::Validation(format!(
+                "Group {} does not exist",
+                group
+            )))?,
+        None => primary_gid,
+    };
+    Ok(FileOwner {
+        spec: spec.to_string(),
+        uid,
+        gid,
+    })
+}
+
+fn lookup_account(path: &str, name: &str) -> Option<(u32, Option<u32>)> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        let id = fields.get(2)?.parse::<u32>().ok()?;
+        let matches = fields[0] == name || name.parse::<u32>() == Ok(id);
+        matches.then(|| (id, fields.get(3).and_then(|gid| gid.parse().ok())))
+    })
+}
+
+fn chown_cert_files(
+    owner: &FileOwner,
+    cert: &Path,
+    key: &Path,
+    include_dir: bool,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let mut paths = vec![cert, key];
+    if include_dir && let Some(parent) = cert.parent() {
+        paths.push(parent);
+    }
+    chown_paths(owner, &paths, dry_run)?;
+    if !dry_run {
+        success(&format!("Certificate files owned by {}", owner.spec));
+    }
+    Ok(())
+}
+
+fn chown_paths(owner: &FileOwner, paths: &[&Path], dry_run: bool) -> Result<(), AppError> {
+    for path in paths {
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would chown {} to {}",
+                path.display(),
+                owner.spec
+            ));
+            continue;
+        }
+        std::os::unix::fs::chown(path, Some(owner.uid), Some(owner.gid)).map_err(|e| {
+            AppError::Io(format!(
+                "Failed to chown {} to {}: {e}",
+                path.display(),
+                owner.spec
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+fn missing_dirs(dir: &Path) -> Vec<PathBuf> {
+    dir.ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+fn chown_output(
+    owner: &FileOwner,
+    output_path: &Path,
+    created_dirs: &[PathBuf],
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let mut paths = vec![output_path];
+    paths.extend(created_dirs.iter().map(PathBuf::as_path));
+    chown_paths(owner, &paths, dry_run)?;
+    if !dry_run {
+        success(&format!(
+            "{} owned by {}",
+            output_path.display(),
+            owner.spec
+        ));
+    }
+    Ok(())
+}
+
+enum PropagationCheck {
+    Resolvers,
+    Cloudflare { token: String, zone_id: String },
+}
+
+pub fn wait_propagation(
+    runner: &dyn CommandRunner,
+    env_overrides: &HashMap<String, String>,
+    record: &str,
+    value: Option<&str>,
+    timeout: u64,
+    check: &str,
+) -> Result<(), AppError> {
+    let check = match check {
+        "cloudflare" => PropagationCheck::Cloudflare {
+            token: resolve_from_envs(env_overrides, &["CF_TOKEN"]).ok_or_else(|| {
+                AppError::MissingInput("CF_TOKEN is required for the cloudflare check".to_string())
+            })?,
+            zone_id: resolve_from_envs(env_overrides, &["CF_ZONE_ID"]).unwrap_or_default(),
+        },
+        _ => PropagationCheck::Resolvers,
+    };
+    wait_for_dns_propagation(runner, record, value, Duration::from_secs(timeout), &check)
+}
+
+// acme.sh adds every TXT record before it asks the CA to validate, so a hook
+// whose add step blocks on wait-propagation holds validation back until the
+// record is visible. A timeout fails the add and with it the issuance.
+fn install_propagation_hook(
+    acme_bin: &Path,
+    check: &str,
+    timeout: u64,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let dnsapi = acme_bin
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("dnsapi");
+    let hook_path = dnsapi.join(format!("{}.sh", PROPAGATION_HOOK));
+    let dns_cf = dnsapi.join("dns_cf.sh");
+    let exe = env::current_exe()
+        .map_err(|e| AppError::Io(format!("Failed to locate this binary: {e}")))?;
+    let hook = render_propagation_hook(&dns_cf, &exe, check, timeout);
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would install acme.sh DNS hook: {}",
+            hook_path.display()
+        ));
+        return Ok(());
+    }
+    if !dns_cf.is_file() {
+        return Err(AppError::Validation(format!(
+            "--wait-for-propagation needs acme.sh's {}",
+            dns_cf.display()
+        )));
+    }
+    if fs::read_to_string(&hook_path).is_ok_and(|existing| existing == hook) {
+        return Ok(());
+    }
+    write_atomic(&hook_path, hook)
+        .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", hook_path.display())))?;
+    record_change(hook_path.display().to_string());
+    debug(&format!(
+        "Installed acme.sh DNS hook {}",
+        hook_path.display()
+    ));
+    Ok(())
+}
+
+// acme.sh keeps using the hook on renewal, so the wait is skipped instead of
+// failing if this binary has since moved.
+fn render_propagation_hook(dns_cf: &Path, exe: &Path, check: &str, timeout: u64) -> String {
+    r#"#!/usr/bin/env sh
+{{MARKER}}
+
+. {{DNS_CF}}
+
+{{HOOK}}_add() {
+  dns_cf_add "$1" "$2" || return 1
+  if [ -x {{EXE}} ]; then
+    CF_TOKEN="${CF_Token:-}" CF_ZONE_ID="${CF_Zone_ID:-}" {{EXE}} wait-propagation \
+      --record "$1" --value "$2" --timeout {{TIMEOUT}} --check {{CHECK}}
+  fi
+}
+
+{{HOOK}}_rm() {
+  dns_cf_rm "$1" "$2"
+}
+"#
+    .replace("{{MARKER}}", MANAGED_MARKER)
+    .replace("{{DNS_CF}}", &shell_quote(&dns_cf.display().to_string()))
+    .replace("{{HOOK}}", PROPAGATION_HOOK)
+    .replace("{{EXE}}", &shell_quote(&exe.display().to_string()))
+    .replace("{{TIMEOUT}}", &timeout.to_string())
+    .replace("{{CHECK}}", check)
+}
+
+fn challenge_zone(record: &str) -> String {
+    apex_domain(record.strip_prefix("_acme-challenge.").unwrap_or(record))
+}
+
+fn wait_for_dns_propagation(
+    runner: &dyn CommandRunner,
+    record: &str,
+    value: Option<&str>,
+    timeout: Duration,
+    check: &PropagationCheck,
+) -> Result<(), AppError> {
+    let zone = challenge_zone(record);
+    let mut zone_id = String::new();
+    let mut pending = match check {
+        PropagationCheck::Cloudflare { token, zone_id: id } if command_exists("curl") => {
+            zone_id = if id.is_empty() {
+                cf_zone_id_for(runner, token, &zone)?
+            } else {
+                id.clone()
+            };
+            vec![CLOUDFLARE_API_LABEL.to_string()]
+        }
+        _ if command_exists("dig") => {
+            let mut servers = lookup_authoritative_servers(runner, &zone);
+            servers.extend(PROPAGATION_RESOLVERS.iter().map(|s| s.to_string()));
+            servers
+        }
+        _ => {
+            warn("dig not found, skipping DNS propagation wait");
+            return Ok(());
+        }
+    };
+    info(&format!("Waiting for {} to propagate", record));
+
+    let start = Instant::now();
+    loop {
+        pending.retain(|server| match check {
+            PropagationCheck::Cloudflare { token, .. } if server == CLOUDFLARE_API_LABEL => {
+                !cf_txt_record_exists(runner, record, value, token, &zone_id)
+            }
+            _ => !txt_record_exists(runner, record, value, server),
+        });
+        if pending.is_empty() {
+            break;
+        }
+        if start.elapsed() >= timeout {
+            return Err(AppError::ExternalCommand(format!(
+                "{} did not propagate within {}s, still missing on: {}",
+                record,
+                timeout.as_secs(),
+                pending.join(", ")
+            )));
+        }
+        info(&format!(
+            "TXT record not visible yet on {} ({}s elapsed)",
+            pending.join(", "),
+            start.elapsed().as_secs()
+        ));
+        thread::sleep(PROPAGATION_POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+    }
+
+    success(&format!("{} visible on all resolvers", record));
+    Ok(())
+}
+
+fn cf_zone_id_for(runner: &dyn CommandRunner, token: &str, zone: &str) -> Result<String, AppError> {
+    let body = cf_api_get(runner, token, &format!("zones?name={}", zone))?;
+    body["result"][0]["id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| AppError::Validation(format!("No Cloudflare zone found for {}", zone)))
+}
+
+fn cf_txt_record_exists(
+    runner: &dyn CommandRunner,
+    record: &str,
+    value: Option<&str>,
+    token: &str,
+    zone_id: &str,
+) -> bool {
+    let path = format!("zones/{}/dns_records?type=TXT&name={}", zone_id, record);
+    let Ok(body) = cf_api_get(runner, token, &path) else {
+        return false;
+    };
+    if body["success"].as_bool() != Some(true) {
+        return false;
+    }
+    let Some(records) = body["result"].as_array() else {
+        return false;
+    };
+    match value {
+        Some(value) => records
+            .iter()
+            .any(|r| r["content"].as_str().map(|c| c.trim_matches('"')) == Some(value)),
+        None => !records.is_empty(),
+    }
+}
+
+fn cf_api_get(
+    runner: &dyn CommandRunner,
+    token: &str,
+    path: &str,
+) -> Result<serde_json::Value, AppError> {
+    cf_api_request(runner, token, path, None)
+}
+
+fn cf_api_request(
+    runner: &dyn CommandRunner,
+    token: &str,
+    path: &str,
+    json_body: Option<&str>,
+) -> Result<serde_json::Value, AppError> {
+    // Token goes through stdin so it never shows up in the process list.
+    let mut config = format!(
+        "url = \"https://api.cloudflare.com/client/v4/{}\"\nheader = \"Authorization: Bearer {}\"\n",
+        path, token
+    );
+    if let Some(body) = json_body {
+        config.push_str(&format!(
+            "request = \"POST\"\nheader = \"Content-Type: application/json\"\ndata = \"{}\"\n",
+            body.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    let output = runner
+        .output_with_input(
+            Command::new("curl")
+                .arg("-sS")
+                .arg("--max-time")
+                .arg("10")
+                .arg("--config")
+                .arg("-"),
+            config.as_bytes(),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run curl: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::ExternalCommand(format!(
+            "Cloudflare API request failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| {
+        AppError::ExternalCommand(format!("Invalid response from Cloudflare API: {e}"))
+    })
+}
+
+fn cf_api_errors(body: &serde_json::Value) -> String {
+    body["errors"]
+        .as_array()
+        .map(|errors| {
+            errors
+                .iter()
+                .filter_map(|error| error["message"].as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| "no details returned".to_string())
+}
+
+// The cert is already installed when this runs, so failures only warn.
+fn purge_cf_cache(
+    runner: &dyn CommandRunner,
+    token: &str,
+    zone_id: &str,
+    domain: &str,
+    dry_run: bool,
+) {
+    step("Purging Cloudflare cache");
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would purge the Cloudflare cache for the {} zone",
+            domain
+        ));
+        return;
+    }
+    let zone_id = if zone_id.is_empty() {
+        let zone = cf_api_get(
+            runner,
+            token,
+            &format!("zones?name={}", apex_domain(domain)),
+        );
+        match zone
+            .as_ref()
+            .ok()
+            .and_then(|body| body["result"][0]["id"].as_str())
+        {
+            Some(id) => id.to_string(),
+            None => {
+                warn(&format!(
+                    "Cloudflare cache purge skipped: no zone found for {}",
+                    domain
+                ));
+                return;
+            }
+        }
+    } else {
+        zone_id.to_string()
+    };
+    match cf_api_request(
+        runner,
+        token,
+        &format!("zones/{}/purge_cache", zone_id),
+        Some(r#"{"purge_everything":true}"#),
+    ) {
+        Ok(body) if body["success"].as_bool() == Some(true) => {
+            success(&format!("Cloudflare cache purged (zone {})", zone_id));
+        }
+        Ok(body) => warn(&format!(
+            "Cloudflare cache purge failed: {}",
+            cf_api_errors(&body)
+        )),
+        Err(err) => warn(&format!("Cloudflare cache purge failed: {}", err)),
+    }
+}
+
+fn verify_cf_token(
+    runner: &dyn CommandRunner,
+    token: &str,
+    zone_id: &str,
+    zone_name: &str,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Verifying Cloudflare token");
+    if dry_run {
+        info("[dry-run] Would verify the Cloudflare token and its DNS edit permission");
+        return Ok(());
+    }
+
+    let body = cf_api_get(runner, token, "user/tokens/verify")?;
+    if body["success"].as_bool() != Some(true) || body["result"]["status"] != "active" {
+        return Err(AppError::Validation(format!(
+            "Cloudflare token is invalid or not active: {}",
+            cf_api_errors(&body)
+        )));
+    }
+
+    let zone_path = if zone_id.is_empty() {
+        format!("zones?name={}", zone_name)
+    } else {
+        format!("zones/{}", zone_id)
+    };
+    let body = cf_api_get(runner, token, &zone_path)?;
+    let zone = match &body["result"] {
+        serde_json::Value::Array(zones) => zones.first().cloned(),
+        serde_json::Value::Object(_) => Some(body["result"].clone()),
+        _ => None,
+    };
+    let Some(zone) = zone.filter(|_| body["success"].as_bool() == Some(true)) else {
+        return Err(AppError::Validation(format!(
+            "Cloudflare token cannot read the {} zone (needs Zone:Zone:Read): {}",
+            zone_name,
+            cf_api_errors(&body)
+        )));
+    };
+    let permissions: Vec<&str> = zone["permissions"]
+        .as_array()
+        .map(|values| values.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+    // Scoped API tokens usually get no permissions list back, so edit rights
+    // can only be confirmed when Cloudflare reports them.
+    if permissions.is_empty() {
+        warn(&format!(
+            "Cloudflare token is active but its DNS edit permission on {} could not be confirmed",
+            zone["name"].as_str().unwrap_or(zone_name)
+        ));
+        return Ok(());
+    }
+    if !permissions.contains(&"#dns_records:edit") {
+        return Err(AppError::Validation(format!(
+            "Cloudflare token lacks Zone:DNS:Edit on {}",
+            zone["name"].as_str().unwrap_or(zone_name)
+        )));
+    }
+    success("Cloudflare token is active and can edit DNS records");
+    Ok(())
+}
+
+fn lookup_authoritative_servers(runner: &dyn CommandRunner, domain: &str) -> Vec<String> {
+    let output = match runner.output(Command::new("dig").args(["+short", "NS", domain])) {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().trim_end_matches('.').to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+// With a value only that TXT string counts, so a stale record left by an
+// earlier attempt cannot satisfy the wait.
+fn txt_record_exists(
+    runner: &dyn CommandRunner,
+    record: &str,
+    value: Option<&str>,
+    server: &str,
+) -> bool {
+    let output = runner.output(
+        Command::new("dig")
+            .arg("+short")
+            .arg("TXT")
+            .arg(record)
+            .arg(format!("@{}", server)),
+    );
+    match output {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let mut answers = stdout
+                .lines()
+                .map(|line| line.trim().trim_matches('"'))
+                .filter(|line| !line.is_empty());
+            match value {
+                Some(value) => answers.any(|answer| answer == value),
+                None => answers.next().is_some(),
+            }
+        }
+        _ => false,
+    }
+}
+
+fn install_acme_cert(
+    runner: &dyn CommandRunner,
+    acme_bin: &Path,
+    acme_homes: &AcmeHomes,
+    domain: &str,
+    variant: &CertVariant,
+    nginx_bin: Option<&PathBuf>,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if let Some(parent) = variant.cert.parent() {
+        if dry_run {
+            info(&format!(
+                "[dry-run] Would create cert dir: {}",
+                parent.display()
+            ));
+        } else {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::Io(format!("Failed to create {}: {e}", parent.display())))?;
+        }
+    }
+
+    backup_cert_pair(&variant.cert, &variant.key, dry_run)?;
+    let mut cmd = Command::new(acme_bin);
+    cmd.arg("--install-cert")
+        .arg("-d")
+        .arg(domain)
+        .arg("--keylength")
+        .arg(variant.keylength)
+        .arg("--fullchain-file")
+        .arg(&variant.cert)
+        .arg("--key-file")
+        .arg(&variant.key)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    acme_homes.apply(&mut cmd);
+
+    if let Some(nginx_bin) = nginx_bin {
+        let reload_cmd = format!("{} -s reload", nginx_bin.display());
+        cmd.arg("--reloadcmd").arg(reload_cmd);
+    }
+
+    record_change(variant.cert.display().to_string());
+    record_change(variant.key.display().to_string());
+    if dry_run {
+        info("[dry-run] Would run acme.sh --install-cert");
+        return Ok(());
+    }
+
+    let status = runner.status(&mut cmd).map_err(|e| {
+        AppError::ExternalCommand(format!("Failed to run acme.sh --install-cert: {e}"))
+    })?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand(
+            "acme.sh --install-cert failed".to_string(),
+        ));
+    }
+    success("Certificate files installed");
+    Ok(())
+}
+
+fn reload_nginx_binary(
+    runner: &dyn CommandRunner,
+    nginx_bin: Option<&PathBuf>,
+    confirm: bool,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    step("Reloading nginx");
+    let nginx_bin = nginx_bin.ok_or(AppError::MissingInput(
+        "nginx binary is required for reload".to_string(),
+    ))?;
+    test_nginx_config(runner, nginx_bin, dry_run)?;
+    if confirm
+        && !confirm_with_timeout("Reload nginx now?", DEFAULT_CONFIRM_TIMEOUT, dry_run)?
+        && !dry_run
+    {
+        warn(&format!(
+            "nginx reload skipped, run {} -s reload when ready",
+            nginx_bin.display()
+        ));
+        return Ok(());
+    }
+    if dry_run {
+        info("[dry-run] Would reload nginx");
+        return Ok(());
+    }
+
+    let status = runner
+        .status_with_timeout(
+            Command::new(nginx_bin)
+                .arg("-s")
+                .arg("reload")
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+            reload_timeout(),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to reload nginx: {e}")))?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand("nginx reload failed".to_string()));
+    }
+    success("nginx reloaded");
+    Ok(())
+}
+
+fn run_post_write_cmd(
+    runner: &dyn CommandRunner,
+    command: &str,
+    config_file: &Path,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would run post-write command: {}",
+            command
+        ));
+        return Ok(());
+    }
+
+    let status = runner
+        .status(
+            Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("CONFIG_FILE", config_file)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run post-write command: {e}")))?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand(format!(
+            "Post-write command failed: {}",
+            command
+        )));
+    }
+    success("post-write command completed");
+    Ok(())
+}
+
+fn test_nginx_config(
+    runner: &dyn CommandRunner,
+    nginx_bin: &Path,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if dry_run {
+        info("[dry-run] Would run nginx -t");
+        return Ok(());
+    }
+
+    let status = runner
+        .status_with_timeout(
+            Command::new(nginx_bin)
+                .arg("-t")
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+            reload_timeout(),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run nginx -t: {e}")))?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand("nginx -t failed".to_string()));
+    }
+    success("nginx config test passed");
+    Ok(())
+}
+
+fn test_caddy_config(
+    runner: &dyn CommandRunner,
+    config_path: &Path,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if dry_run {
+        info("[dry-run] Would run caddy validate");
+        return Ok(());
+    }
+
+    let status = runner
+        .status(
+            Command::new("caddy")
+                .arg("validate")
+                .arg("--adapter")
+                .arg("caddyfile")
+                .arg("--config")
+                .arg(config_path)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run caddy validate: {e}")))?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand(
+            "caddy validate failed".to_string(),
+        ));
+    }
+    success("caddy config validation passed");
+    Ok(())
+}
+
+fn test_apache_config(runner: &dyn CommandRunner, dry_run: bool) -> Result<(), AppError> {
+    if dry_run {
+        info("[dry-run] Would run apachectl configtest");
+        return Ok(());
+    }
+
+    let status = runner
+        .status(
+            Command::new("apachectl")
+                .arg("configtest")
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|e| {
+            AppError::ExternalCommand(format!("Failed to run apachectl configtest: {e}"))
+        })?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand(
+            "apachectl configtest failed".to_string(),
+        ));
+    }
+    success("apache config test passed");
+    Ok(())
+}
+
+fn resolve_nginx_bin(env_overrides: &HashMap<String, String>) -> PathBuf {
+    resolve_from_envs(env_overrides, &["NGINX_BIN"])
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("nginx"))
+}
+
+fn load_template(path: Option<&Path>, embedded: &str) -> Result<String, AppError> {
+    match path {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| AppError::Io(format!("Failed to read template {}: {e}", path.display()))),
+        None => Ok(embedded.to_string()),
+    }
+}
+
+fn watch_template<F>(path: &Path, interval_secs: u64, mut on_change: F) -> Result<(), AppError>
+where
+    F: FnMut(&str) -> Result<(), AppError>,
+{
+    let interval = Duration::from_secs(interval_secs.max(1));
+    info(&format!(
+        "Watching {} every {}s (Ctrl+C to stop)",
+        path.display(),
+        interval.as_secs()
+    ));
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last = modified(path);
+    loop {
+        thread::sleep(interval);
+        let current = modified(path);
+        if current == last {
+            continue;
+        }
+        last = current;
+        if let Err(err) = load_template(Some(path), "").and_then(|t| on_change(&t)) {
+            warn(&err.to_string());
+        }
+    }
+}
+
+fn install_if_missing<F>(
+    command_name: &str,
+    changes: &mut Vec<(String, String)>,
+    dry_run: bool,
+    installer: F,
+) -> Result<(), AppError>
+where
+    F: Fn(bool) -> Result<(), AppError>,
+{
+    if command_exists(command_name) {
+        info(&format!("{} is already installed", command_name));
+        changes.push((command_name.to_string(), "skipped".to_string()));
+        return Ok(());
+    }
+
+    info(&format!("Installing {}", command_name));
+    installer(dry_run)?;
+    let (change, action) = if dry_run {
+        (format!("Would install {}", command_name), "would-install")
+    } else {
+        (format!("Installed {}", command_name), "installed")
+    };
+    record_change(change);
+    changes.push((command_name.to_string(), action.to_string()));
+    Ok(())
+}
+
+struct NginxMirrors {
+    base: String,
+    packages: String,
+}
+
+impl NginxMirrors {
+    fn resolve(nginx: Option<String>, apt: Option<String>) -> Result<Self, AppError> {
+        let base = match nginx {
+            Some(url) => validate_mirror_url("--mirror-nginx", &url)?,
+            None => DEFAULT_NGINX_MIRROR.to_string(),
+        };
+        let packages = match apt {
+            Some(url) => validate_mirror_url("--mirror-apt", &url)?,
+            None => format!("{}/packages", base),
+        };
+        Ok(Self { base, packages })
+    }
+
+    fn packages_host(&self) -> &str {
+        backend_host(&self.packages).unwrap_or("nginx.org")
+    }
+}
+
+fn validate_hostname(flag: &str, value: &str) -> Result<String, AppError> {
+    let host = value.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = host.len() <= 253
+        && host.contains('.')
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    if !valid {
+        return Err(AppError::Validation(format!(
+            "{} must be a hostname, got {}",
+            flag, value
+        )));
+    }
+    Ok(host)
+}
+
+fn validate_mirror_url(flag: &str, url: &str) -> Result<String, AppError> {
+    let url = url.trim().trim_end_matches('/');
+    let valid = url.split_once("://").is_some_and(|(scheme, rest)| {
+        matches!(scheme, "http" | "https")
+            && !rest.is_empty()
+            && !rest.starts_with('/')
+            && !rest.contains(char::is_whitespace)
+    });
+    if !valid {
+        return Err(AppError::Validation(format!(
+            "{} must be an http(s) URL, got {}",
+            flag, url
+        )));
+    }
+    Ok(url.to_string())
+}
+
+fn install_nginx_official(
+    runner: &dyn CommandRunner,
+    mirrors: &NginxMirrors,
+    rsa_key_sha256: Option<&str>,
+    retries: u32,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let os_id = read_os_id()?;
+    let base_id = if is_supported_os(&os_id) {
+        os_id
+    } else {
+        let base = read_os_like()?
+            .into_iter()
+            .find(|like| is_supported_os(like))
+            .ok_or(AppError::Unsupported(format!(
+                "Unsupported OS for nginx install: {}",
+                os_id
+            )))?;
+        info(&format!("Assuming {} base for {}", base, os_id));
+        base
+    };
+    match base_id.as_str() {
+        "debian" => install_nginx_debian_like(runner, mirrors, "debian", retries, dry_run),
+        "ubuntu" => install_nginx_debian_like(runner, mirrors, "ubuntu", retries, dry_run),
+        "alpine" => install_nginx_alpine(runner, mirrors, rsa_key_sha256, retries, dry_run),
+        _ => Err(AppError::Unsupported(format!(
+            "Unsupported OS for nginx install: {}",
+            base_id
+        ))),
+    }
+}
+
+fn is_supported_os(os_id: &str) -> bool {
+    matches!(os_id, "debian" | "ubuntu" | "alpine")
+}
+
+fn install_nginx_debian_like(
+    runner: &dyn CommandRunner,
+    mirrors: &NginxMirrors,
+    os_id: &str,
+    retries: u32,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let keyring_pkg = if os_id == "ubuntu" {
+        "ubuntu-keyring"
+    } else {
+        "debian-archive-keyring"
+    };
+    run_cmd(
+        runner,
+        "apt",
+        &[
+            "install",
+            "-y",
+            "curl",
+            "gnupg2",
+            "ca-certificates",
+            "lsb-release",
+            keyring_pkg,
+        ],
+        dry_run,
+    )?;
+
+    let key_url = format!("{}/keys/nginx_signing.key", mirrors.base);
+    with_retries(retries, "Downloading nginx signing key", || {
+        run_cmd(
+            runner,
+            "curl",
+            &["-o", "/tmp/nginx_signing.key", &key_url],
+            dry_run,
+        )
+    })?;
+    verify_nginx_signing_key(runner, Path::new("/tmp/nginx_signing.key"), dry_run)?;
+    run_cmd(
+        runner,
+        "gpg",
+        &[
+            "--dearmor",
+            "-o",
+            "/usr/share/keyrings/nginx-archive-keyring.gpg",
+            "/tmp/nginx_signing.key",
+        ],
+        dry_run,
+    )?;
+
+    let codename = read_os_codename(runner)?;
+    let repo_line = format!(
+        "deb [signed-by=/usr/share/keyrings/nginx-archive-keyring.gpg] {}/mainline/{os_id} {codename} nginx\n",
+        mirrors.packages
+    );
+    let list_path = Path::new("/etc/apt/sources.list.d/nginx.list");
+    let pin_path = Path::new("/etc/apt/preferences.d/99nginx");
+    ensure_clobber_allowed(list_path)?;
+    ensure_clobber_allowed(pin_path)?;
+    if dry_run {
+        info("[dry-run] Would write /etc/apt/sources.list.d/nginx.list");
+        info("[dry-run] Would write /etc/apt/preferences.d/99nginx");
+    } else {
+        write_atomic(list_path, stamp_managed(repo_line))
+            .map_err(|e| AppError::Io(format!("Failed to write nginx.list: {e}")))?;
+        let pin = format!(
+            "Package: *\nPin: origin {}\nPin: release o=nginx\nPin-Priority: 900\n",
+            mirrors.packages_host()
+        );
+        write_atomic(pin_path, stamp_managed(pin))
+            .map_err(|e| AppError::Io(format!("Failed to write 99nginx: {e}")))?;
+    }
+
+    run_cmd(runner, "apt", &["update"], dry_run)?;
+    run_cmd(runner, "apt", &["install", "-y", "nginx"], dry_run)?;
+    Ok(())
+}
+
+fn install_nginx_alpine(
+    runner: &dyn CommandRunner,
+    mirrors: &NginxMirrors,
+    rsa_key_sha256: Option<&str>,
+    retries: u32,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    // The apk key is a bare RSA key with no signature to check, so the only
+    // trust anchor is a pinned digest; --nginx-key-sha256 overrides it after
+    // nginx.org rotates the key.
+    let (algorithm, rsa_key_digest) = match rsa_key_sha256 {
+        Some(digest) => ("sha256", digest),
+        None => ("sha512", NGINX_SIGNING_RSA_SHA512),
+    };
+    run_cmd(
+        runner,
+        "apk",
+        &["add", "openssl", "curl", "ca-certificates"],
+        dry_run,
+    )?;
+
+    let release = fs::read_to_string("/etc/alpine-release")
+        .map_err(|e| AppError::Io(format!("Failed to read /etc/alpine-release: {e}")))?;
+    let version = release
+        .trim()
+        .split('.')
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(".");
+    let repo_line = format!(
+        "@nginx {}/mainline/alpine/v{}/main\n",
+        mirrors.packages, version
+    );
+
+    if dry_run {
+        info("[dry-run] Would append nginx repo to /etc/apk/repositories");
+    } else {
+        let repos_path = "/etc/apk/repositories";
+        let mut repos = fs::read_to_string(repos_path)
+            .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", repos_path)))?;
+        if !repos.contains(&repo_line) {
+            if !repos.ends_with('\n') {
+                repos.push('\n');
+            }
+            repos.push_str(&repo_line);
+            ensure_clobber_allowed(Path::new(repos_path))?;
+            write_atomic(Path::new(repos_path), repos)
+                .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", repos_path)))?;
+        }
+    }
+
+    let key_url = format!("{}/keys/nginx_signing.rsa.pub", mirrors.base);
+    with_retries(retries, "Downloading nginx signing key", || {
+        run_cmd(
+            runner,
+            "curl",
+            &["-o", "/tmp/nginx_signing.rsa.pub", &key_url],
+            dry_run,
+        )
+    })?;
+    verify_nginx_signing_rsa_key(
+        runner,
+        Path::new("/tmp/nginx_signing.rsa.pub"),
+        algorithm,
+        rsa_key_digest,
+        dry_run,
+    )?;
+    if dry_run {
+        info("[dry-run] Would move nginx signing key to /etc/apk/keys/");
+    } else {
+        with_retries(retries, "Moving nginx signing key", || {
+            fs::rename(
+                "/tmp/nginx_signing.rsa.pub",
+                "/etc/apk/keys/nginx_signing.rsa.pub",
+            )
+            .map_err(|e| AppError::Io(format!("Failed to move nginx signing key: {e}")))
+        })?;
+    }
+
+    run_cmd(runner, "apk", &["add", "nginx@nginx"], dry_run)?;
+    Ok(())
+}
+
+fn purge_cache_dir(cache_dir: &Path, dry_run: bool) {
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would remove cache dir after failure: {}",
+            cache_dir.display()
+        ));
+        return;
+    }
+    if !cache_dir.exists() {
+        return;
+    }
+    match fs::remove_dir_all(cache_dir) {
+        Ok(()) => info(&format!("Removed cache dir: {}", cache_dir.display())),
+        Err(e) => warn(&format!(
+            "Failed to remove cache dir {}: {e}",
+            cache_dir.display()
+        )),
+    }
+}
+
+fn with_retries<T>(
+    retries: u32,
+    label: &str,
+    mut action: impl FnMut() -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    let mut attempt = 0;
+    loop {
+        if retries > 0 {
+            info(&format!(
+                "{} (attempt {}/{})",
+                label,
+                attempt + 1,
+                retries + 1
+            ));
+        }
+        match action() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retries => {
+                let delay = Duration::from_secs(1 << attempt.min(5));
+                warn(&format!("{}, retrying in {}s", err, delay.as_secs()));
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn verify_nginx_signing_key(
+    runner: &dyn CommandRunner,
+    key_path: &Path,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would verify nginx signing key fingerprints: {}",
+            NGINX_SIGNING_KEY_FINGERPRINTS.join(", ")
+        ));
+        return Ok(());
+    }
+
+    let output = runner
+        .output(
+            Command::new("gpg")
+                .arg("--show-keys")
+                .arg("--with-colons")
+                .arg(key_path),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run gpg --show-keys: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::ExternalCommand(format!(
+            "Failed to read nginx signing key {}",
+            key_path.display()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fingerprints = primary_key_fingerprints(&stdout);
+    let unknown: Vec<&str> = fingerprints
+        .iter()
+        .copied()
+        .filter(|fpr| {
+            !NGINX_SIGNING_KEY_FINGERPRINTS
+                .iter()
+                .any(|known| fpr.eq_ignore_ascii_case(known))
+        })
+        .collect();
+    if fingerprints.is_empty() || !unknown.is_empty() {
+        let _ = fs::remove_file(key_path);
+        return Err(AppError::Validation(format!(
+            "nginx signing key fingerprint mismatch: expected only {}, found {}",
+            NGINX_SIGNING_KEY_FINGERPRINTS.join(", "),
+            if unknown.is_empty() {
+                "no keys".to_string()
+            } else {
+                unknown.join(", ")
+            }
+        )));
+    }
+    success("nginx signing key fingerprints verified");
+    Ok(())
+}
+
+// `fpr` records follow both `pub` and `sub` records; only primary keys are
+// pinned, so subkey fingerprints are skipped.
+fn primary_key_fingerprints(colons: &str) -> Vec<&str> {
+    let mut fingerprints = Vec::new();
+    let mut in_primary = false;
+    for line in colons.lines() {
+        let mut fields = line.split(':');
+        match fields.next() {
+            Some("pub") => in_primary = true,
+            Some("sub") | Some("ssb") | Some("sec") => in_primary = false,
+            Some("fpr") if in_primary => {
+                if let Some(fpr) = fields.nth(8) {
+                    fingerprints.push(fpr);
+                }
+                in_primary = false;
+            }
+            _ => {}
+        }
+    }
+    fingerprints
+}
+
+fn verify_nginx_signing_rsa_key(
+    runner: &dyn CommandRunner,
+    key_path: &Path,
+    algorithm: &str,
+    expected: &str,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let label = algorithm.to_ascii_uppercase().replace("SHA", "SHA-");
+    if dry_run {
+        info(&format!(
+            "[dry-run] Would verify nginx signing key {}: {}",
+            label, expected
+        ));
+        return Ok(());
+    }
+
+    let output = runner
+        .output(
+            Command::new("openssl")
+                .arg("dgst")
+                .arg(format!("-{algorithm}"))
+                .arg("-r")
+                .arg(key_path),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run openssl dgst: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::ExternalCommand(format!(
+            "Failed to hash nginx signing key {}",
+            key_path.display()
+        )));
+    }
+    let actual = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if actual != expected {
+        let _ = fs::remove_file(key_path);
+        return Err(AppError::Validation(format!(
+            "nginx signing key {} mismatch: expected {}, got {}",
+            label,
+            expected,
+            if actual.is_empty() {
+                "nothing"
+            } else {
+                &actual
+            }
+        )));
+    }
+    success(&format!("nginx signing key {} verified", label));
+    Ok(())
+}
+
+fn parse_sha256(flag: &str, value: &str) -> Result<String, AppError> {
+    let digest = value.trim().to_ascii_lowercase();
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::Validation(format!(
+            "{} expects 64 hex characters, got {:?}",
+            flag, value
+        )));
+    }
+    Ok(digest)
+}
+
+fn setup_acme_renew_cron(
+    runner: &dyn CommandRunner,
+    acme_bin: &Path,
+    acme_homes: &AcmeHomes,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    let mut cron_args = format!("--cron --home {}", acme_homes.home.display());
+    if let Some(config_home) = &acme_homes.config_home {
+        cron_args.push_str(&format!(" --config-home {}", config_home.display()));
+    }
+    if let Some(cert_home) = &acme_homes.cert_home {
+        cron_args.push_str(&format!(" --cert-home {}", cert_home.display()));
+    }
+    let cron_line = format!(
+        "0 0 1,16 * * /bin/sh {} {} >/dev/null 2>&1",
+        acme_bin.display(),
+        cron_args
+    );
+    let container = is_container();
+    if !command_exists("crontab") {
+        if container {
+            warn(&format!(
+                "crontab not found in this container, schedule renewals from the host: {}",
+                cron_line
+            ));
+        } else {
+            info("crontab not found, skipping renew cron setup");
+        }
+        return Ok(());
+    }
+
+    step("Setting up acme renew cron");
+    let mut content = read_crontab(runner)?;
+    if content.contains(&cron_line) {
+        info("acme renew cron already exists");
+        return Ok(());
+    }
+
+    record_change(format!("crontab: {}", cron_line));
+    if dry_run {
+        info(&format!("[dry-run] Would ensure cron: {}", cron_line));
+        return Ok(());
+    }
+
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&cron_line);
+    content.push('\n');
+    write_crontab(runner, &content)?;
+
+    success("acme renew cron added");
+    if container {
+        warn(
+            "Inside a container the cron entry only fires while a cron daemon runs, and is lost on recreate unless /var/spool/cron is persisted",
+        );
+    }
+    Ok(())
+}
+
+fn setup_cert_deploy_cron(
+    runner: &dyn CommandRunner,
+    domain: &str,
+    deploy_args: &[String],
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if !command_exists("crontab") {
+        info("crontab not found, skipping deploy cron setup");
+        return Ok(());
+    }
+
+    step("Setting up cert deploy cron");
+    let exe = env::current_exe()
+        .map_err(|e| AppError::Io(format!("Failed to resolve current executable: {e}")))?;
+    let marker = format!("# emby-proxy-cli deploy {}", domain);
+    let cron_line = format!(
+        "30 0 1,16 * * {} {} >/dev/null 2>&1 {}",
+        shell_quote(&exe.display().to_string()),
+        deploy_args
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+        marker
+    );
+
+    let existing = read_crontab(runner)?;
+    if existing.lines().any(|line| line == cron_line) {
+        info("cert deploy cron already exists");
+        return Ok(());
+    }
+
+    record_change(format!("crontab: {}", cron_line));
+    if dry_run {
+        info(&format!("[dry-run] Would ensure cron: {}", cron_line));
+        return Ok(());
+    }
+    let mut content: String = existing
+        .lines()
+        .filter(|line| !line.ends_with(&marker))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    content.push_str(&cron_line);
+    content.push('\n');
+    write_crontab(runner, &content)?;
+
+    success("cert deploy cron added");
+    Ok(())
+}
+
+fn shell_quote(value: &str) -> String {
+    let safe = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    if safe && !value.is_empty() {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+fn read_crontab(runner: &dyn CommandRunner) -> Result<String, AppError> {
+    let existing = runner
+        .output(Command::new("crontab").arg("-l"))
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to read crontab: {e}")))?;
+    Ok(String::from_utf8_lossy(&existing.stdout).to_string())
+}
+
+fn write_crontab(runner: &dyn CommandRunner, content: &str) -> Result<(), AppError> {
+    let status = runner
+        .status_with_input(
+            Command::new("crontab")
+                .arg("-")
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+            content.as_bytes(),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to write crontab: {e}")))?;
+    if !status.success() {
+        return Err(AppError::ExternalCommand(
+            "Failed to update crontab".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+static RELOAD_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(60);
+
+pub fn set_reload_timeout(seconds: u64) {
+    RELOAD_TIMEOUT_SECS.store(seconds, Ordering::Relaxed);
+}
+
+fn reload_timeout() -> Duration {
+    Duration::from_secs(RELOAD_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+const DEFAULT_NGINX_MIRROR: &str = "https://nginx.org";
+const SETUP_COMPONENTS: [&str; 3] = ["zsh", "cron", "nginx"];
+
+fn confirm_with_timeout(prompt: &str, timeout: Duration, dry_run: bool) -> Result<bool, AppError> {
+    if dry_run {
+        info(&format!("[dry-run] Would prompt: {}", prompt));
+        return Ok(false);
+    }
+
+    info(&format!(
+        "{} (y/N) [timeout {}s]",
+        prompt,
+        timeout.as_secs()
+    ));
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = String::new();
+        let _ = std::io::stdin().read_line(&mut input);
+        let _ = tx.send(input);
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(input) => {
+            let trimmed = input.trim();
+            Ok(trimmed.eq_ignore_ascii_case("y") || trimmed.eq_ignore_ascii_case("yes"))
+        }
+        Err(mpsc::RecvTimeoutError::Timeout) => Ok(false),
+        Err(_) => Err(AppError::Io("Failed to read input".to_string())),
+    }
+}
+
+fn command_exists(command_name: &str) -> bool {
+    if let Ok(path_var) = env::var("PATH") {
+        for dir in path_var.split(':') {
+            let candidate = Path::new(dir).join(command_name);
+            if candidate.exists() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn run_cmd(
+    runner: &dyn CommandRunner,
+    cmd: &str,
+    args: &[&str],
+    dry_run: bool,
+) -> Result<(), AppError> {
+    if dry_run {
+        info(&format!("[dry-run] Would run: {} {}", cmd, args.join(" ")));
+        return Ok(());
+    }
+    let status = runner
+        .status(
+            Command::new(cmd)
+                .args(args)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run {}: {e}", cmd)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(AppError::ExternalCommand(format!(
+            "Command failed: {}",
+            cmd
+        )))
+    }
+}
+
+fn read_os_id() -> Result<String, AppError> {
+    let content = fs::read_to_string("/etc/os-release")
+        .map_err(|e| AppError::Io(format!("Failed to read /etc/os-release: {e}")))?;
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("ID=") {
+            return Ok(value.trim_matches('"').to_string());
+        }
+    }
+    Err(AppError::Unsupported(
+        "OS ID not found in /etc/os-release".to_string(),
+    ))
+}
+
+fn cert_root(cert_base_dir: Option<PathBuf>, env_overrides: &HashMap<String, String>) -> PathBuf {
+    resolve_optional_path(cert_base_dir, env_overrides, "CERT_BASE_DIR")
+        .unwrap_or_else(|| default_cert_root().to_path_buf())
+}
+
+fn default_cert_root() -> &'static Path {
+    match read_os_id().as_deref() {
+        Ok("alpine") => Path::new(ALPINE_CERT_ROOT),
+        _ => Path::new(DEFAULT_CERT_ROOT),
+    }
+}
+
+fn read_os_like() -> Result<Vec<String>, AppError> {
+    let content = fs::read_to_string("/etc/os-release")
+        .map_err(|e| AppError::Io(format!("Failed to read /etc/os-release: {e}")))?;
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("ID_LIKE=") {
+            return Ok(value
+                .trim_matches('"')
+                .split_whitespace()
+                .map(str::to_string)
+                .collect());
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn read_os_codename(runner: &dyn CommandRunner) -> Result<String, AppError> {
+    let content = fs::read_to_string("/etc/os-release")
+        .map_err(|e| AppError::Io(format!("Failed to read /etc/os-release: {e}")))?;
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("UBUNTU_CODENAME=") {
+            return Ok(value.trim_matches('"').to_string());
+        }
+    }
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("VERSION_CODENAME=") {
+            return Ok(value.trim_matches('"').to_string());
+        }
+    }
+    let output = runner
+        .output(Command::new("lsb_release").arg("-cs"))
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to run lsb_release: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::ExternalCommand(
+            "Failed to read OS codename".to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn ensure_linux() -> Result<(), AppError> {
+    if std::env::consts::OS == "linux" {
+        return Ok(());
+    }
+    let version = read_uname().unwrap_or_else(|| std::env::consts::OS.to_string());
+    Err(AppError::Unsupported(format!(
+        "Unsupported platform: not a Linux distribution. System: {}",
+        version
+    )))
+}
+
+fn read_uname() -> Option<String> {
+    let output = Command::new("uname").arg("-a").output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Names what gave the container away so skipped steps can say why.
+fn container_hint() -> Option<String> {
+    if let Ok(kind) = env::var("container")
+        && !kind.is_empty()
+    {
+        return Some(format!("container={}", kind));
+    }
+    if let Some(marker) = CONTAINER_MARKERS
+        .iter()
+        .find(|marker| Path::new(marker).exists())
+    {
+        return Some(marker.to_string());
+    }
+    let cgroup = fs::read_to_string("/proc/1/cgroup").ok()?;
+    CONTAINER_CGROUP_HINTS
+        .iter()
+        .find(|hint| cgroup.contains(*hint))
+        .map(|hint| format!("/proc/1/cgroup mentions {}", hint))
+}
+
+fn is_container() -> bool {
+    container_hint().is_some()
+}
+
+fn ensure_root(runner: &dyn CommandRunner) -> Result<(), AppError> {
+    let output = runner
+        .output(Command::new("id").arg("-u"))
+        .map_err(|e| AppError::ExternalCommand(format!("Failed to check uid: {e}")))?;
+    let uid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if uid != "0" {
+        return Err(AppError::NotRoot);
+    }
+    Ok(())
+}
+
+fn print_summary(changes: &[(String, String)], elapsed: std::time::Duration) {
+    step("Summary");
+    if changes.is_empty() {
+        info("No changes were made");
+    } else {
+        let rows: Vec<Vec<String>> = changes
+            .iter()
+            .map(|(component, action)| vec![component.clone(), action.clone()])
+            .collect();
+        print_table(&["Component", "Action"], &rows);
+    }
+    let seconds = elapsed.as_secs();
+    let minutes = seconds / 60;
+    let remainder = seconds % 60;
+    info(&format!("Execution time: {}m {}s", minutes, remainder));
+}
+
+struct AcmeHomes {
+    home: PathBuf,
+    config_home: Option<PathBuf>,
+    cert_home: Option<PathBuf>,
+}
+
+impl AcmeHomes {
+    fn cert_root(&self) -> &Path {
+        self.cert_home.as_deref().unwrap_or(&self.home)
+    }
+
+    fn apply(&self, cmd: &mut Command) {
+        if let Some(config_home) = &self.config_home {
+            cmd.arg("--config-home").arg(config_home);
+        }
+        if let Some(cert_home) = &self.cert_home {
+            cmd.arg("--cert-home").arg(cert_home);
+        }
+    }
+}
+
+struct CertVariant {
+    keylength: &'static str,
+    cache_suffix: &'static str,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+fn cert_variants(cert_path: &Path, key_path: &Path, dual_cert: bool) -> Vec<CertVariant> {
+    if !dual_cert {
+        return vec![CertVariant {
+            keylength: "ec-256",
+            cache_suffix: "_ecc",
+            cert: cert_path.to_path_buf(),
+            key: key_path.to_path_buf(),
+        }];
+    }
+    vec![
+        CertVariant {
+            keylength: "ec-256",
+            cache_suffix: "_ecc",
+            cert: variant_path(cert_path, "ec"),
+            key: variant_path(key_path, "ec"),
+        },
+        CertVariant {
+            keylength: "2048",
+            cache_suffix: "",
+            cert: variant_path(cert_path, "rsa"),
+            key: variant_path(key_path, "rsa"),
+        },
+    ]
+}
+
+fn variant_path(path: &Path, variant: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, variant, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, variant),
+    };
+    path.with_file_name(name)
+}
+
+fn parse_bind_address(value: &str) -> Result<String, AppError> {
+    let trimmed = value.trim();
+    let inner = trimmed
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(trimmed);
+    match inner.parse::<IpAddr>() {
+        Ok(IpAddr::V4(addr)) => Ok(format!("{}:", addr)),
+        Ok(IpAddr::V6(addr)) => Ok(format!("[{}]:", addr)),
+        Err(_) => Err(AppError::Validation(format!(
+            "Invalid bind address: {} (expected IPv4 or IPv6)",
+            value
+        ))),
+    }
+}
+
+fn render_default_return(action: &[String]) -> Result<String, AppError> {
+    match action {
+        [] => Ok("444".to_string()),
+        [kind] if kind == "close" => Ok("444".to_string()),
+        [kind, url] if kind == "redirect" => {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(AppError::Validation(format!(
+                    "Redirect target must start with http:// or https://: {}",
+                    url
+                )));
+            }
+            Ok(format!("301 {}", url))
+        }
+        [kind, code] if kind == "status" => match code.parse::<u16>() {
+            Ok(code) if (100..=599).contains(&code) => Ok(code.to_string()),
+            _ => Err(AppError::Validation(format!(
+                "Invalid status code: {} (expected 100-599)",
+                code
+            ))),
+        },
+        _ => Err(AppError::Validation(format!(
+            "Invalid default action: {} (expected close, redirect <url> or status <code>)",
+            action.join(" ")
+        ))),
+    }
+}
+
+fn resolve_listen_addrs(
+    bind: Option<String>,
+    ipv6: Option<bool>,
+    ipv6only: Option<&str>,
+) -> Result<Vec<(String, String)>, AppError> {
+    let ipv6only_param = ipv6only
+        .map(|value| format!(" ipv6only={}", value))
+        .unwrap_or_default();
+    if let Some(bind) = bind {
+        let addr = parse_bind_address(&bind)?;
+        let params = if addr == "[::]:" {
+            ipv6only_param
+        } else {
+            String::new()
+        };
+        return Ok(vec![(addr, params)]);
+    }
+    let ipv6 = ipv6.unwrap_or_else(ipv6_available);
+    let mut addrs = Vec::new();
+    if !ipv6 || ipv6only != Some("off") {
+        addrs.push((String::new(), String::new()));
+    }
+    if ipv6 {
+        addrs.push(("[::]:".to_string(), ipv6only_param));
+    }
+    Ok(addrs)
+}
+
+fn ipv6_available() -> bool {
+    fs::read_to_string("/proc/net/if_inet6").is_ok_and(|content| !content.trim().is_empty())
+}
+
+fn render_listen(addrs: &[(String, String)], suffix: &str) -> String {
+    addrs
+        .iter()
+        .map(|(addr, params)| format!("    listen {}{}{};\n", addr, suffix, params))
+        .collect()
+}
+
+fn render_hsts(
+    enabled: bool,
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+) -> Result<String, AppError> {
+    Ok(
+        match hsts_value(enabled, max_age, include_subdomains, preload)? {
+            Some(value) => format!(
+                "    add_header Strict-Transport-Security \"{}\" always;\n",
+                value
+            ),
+            None => String::new(),
+        },
+    )
+}
+
+fn render_apache_hsts(
+    enabled: bool,
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+) -> Result<String, AppError> {
+    Ok(
+        match hsts_value(enabled, max_age, include_subdomains, preload)? {
+            Some(value) => format!(
+                "    Header always set Strict-Transport-Security \"{}\"\n",
+                value
+            ),
+            None => String::new(),
+        },
+    )
+}
+
+fn render_caddy_hsts(
+    enabled: bool,
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+) -> Result<String, AppError> {
+    Ok(
+        match hsts_value(enabled, max_age, include_subdomains, preload)? {
+            Some(value) => format!("\theader Strict-Transport-Security \"{}\"\n", value),
+            None => String::new(),
+        },
+    )
+}
+
+fn hsts_value(
+    enabled: bool,
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+) -> Result<Option<String>, AppError> {
+    if !enabled {
+        return Ok(None);
+    }
+    if preload && (!include_subdomains || max_age < HSTS_PRELOAD_MIN_AGE) {
+        return Err(AppError::Validation(format!(
+            "--hsts-preload requires --hsts-include-subdomains and --hsts-max-age >= {}",
+            HSTS_PRELOAD_MIN_AGE
+        )));
+    }
+    let mut value = format!("max-age={}", max_age);
+    if include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if preload {
+        value.push_str("; preload");
+    }
+    Ok(Some(value))
+}
+
+fn resolve_stub_status(
+    path: Option<String>,
+    allow: Vec<String>,
+    env_overrides: &HashMap<String, String>,
+) -> Result<String, AppError> {
+    let Some(path) = path.or_else(|| resolve_from_envs(env_overrides, &["STUB_STATUS"])) else {
+        return Ok(String::new());
+    };
+    let path = parse_uri_prefix("--stub-status", &path)?;
+    let allow = if allow.is_empty() {
+        resolve_from_envs(env_overrides, &["STUB_STATUS_ALLOW"])
+            .map(|value| split_list(&value))
+            .unwrap_or_else(|| DEFAULT_STUB_STATUS_ALLOW.map(str::to_string).to_vec())
+    } else {
+        allow
+    };
+    render_stub_status(&path, &allow)
+}
+
+fn render_stub_status(path: &str, allow: &[String]) -> Result<String, AppError> {
+    let mut block = format!("    location = {} {{\n        stub_status;\n", path);
+    for source in allow {
+        let source = source.trim();
+        let (addr, prefix) = match source.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (source, None),
+        };
+        let valid = addr.parse::<IpAddr>().is_ok_and(|ip| {
+            let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+            prefix.is_none_or(|prefix| prefix.parse::<u8>().is_ok_and(|n| n <= max_prefix))
+        });
+        if !valid {
+            return Err(AppError::Validation(format!(
+                "--stub-status-allow expects an IP address or CIDR, got {}",
+                source
+            )));
+        }
+        block.push_str(&format!("        allow {};\n", source));
+    }
+    block.push_str("        deny all;\n    }\n\n");
+    Ok(block)
+}
+
+fn parse_country_codes(codes: &[String]) -> Result<Vec<String>, AppError> {
+    let mut parsed: Vec<String> = Vec::new();
+    for code in codes {
+        let code = code.trim().to_ascii_uppercase();
+        if code.len() != 2 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(AppError::Validation(format!(
+                "--geo-allow expects two-letter country codes, got {}",
+                code
+            )));
+        }
+        if !parsed.contains(&code) {
+            parsed.push(code);
+        }
+    }
+    Ok(parsed)
+}
+
+fn render_geoip(db: &Path, countries: &[String], name: &str) -> (String, String) {
+    let allowed = countries
+        .iter()
+        .map(|code| format!("    {} 1;\n", code))
+        .collect::<String>();
+    let http = format!(
+        "geoip2 {} {{\n    $geoip2_data_country_code country iso_code;\n}}\n\nmap $geoip2_data_country_code ${}_geo_allowed {{\n    default 0;\n{}}}\n\n",
+        db.display(),
+        name,
+        allowed
+    );
+    let gate = format!(
+        "    if (${}_geo_allowed = 0) {{\n        return 403;\n    }}\n",
+        name
+    );
+    (http, gate)
+}
+
+fn render_certificates(variants: &[CertVariant]) -> String {
+    variants
+        .iter()
+        .map(|variant| {
+            format!(
+                "    ssl_certificate {};\n    ssl_certificate_key {};\n",
+                variant.cert.display(),
+                variant.key.display()
+            )
+        })
+        .collect()
+}
+
+fn render_inline_certificates(variants: &[CertVariant]) -> Result<String, AppError> {
+    variants
+        .iter()
+        .map(|variant| {
+            Ok(format!(
+                "    ssl_certificate \"data:{}\";\n    ssl_certificate_key \"data:{}\";\n",
+                read_pem(&variant.cert, "CERTIFICATE")?,
+                read_pem(&variant.key, "PRIVATE KEY")?
+            ))
+        })
+        .collect()
+}
+
+fn read_pem(path: &Path, label: &str) -> Result<String, AppError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+    let content = content.trim();
+    let valid = content.starts_with("-----BEGIN ")
+        && content.ends_with("-----")
+        && content.lines().any(|line| {
+            line.starts_with("-----BEGIN ") && line.ends_with(&format!("{}-----", label))
+        });
+    if !valid {
+        return Err(AppError::Validation(format!(
+            "{} does not contain a PEM {}",
+            path.display(),
+            label.to_lowercase()
+        )));
+    }
+    Ok(format!("{}\n", content))
+}
+
+fn check_key_matches_cert(
+    runner: &dyn CommandRunner,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(), AppError> {
+    let public_key = |args: &[&str], path: &Path| -> Result<Vec<u8>, AppError> {
+        let output = runner
+            .output(Command::new("openssl").args(args).arg(path))
+            .map_err(|e| AppError::ExternalCommand(format!("Failed to run openssl: {e}")))?;
+        if !output.status.success() {
+            return Err(AppError::ExternalCommand(format!(
+                "Failed to read the public key from {}",
+                path.display()
+            )));
+        }
+        Ok(output.stdout)
+    };
+    let cert_key = public_key(&["x509", "-noout", "-pubkey", "-in"], cert_path)?;
+    let key = public_key(&["pkey", "-pubout", "-in"], key_path)?;
+    if cert_key != key {
+        return Err(AppError::Validation(format!(
+            "{} does not match the certificate {}",
+            key_path.display(),
+            cert_path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn restrict_permissions(path: &Path) -> Result<(), AppError> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| AppError::Io(format!("Failed to chmod {}: {e}", path.display())))
+}
+
+enum CertNameScheme {
+    Domain,
+    Certbot,
+    Custom { cert: String, key: String },
+}
+
+impl CertNameScheme {
+    fn file_names(&self, domain: &str) -> (String, String) {
+        match self {
+            CertNameScheme::Domain => (format!("{}.cer", domain), format!("{}.key", domain)),
+            CertNameScheme::Certbot => ("fullchain.pem".to_string(), "privkey.pem".to_string()),
+            CertNameScheme::Custom { cert, key } => (cert.clone(), key.clone()),
+        }
+    }
+}
+
+fn resolve_cert_name_scheme(
+    scheme: Option<String>,
+    cert_filename: Option<String>,
+    key_filename: Option<String>,
+    env_overrides: &HashMap<String, String>,
+) -> Result<CertNameScheme, AppError> {
+    let scheme = scheme
+        .or_else(|| resolve_from_envs(env_overrides, &["CERT_NAME_SCHEME"]))
+        .unwrap_or_else(|| "domain".to_string());
+    let cert_filename =
+        cert_filename.or_else(|| resolve_from_envs(env_overrides, &["CERT_FILENAME"]));
+    let key_filename = key_filename.or_else(|| resolve_from_envs(env_overrides, &["KEY_FILENAME"]));
+    if scheme != "custom" && (cert_filename.is_some() || key_filename.is_some()) {
+        return Err(AppError::Validation(
+            "--cert-filename/--key-filename require --cert-name-scheme custom".to_string(),
+        ));
+    }
+    match scheme.as_str() {
+        "domain" => Ok(CertNameScheme::Domain),
+        "certbot" => Ok(CertNameScheme::Certbot),
+        "custom" => {
+            let (Some(cert), Some(key)) = (cert_filename, key_filename) else {
+                return Err(AppError::MissingInput(
+                    "--cert-name-scheme custom requires --cert-filename and --key-filename"
+                        .to_string(),
+                ));
+            };
+            for name in [&cert, &key] {
+                if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                    return Err(AppError::Validation(format!(
+                        "Invalid cert file name '{}': expected a plain file name",
+                        name
+                    )));
+                }
+            }
+            if cert == key {
+                return Err(AppError::Validation(
+                    "--cert-filename and --key-filename must differ".to_string(),
+                ));
+            }
+            Ok(CertNameScheme::Custom { cert, key })
+        }
+        other => Err(AppError::Validation(format!(
+            "Invalid CERT_NAME_SCHEME '{}': expected domain, certbot or custom",
+            other
+        ))),
+    }
+}
+
+fn resolve_cert_paths(
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+    cert_dir: Option<PathBuf>,
+    domain: Option<String>,
+    scheme: &CertNameScheme,
+) -> Result<(PathBuf, PathBuf), AppError> {
+    let (cert_path, key_path) = match (cert_path, key_path) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        (None, None) => {
+            let cert_dir = cert_dir.ok_or(AppError::MissingInput(
+                "cert_dir is required to derive cert paths".to_string(),
+            ))?;
+            let domain = domain.ok_or(AppError::MissingInput(
+                "domain is required to derive cert paths".to_string(),
+            ))?;
+            let (cert_name, key_name) = scheme.file_names(&domain);
+            (cert_dir.join(cert_name), cert_dir.join(key_name))
+        }
+        _ => {
+            return Err(AppError::Validation(
+                "Both cert and key paths must be set together".to_string(),
+            ));
+        }
+    };
+    ensure_file_path(&cert_path)?;
+    ensure_file_path(&key_path)?;
+    Ok((cert_path, key_path))
+}
+
+fn ensure_file_path(path: &Path) -> Result<(), AppError> {
+    if path.is_dir() {
+        return Err(AppError::Validation(format!(
+            "{} is a directory, expected a file path",
+            path.display()
+        )));
+    }
+    let mut ancestor = path.parent();
+    while let Some(dir) = ancestor {
+        if dir.as_os_str().is_empty() || dir.exists() {
+            break;
+        }
+        ancestor = dir.parent();
+    }
+    if let Some(dir) = ancestor
+        && !dir.as_os_str().is_empty()
+        && !dir.is_dir()
+    {
+        return Err(AppError::Validation(format!(
+            "Cannot create parent directory for {}: {} is not a directory",
+            path.display(),
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::runner::mock::RecordingRunner;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("emby-proxy-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_mode_restricts_replaced_world_readable_file() {
+        let dir = temp_dir("write-atomic-mode");
+        let path = dir.join("proxy.conf");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_atomic_mode(&path, "ssl_certificate_key data:...", Some(0o600)).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o600);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copy_cert_files_replaces_atomically_and_keeps_key_mode() {
+        let dir = temp_dir("copy-cert-files");
+        let (cert_src, key_src) = (dir.join("src.crt"), dir.join("src.key"));
+        fs::write(&cert_src, "new cert").unwrap();
+        fs::write(&key_src, "new key").unwrap();
+        fs::set_permissions(&key_src, fs::Permissions::from_mode(0o600)).unwrap();
+        let live = dir.join("live");
+        let (cert_dst, key_dst) = (live.join("cert.pem"), live.join("key.pem"));
+
+        copy_cert_files(&cert_src, &key_src, &cert_dst, &key_dst, false).unwrap();
+
+        assert_eq!(fs::read_to_string(&cert_dst).unwrap(), "new cert");
+        assert_eq!(fs::read_to_string(&key_dst).unwrap(), "new key");
+        let mode = fs::metadata(&key_dst).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o600);
+        let leftovers: Vec<_> = fs::read_dir(&live)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains(".tmp-"))
+            .collect();
+        assert!(leftovers.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn issue_args(dir: &Path) -> IssueCertArgs {
+        IssueCertArgs {
+            cf_token: Some("token".to_string()),
+            cf_account_id: Some("account".to_string()),
+            cf_zone_id: Some("zone".to_string()),
+            domain: Some("example.com".to_string()),
+            wildcard_domain: Some("*.example.com".to_string()),
+            acme_bin: Some(dir.join("acme.sh")),
+            acme_home: Some(dir.join("acme")),
+            cert_output_path: Some(dir.join("certs/example.com.cer")),
+            key_output_path: Some(dir.join("certs/example.com.key")),
+            no_cron: true,
+            ..IssueCertArgs::default()
+        }
+    }
+
+    fn acme_issue_argvs(
+        name: &str,
+        configure: impl FnOnce(&mut IssueCertArgs),
+    ) -> Vec<Vec<String>> {
+        let dir = temp_dir(name);
+        let mut args = issue_args(&dir);
+        configure(&mut args);
+        let runner = RecordingRunner::new();
+        issue_cert(&runner, &HashMap::new(), args, false, false).unwrap();
+        let acme = dir.join("acme.sh").display().to_string();
+        runner
+            .argvs("acme.sh")
+            .into_iter()
+            .filter(|argv| argv[1] == "--issue")
+            .map(|mut argv| {
+                assert_eq!(argv[0], acme);
+                argv.remove(0);
+                argv
+            })
+            .collect()
+    }
+
+    #[test]
+    fn issue_cert_runs_acme_with_default_arguments() {
+        let argvs = acme_issue_argvs("issue-default", |_| {});
+        assert_eq!(
+            argvs,
+            vec![strings(&[
+                "--issue",
+                "--force",
+                "-d",
+                "example.com",
+                "-d",
+                "*.example.com",
+                "--dns",
+                "dns_cf",
+                "--keylength",
+                "ec-256",
+            ])]
+        );
+    }
+
+    #[test]
+    fn issue_cert_passes_cloudflare_credentials_through_the_environment() {
+        let dir = temp_dir("issue-env");
+        let runner = RecordingRunner::new();
+        issue_cert(&runner, &HashMap::new(), issue_args(&dir), false, false).unwrap();
+        let issue = runner
+            .recorded()
+            .into_iter()
+            .find(|recorded| recorded.argv.get(1).is_some_and(|arg| arg == "--issue"))
+            .unwrap();
+        let mut envs = issue.envs;
+        envs.sort();
+        assert_eq!(
+            envs,
+            vec![
+                ("CF_Account_ID".to_string(), "account".to_string()),
+                ("CF_Token".to_string(), "token".to_string()),
+                ("CF_Zone_ID".to_string(), "zone".to_string()),
+            ]
+        );
+        assert!(!issue.argv.iter().any(|arg| arg.contains("token")));
+    }
+
+    #[test]
+    fn issue_cert_dual_cert_requests_ecc_then_rsa() {
+        let argvs = acme_issue_argvs("issue-dual", |args| args.dual_cert = true);
+        let keylengths: Vec<&str> = argvs
+            .iter()
+            .map(|argv| {
+                let index = argv.iter().position(|arg| arg == "--keylength").unwrap();
+                argv[index + 1].as_str()
+            })
+            .collect();
+        assert_eq!(keylengths, ["ec-256", "2048"]);
+    }
+
+    #[test]
+    fn issue_cert_dual_cert_installs_each_variant_to_its_own_paths() {
+        let dir = temp_dir("issue-dual-install");
+        let mut args = issue_args(&dir);
+        args.dual_cert = true;
+        let runner = RecordingRunner::new();
+        issue_cert(&runner, &HashMap::new(), args, false, false).unwrap();
+        let installs: Vec<Vec<String>> = runner
+            .argvs("acme.sh")
+            .into_iter()
+            .filter(|argv| argv[1] == "--install-cert")
+            .collect();
+        assert_eq!(installs.len(), 2);
+        let certs = dir.join("certs");
+        assert_eq!(
+            installs[0][2..],
+            strings(&[
+                "-d",
+                "example.com",
+                "--keylength",
+                "ec-256",
+                "--fullchain-file",
+                &certs.join("example.com.ec.cer").display().to_string(),
+                "--key-file",
+                &certs.join("example.com.ec.key").display().to_string(),
+            ])[..]
+        );
+        assert_eq!(installs[1][5], "2048");
+        assert_eq!(
+            installs[1][7],
+            certs.join("example.com.rsa.cer").display().to_string()
+        );
+    }
+
+    #[test]
+    fn issue_cert_challenge_alias_follows_every_domain() {
+        let argvs = acme_issue_argvs("issue-alias", |args| {
+            args.challenge_alias = Some("alias.example.net".to_string());
+        });
+        assert_eq!(
+            argvs[0][2..8],
+            strings(&[
+                "-d",
+                "example.com",
+                "--challenge-alias",
+                "alias.example.net",
+                "-d",
+                "*.example.com",
+            ])[..]
+        );
+        assert_eq!(
+            argvs[0][8..10],
+            strings(&["--challenge-alias", "alias.example.net"])[..]
+        );
+    }
+
+    #[test]
+    fn issue_cert_preferred_chain_is_trimmed() {
+        let argvs = acme_issue_argvs("issue-chain", |args| {
+            args.preferred_chain = Some(" ISRG Root X1 ".to_string());
+        });
+        assert!(
+            argvs[0].ends_with(&strings(&["--preferred-chain", "ISRG Root X1"])),
+            "{:?}",
+            argvs[0]
+        );
+    }
+
+    #[test]
+    fn issue_cert_passes_staging_and_other_acme_args_last() {
+        let argvs = acme_issue_argvs("issue-staging", |args| {
+            args.acme_args = strings(&["--staging", "--debug", "2"]);
+            args.wildcard_only = true;
+            args.skip_cache_removal = true;
+        });
+        assert_eq!(
+            argvs,
+            vec![strings(&[
+                "--issue",
+                "-d",
+                "*.example.com",
+                "--dns",
+                "dns_cf",
+                "--keylength",
+                "ec-256",
+                "--staging",
+                "--debug",
+                "2",
+            ])]
+        );
+    }
+
+    #[test]
+    fn issue_cert_dry_run_runs_no_acme_commands() {
+        let dir = temp_dir("issue-dry-run");
+        let runner = RecordingRunner::new();
+        issue_cert(&runner, &HashMap::new(), issue_args(&dir), false, true).unwrap();
+        assert!(runner.argvs("acme.sh").is_empty());
+    }
+
+    fn gpg_colons(fingerprints: &[&str]) -> String {
+        fingerprints
+            .iter()
+            .map(|fpr| {
+                format!(
+                    "pub:-:2048:1:{}:1:::-:::scSC::::::23::0:\nfpr:::::::::{}:\nuid:-::::1::x::nginx signing key <signing-key@nginx.com>::::::::::0:\nsub:-:2048:1:AAAA:1::::::e::::::23:\nfpr:::::::::0000000000000000000000000000000000000000:\n",
+                    &fpr[24..],
+                    fpr
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nginx_signing_key_with_only_published_keys_is_accepted() {
+        let dir = temp_dir("nginx-gpg-match");
+        let key = dir.join("nginx_signing.key");
+        fs::write(&key, "key").unwrap();
+        let runner =
+            RecordingRunner::new().with_stdout("gpg", &gpg_colons(&NGINX_SIGNING_KEY_FINGERPRINTS));
+        verify_nginx_signing_key(&runner, &key, false).unwrap();
+        assert!(key.exists());
+    }
+
+    #[test]
+    fn nginx_signing_key_with_an_extra_unknown_key_is_rejected_and_removed() {
+        let dir = temp_dir("nginx-gpg-extra");
+        let key = dir.join("nginx_signing.key");
+        fs::write(&key, "key").unwrap();
+        let attacker = "DEADBEEFDEADBEEFDEADBEEFDEADBEEFDEADBEEF";
+        let runner = RecordingRunner::new().with_stdout(
+            "gpg",
+            &gpg_colons(&[NGINX_SIGNING_KEY_FINGERPRINTS[0], attacker]),
+        );
+        let err = verify_nginx_signing_key(&runner, &key, false).unwrap_err();
+        assert!(err.to_string().contains(attacker), "{err}");
+        assert!(!key.exists());
+    }
+
+    const KEY_SHA256: &str = "3f1b2c9e0d4a5b6c7d8e9f00112233445566778899aabbccddeeff0011223344";
+
+    #[test]
+    fn nginx_signing_rsa_key_must_match_the_pinned_sha256() {
+        let dir = temp_dir("nginx-key-match");
+        let key = dir.join("nginx_signing.rsa.pub");
+        fs::write(&key, "key").unwrap();
+        let runner = RecordingRunner::new().with_stdout(
+            "openssl",
+            &format!("{} *{}\n", KEY_SHA256.to_uppercase(), key.display()),
+        );
+        verify_nginx_signing_rsa_key(&runner, &key, "sha256", KEY_SHA256, false).unwrap();
+        assert_eq!(
+            runner.argvs("openssl"),
+            vec![strings(&[
+                "openssl",
+                "dgst",
+                "-sha256",
+                "-r",
+                &key.display().to_string()
+            ])]
+        );
+        assert!(key.exists());
+    }
+
+    #[test]
+    fn nginx_signing_rsa_key_with_another_digest_is_rejected_and_removed() {
+        let dir = temp_dir("nginx-key-mismatch");
+        let key = dir.join("nginx_signing.rsa.pub");
+        fs::write(&key, "key").unwrap();
+        let other = "0".repeat(64);
+        let runner = RecordingRunner::new()
+            .with_stdout("openssl", &format!("{} *{}\n", other, key.display()));
+        let err =
+            verify_nginx_signing_rsa_key(&runner, &key, "sha256", KEY_SHA256, false).unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)), "{err}");
+        assert!(!key.exists());
+    }
+
+    #[test]
+    fn nginx_signing_rsa_key_defaults_to_the_builtin_sha512_pin() {
+        let dir = temp_dir("nginx-key-builtin");
+        let key = dir.join("nginx_signing.rsa.pub");
+        fs::write(&key, "key").unwrap();
+        let runner = RecordingRunner::new().with_stdout(
+            "openssl",
+            &format!("{} *{}\n", NGINX_SIGNING_RSA_SHA512, key.display()),
+        );
+        verify_nginx_signing_rsa_key(&runner, &key, "sha512", NGINX_SIGNING_RSA_SHA512, false)
+            .unwrap();
+        assert_eq!(runner.argvs("openssl")[0][2], "-sha512");
+        assert!(key.exists());
+    }
+
+    #[test]
+    fn parse_sha256_normalizes_and_rejects_partial_digests() {
+        assert_eq!(
+            parse_sha256("--flag", &format!(" {} ", KEY_SHA256.to_uppercase())).unwrap(),
+            KEY_SHA256
+        );
+        assert!(parse_sha256("--flag", &KEY_SHA256[..30]).is_err());
+        assert!(parse_sha256("--flag", &"g".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn issue_cert_routes_dns_through_the_propagation_hook() {
+        let dir = temp_dir("issue-propagation");
+        fs::create_dir_all(dir.join("dnsapi")).unwrap();
+        fs::write(dir.join("dnsapi/dns_cf.sh"), "").unwrap();
+        let mut args = issue_args(&dir);
+        args.wait_for_propagation = true;
+        args.propagation_timeout = 30;
+        let runner = RecordingRunner::new();
+        issue_cert(&runner, &HashMap::new(), args, false, false).unwrap();
+        let issue = runner
+            .argvs("acme.sh")
+            .into_iter()
+            .find(|argv| argv[1] == "--issue")
+            .unwrap();
+        let dns = issue.iter().position(|arg| arg == "--dns").unwrap();
+        assert_eq!(issue[dns + 1], PROPAGATION_HOOK);
+        let hook = fs::read_to_string(dir.join("dnsapi/dns_emby_gate.sh")).unwrap();
+        assert!(hook.contains(&format!(". {}", dir.join("dnsapi/dns_cf.sh").display())));
+        assert!(hook.contains("--timeout 30 --check resolvers"));
+    }
+
+    #[test]
+    fn issue_cert_propagation_hook_requires_dns_cf() {
+        let dir = temp_dir("issue-propagation-missing");
+        let mut args = issue_args(&dir);
+        args.wait_for_propagation = true;
+        let runner = RecordingRunner::new();
+        let err = issue_cert(&runner, &HashMap::new(), args, false, false).unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)));
+        assert!(runner.argvs("acme.sh").is_empty());
+    }
+
+    #[test]
+    fn propagation_hook_waits_after_adding_the_record() {
+        let hook = render_propagation_hook(
+            Path::new("/root/.acme.sh/dnsapi/dns_cf.sh"),
+            Path::new("/usr/local/bin/emby proxy"),
+            "cloudflare",
+            120,
+        );
+        assert_eq!(
+            hook,
+            r#"#!/usr/bin/env sh
+# managed by emby-proxy-cli
+
+. /root/.acme.sh/dnsapi/dns_cf.sh
+
+dns_emby_gate_add() {
+  dns_cf_add "$1" "$2" || return 1
+  if [ -x '/usr/local/bin/emby proxy' ]; then
+    CF_TOKEN="${CF_Token:-}" CF_ZONE_ID="${CF_Zone_ID:-}" '/usr/local/bin/emby proxy' wait-propagation \
+      --record "$1" --value "$2" --timeout 120 --check cloudflare
+  fi
+}
+
+dns_emby_gate_rm() {
+  dns_cf_rm "$1" "$2"
+}
+"#
+        );
+    }
+
+    #[test]
+    fn txt_record_exists_matches_the_expected_value() {
+        let runner = RecordingRunner::new().with_stdout("dig", "\"stale\"\n\"fresh\"\n");
+        assert!(txt_record_exists(
+            &runner,
+            "_acme-challenge.example.com",
+            Some("fresh"),
+            "1.1.1.1"
+        ));
+        assert!(!txt_record_exists(
+            &runner,
+            "_acme-challenge.example.com",
+            Some("other"),
+            "1.1.1.1"
+        ));
+        assert!(txt_record_exists(
+            &runner,
+            "_acme-challenge.example.com",
+            None,
+            "1.1.1.1"
+        ));
+        assert_eq!(
+            runner.argvs("dig")[0],
+            strings(&[
+                "dig",
+                "+short",
+                "TXT",
+                "_acme-challenge.example.com",
+                "@1.1.1.1"
+            ])
+        );
+
+        let empty = RecordingRunner::new();
+        assert!(!txt_record_exists(
+            &empty,
+            "_acme-challenge.example.com",
+            None,
+            "1.1.1.1"
+        ));
     }
-}
 
-fn install_nginx_debian_like(os_id: &str, dry_run: bool) -> Result<(), String> {
-    let keyring_pkg = if os_id == "ubuntu" {
-        "ubuntu-keyring"
-    } else {
-        "debian-archive-keyring"
-    };
-    run_cmd(
-        "apt",
-        &[
-            "install",
-            "-y",
+    #[test]
+    fn cf_txt_record_exists_matches_the_expected_value() {
+        let runner = RecordingRunner::new().with_stdout(
             "curl",
-            "gnupg2",
-            "ca-certificates",
-            "lsb-release",
-            keyring_pkg,
-        ],
-        dry_run,
-    )?;
+            r#"{"success":true,"result":[{"id":"zone1","content":"\"fresh\""}]}"#,
+        );
+        let record = "_acme-challenge.example.com";
+        assert!(cf_txt_record_exists(
+            &runner,
+            record,
+            Some("fresh"),
+            "token",
+            "zone1"
+        ));
+        assert!(!cf_txt_record_exists(
+            &runner,
+            record,
+            Some("stale"),
+            "token",
+            "zone1"
+        ));
+        assert!(cf_txt_record_exists(
+            &runner, record, None, "token", "zone1"
+        ));
+        let input = runner.recorded()[0].input.clone().unwrap();
+        assert!(input.contains(
+            "client/v4/zones/zone1/dns_records?type=TXT&name=_acme-challenge.example.com\""
+        ));
+        assert!(
+            !runner.argvs("curl")[0]
+                .iter()
+                .any(|arg| arg.contains("token"))
+        );
 
-    run_cmd(
-        "curl",
-        &[
-            "-o",
-            "/tmp/nginx_signing.key",
-            "https://nginx.org/keys/nginx_signing.key",
-        ],
-        dry_run,
-    )?;
-    run_cmd(
-        "gpg",
-        &[
-            "--dearmor",
-            "-o",
-            "/usr/share/keyrings/nginx-archive-keyring.gpg",
-            "/tmp/nginx_signing.key",
-        ],
-        dry_run,
-    )?;
+        let failed = RecordingRunner::new().with_stdout("curl", r#"{"success":false}"#);
+        assert!(!cf_txt_record_exists(
+            &failed, record, None, "token", "zone1"
+        ));
+    }
 
-    let codename = read_os_codename()?;
-    let repo_line = format!(
-        "deb [signed-by=/usr/share/keyrings/nginx-archive-keyring.gpg] https://nginx.org/packages/mainline/{os_id} {codename} nginx\n"
-    );
-    if dry_run {
-        info("[dry-run] Would write /etc/apt/sources.list.d/nginx.list");
-        info("[dry-run] Would write /etc/apt/preferences.d/99nginx");
-    } else {
-        fs::write("/etc/apt/sources.list.d/nginx.list", repo_line)
-            .map_err(|e| format!("Failed to write nginx.list: {e}"))?;
-        let pin = "Package: *\nPin: origin nginx.org\nPin: release o=nginx\nPin-Priority: 900\n";
-        fs::write("/etc/apt/preferences.d/99nginx", pin)
-            .map_err(|e| format!("Failed to write 99nginx: {e}"))?;
+    #[test]
+    fn cf_zone_id_for_looks_up_the_zone_by_name() {
+        let runner =
+            RecordingRunner::new().with_stdout("curl", r#"{"success":true,"result":[{"id":"z"}]}"#);
+        assert_eq!(
+            cf_zone_id_for(&runner, "token", "example.com").unwrap(),
+            "z"
+        );
+        let input = runner.recorded()[0].input.clone().unwrap();
+        assert!(input.contains("client/v4/zones?name=example.com\""));
+
+        let missing = RecordingRunner::new().with_stdout("curl", r#"{"success":true,"result":[]}"#);
+        assert!(matches!(
+            cf_zone_id_for(&missing, "token", "example.com"),
+            Err(AppError::Validation(_))
+        ));
     }
 
-    run_cmd("apt", &["update"], dry_run)?;
-    run_cmd("apt", &["install", "-y", "nginx"], dry_run)?;
-    Ok(())
-}
+    #[test]
+    fn verify_cf_token_looks_up_the_zone_of_the_challenge_record() {
+        assert_eq!(
+            challenge_zone("_acme-challenge.emby.example.com"),
+            "example.com"
+        );
+        assert_eq!(
+            challenge_zone("_acme-challenge.acme.example.net"),
+            "example.net"
+        );
+        let runner = RecordingRunner::new().with_stdout(
+            "curl",
+            r#"{"success":true,"result":{"status":"active","name":"example.com"}}"#,
+        );
+        verify_cf_token(&runner, "token", "", "example.com", false).unwrap();
+        let input = runner.recorded()[1].input.clone().unwrap();
+        assert!(
+            input.contains("client/v4/zones?name=example.com\""),
+            "{input}"
+        );
+    }
 
-fn install_nginx_alpine(dry_run: bool) -> Result<(), String> {
-    run_cmd(
-        "apk",
-        &["add", "openssl", "curl", "ca-certificates"],
-        dry_run,
-    )?;
+    #[test]
+    fn verify_cf_token_rejects_a_zone_without_dns_edit() {
+        let runner = RecordingRunner::new().with_stdout(
+            "curl",
+            r##"{"success":true,"result":{"status":"active","name":"example.com","permissions":["#zone:read"]}}"##,
+        );
+        let err = verify_cf_token(&runner, "token", "zone", "example.com", false).unwrap_err();
+        assert!(err.to_string().contains("Zone:DNS:Edit"), "{err}");
+    }
 
-    let release = fs::read_to_string("/etc/alpine-release")
-        .map_err(|e| format!("Failed to read /etc/alpine-release: {e}"))?;
-    let version = release
-        .trim()
-        .split('.')
-        .take(2)
-        .collect::<Vec<_>>()
-        .join(".");
-    let repo_line = format!(
-        "@nginx https://nginx.org/packages/mainline/alpine/v{}/main\n",
-        version
-    );
+    #[test]
+    fn backup_config_refuses_to_keep_zero_backups() {
+        let dir = temp_dir("backup-keep-zero");
+        let path = dir.join("proxy.conf");
+        fs::write(&path, "live").unwrap();
+        assert!(matches!(
+            backup_config(&path, Some(0), false),
+            Err(AppError::Validation(_))
+        ));
+        assert!(list_backups(&path).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
 
-    if dry_run {
-        info("[dry-run] Would append nginx repo to /etc/apk/repositories");
-    } else {
-        let repos_path = "/etc/apk/repositories";
-        let mut repos = fs::read_to_string(repos_path)
-            .map_err(|e| format!("Failed to read {}: {e}", repos_path))?;
-        if !repos.contains(&repo_line) {
-            if !repos.ends_with('\n') {
-                repos.push('\n');
-            }
-            repos.push_str(&repo_line);
-            fs::write(repos_path, repos)
-                .map_err(|e| format!("Failed to write {}: {e}", repos_path))?;
-        }
+    #[test]
+    fn line_diff_keeps_file_order_and_duplicates() {
+        assert_eq!(
+            line_diff(&["a", "x", "a"], &["a", "y", "a", "a"]),
+            strings(&["- x", "+ y", "+ a"])
+        );
+        assert!(line_diff(&["a", "a"], &["a", "a"]).is_empty());
     }
 
-    run_cmd(
-        "curl",
-        &[
-            "-o",
-            "/tmp/nginx_signing.rsa.pub",
-            "https://nginx.org/keys/nginx_signing.rsa.pub",
-        ],
-        dry_run,
-    )?;
-    if dry_run {
-        info("[dry-run] Would move nginx signing key to /etc/apk/keys/");
-    } else {
-        fs::rename(
-            "/tmp/nginx_signing.rsa.pub",
-            "/etc/apk/keys/nginx_signing.rsa.pub",
+    #[test]
+    fn migrate_renames_top_level_toml_keys_only() {
+        let dir = temp_dir("migrate-toml");
+        let path = dir.join("site.toml");
+        fs::write(
+            &path,
+            "CF_Token = \"secret\"\n\n[resolvers]\nCF_Zone_ID = \"9.9.9.9\"\n",
         )
-        .map_err(|e| format!("Failed to move nginx signing key: {e}"))?;
+        .unwrap();
+        migrate_env_file(&path, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "SCHEMA_VERSION = 1\nCF_TOKEN = \"secret\"\n\n[resolvers]\nCF_Zone_ID = \"9.9.9.9\"\n"
+        );
+        let config = crate::modules::config::load_config(&path).unwrap();
+        assert_eq!(config["CF_TOKEN"], "secret");
+        let _ = fs::remove_dir_all(&dir);
     }
 
-    run_cmd("apk", &["add", "nginx@nginx"], dry_run)?;
-    Ok(())
-}
+    const NOT_AFTER: &str = "notAfter=Jan  1 00:00:00 2199 GMT\n";
 
-fn setup_acme_renew_cron(acme_bin: &Path, acme_home: &Path, dry_run: bool) -> Result<(), String> {
-    if !command_exists("crontab") {
-        info("crontab not found, skipping renew cron setup");
-        return Ok(());
+    fn touch(dir: &Path, names: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
     }
 
-    step("Setting up acme renew cron");
-    let cron_line = format!(
-        "0 0 1,16 * * /bin/sh {} --cron --home {} >/dev/null 2>&1",
-        acme_bin.display(),
-        acme_home.display()
-    );
+    fn openssl_inputs(runner: &RecordingRunner) -> Vec<String> {
+        runner
+            .argvs("openssl")
+            .into_iter()
+            .map(|argv| argv.last().unwrap().clone())
+            .collect()
+    }
 
-    if dry_run {
-        info(&format!("[dry-run] Would ensure cron: {}", cron_line));
-        return Ok(());
+    #[test]
+    fn check_renewal_uses_the_cert_name_scheme() {
+        let dir = temp_dir("check-renewal-scheme");
+        touch(&dir, &["fullchain.pem", "privkey.pem"]);
+        let runner = RecordingRunner::new().with_stdout("openssl", NOT_AFTER);
+        let args = CheckRenewalArgs {
+            domain: strings(&["example.com"]),
+            cert_dir: vec![dir.clone()],
+            threshold_days: 14,
+            cert_name_scheme: Some("certbot".to_string()),
+            ..CheckRenewalArgs::default()
+        };
+        check_renewal(&runner, &HashMap::new(), args).unwrap();
+        let cert = dir.join("fullchain.pem").display().to_string();
+        let key = dir.join("privkey.pem").display().to_string();
+        assert_eq!(openssl_inputs(&runner), vec![cert.clone(), cert, key]);
     }
 
-    let existing = Command::new("crontab")
-        .arg("-l")
-        .output()
-        .map_err(|e| format!("Failed to read crontab: {e}"))?;
-    let mut content = String::from_utf8_lossy(&existing.stdout).to_string();
-    if content.contains(&cron_line) {
-        info("acme renew cron already exists");
-        return Ok(());
+    #[test]
+    fn check_renewal_checks_both_dual_cert_variants() {
+        let dir = temp_dir("check-renewal-dual");
+        touch(&dir, &["example.com.ec.cer", "example.com.ec.key"]);
+        let runner = RecordingRunner::new().with_stdout("openssl", NOT_AFTER);
+        let args = CheckRenewalArgs {
+            domain: strings(&["example.com"]),
+            cert_dir: vec![dir.clone()],
+            threshold_days: 14,
+            dual_cert: true,
+            ..CheckRenewalArgs::default()
+        };
+        let err = check_renewal(&runner, &HashMap::new(), args).unwrap_err();
+        assert!(
+            matches!(err, AppError::Validation(message) if message == "1 of 2 certificates need attention")
+        );
+        let inputs = openssl_inputs(&runner);
+        assert_eq!(
+            inputs[0],
+            dir.join("example.com.ec.cer").display().to_string()
+        );
+        assert_eq!(
+            inputs[2],
+            dir.join("example.com.ec.key").display().to_string()
+        );
+        assert_eq!(inputs.len(), 3);
     }
 
-    if !content.ends_with('\n') {
-        content.push('\n');
+    #[test]
+    fn check_renewal_resolves_the_cert_dir_per_domain() {
+        let base = temp_dir("check-renewal-per-domain");
+        touch(&base.join("a"), &["fullchain.pem", "privkey.pem"]);
+        touch(&base.join("b"), &["fullchain.pem", "privkey.pem"]);
+        let runner = RecordingRunner::new().with_stdout("openssl", NOT_AFTER);
+        let args = CheckRenewalArgs {
+            domain: strings(&["a.example.com", "b.example.com"]),
+            cert_dir_name: strings(&["a", "b"]),
+            cert_base_dir: Some(base.clone()),
+            threshold_days: 14,
+            cert_name_scheme: Some("certbot".to_string()),
+            ..CheckRenewalArgs::default()
+        };
+        check_renewal(&runner, &HashMap::new(), args).unwrap();
+        let inputs = openssl_inputs(&runner);
+        assert_eq!(
+            inputs[0],
+            base.join("a/fullchain.pem").display().to_string()
+        );
+        assert_eq!(
+            inputs[3],
+            base.join("b/fullchain.pem").display().to_string()
+        );
     }
-    content.push_str(&cron_line);
-    content.push('\n');
 
-    let mut child = Command::new("crontab")
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .map_err(|e| format!("Failed to write crontab: {e}"))?;
-    if let Some(stdin) = child.stdin.as_mut() {
-        use std::io::Write;
-        stdin
-            .write_all(content.as_bytes())
-            .map_err(|e| format!("Failed to write crontab: {e}"))?;
-    }
-    let status = child
-        .wait()
-        .map_err(|e| format!("Failed to write crontab: {e}"))?;
-    if !status.success() {
-        return Err("Failed to update crontab".to_string());
+    #[test]
+    fn check_renewal_rejects_domains_sharing_a_cert_file() {
+        let base = temp_dir("check-renewal-shared");
+        let args = CheckRenewalArgs {
+            domain: strings(&["a.example.com", "b.example.com"]),
+            cert_base_dir: Some(base.clone()),
+            cert_name_scheme: Some("certbot".to_string()),
+            ..CheckRenewalArgs::default()
+        };
+        let err = check_renewal(&RecordingRunner::new(), &HashMap::new(), args).unwrap_err();
+        assert!(
+            matches!(err, AppError::Validation(message) if message.contains("both resolve to"))
+        );
+
+        let args = CheckRenewalArgs {
+            domain: strings(&["a.example.com", "b.example.com", "c.example.com"]),
+            cert_dir_name: strings(&["a", "b"]),
+            cert_base_dir: Some(base),
+            ..CheckRenewalArgs::default()
+        };
+        let err = check_renewal(&RecordingRunner::new(), &HashMap::new(), args).unwrap_err();
+        assert!(
+            matches!(err, AppError::Validation(message) if message.starts_with("--cert-dir-name was given 2 times"))
+        );
     }
 
-    success("acme renew cron added");
-    Ok(())
-}
+    fn header_pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
 
-const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+    fn header_names(block: &str) -> Vec<&str> {
+        block
+            .lines()
+            .map(|line| line.split_whitespace().nth(1).unwrap())
+            .collect()
+    }
 
-fn confirm_with_timeout(prompt: &str, timeout: Duration, dry_run: bool) -> Result<bool, String> {
-    if dry_run {
-        info(&format!("[dry-run] Would prompt: {}", prompt));
-        return Ok(false);
+    #[test]
+    fn forward_headers_default_block() {
+        let block = render_forward_headers(&[], &[]).unwrap();
+        assert_eq!(
+            header_names(&block),
+            DEFAULT_FORWARD_HEADERS.map(|(name, _)| name).to_vec()
+        );
+        assert!(block.starts_with("        proxy_set_header Upgrade $http_upgrade;\n"));
     }
 
-    info(&format!(
-        "{} (y/N) [timeout {}s]",
-        prompt,
-        timeout.as_secs()
-    ));
-    let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        let mut input = String::new();
-        let _ = std::io::stdin().read_line(&mut input);
-        let _ = tx.send(input);
-    });
+    #[test]
+    fn forward_headers_add_override_and_remove() {
+        let overrides = header_pairs(&[
+            ("x-real-ip", "$http_cf_connecting_ip"),
+            ("X-Client-Region", "eu west"),
+            ("Accept-Encoding", ""),
+        ]);
+        let removed = strings(&["early-data", "Forwarded"]);
+        let block = render_forward_headers(&overrides, &removed).unwrap();
+        assert_eq!(
+            block,
+            "        proxy_set_header Upgrade $http_upgrade;
+        proxy_set_header Connection $connection_upgrade;
+        proxy_set_header x-real-ip $http_cf_connecting_ip;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+        proxy_set_header X-Forwarded-Proto $scheme;
+        proxy_set_header X-Forwarded-Host $host;
+        proxy_set_header X-Forwarded-Port $server_port;
+        proxy_set_header X-Client-Region \"eu west\";
+        proxy_set_header Accept-Encoding \"\";
+"
+        );
+    }
 
-    match rx.recv_timeout(timeout) {
-        Ok(input) => {
-            let trimmed = input.trim();
-            Ok(trimmed.eq_ignore_ascii_case("y") || trimmed.eq_ignore_ascii_case("yes"))
-        }
-        Err(mpsc::RecvTimeoutError::Timeout) => Ok(false),
-        Err(_) => Err("Failed to read input".to_string()),
+    #[test]
+    fn forward_headers_reject_bad_input() {
+        let host = header_pairs(&[("Host", "example.com")]);
+        assert!(render_forward_headers(&host, &[]).is_err());
+        assert!(render_forward_headers(&[], &strings(&["X-Missing"])).is_err());
+        let multiline = header_pairs(&[("X-Test", "a\nb")]);
+        assert!(render_forward_headers(&multiline, &[]).is_err());
     }
-}
 
-fn command_exists(command_name: &str) -> bool {
-    if let Ok(path_var) = env::var("PATH") {
-        for dir in path_var.split(':') {
-            let candidate = Path::new(dir).join(command_name);
-            if candidate.exists() {
-                return true;
-            }
+    #[test]
+    fn forward_headers_apply_to_every_proxy_location() {
+        let overrides = header_pairs(&[("X-Client-Region", "eu")]);
+        let headers = render_forward_headers(&overrides, &strings(&["X-Real-IP"])).unwrap();
+        let content = render(
+            NGINX_PROXY_TEMPLATE,
+            &TemplateContext {
+                geoip: Some(String::new()),
+                upstream: Some(String::new()),
+                http_listen: Some("    listen 80;\n".to_string()),
+                https_listen: Some("    listen 443 ssl;\n".to_string()),
+                routes: Some(crate::modules::routes::render_routes(
+                    &crate::modules::routes::emby_preset_routes(),
+                )),
+                path_rewrite: Some(String::new()),
+                forward_headers: Some(headers),
+                ssl_certificates: Some(String::new()),
+                hsts: Some(String::new()),
+                stub_status: Some(String::new()),
+                geo_gate: Some(String::new()),
+                ssl_ciphers: Some(ECDSA_SSL_CIPHERS.to_string()),
+                ssl_protocols: Some("TLSv1.2 TLSv1.3".to_string()),
+                proxy_domain: Some("proxy.example.com".to_string()),
+                server_tokens: Some("off".to_string()),
+                backend_url: Some("https://emby.example.com".to_string()),
+                resolver: Some(DEFAULT_RESOLVER.to_string()),
+                host_header: Some(DEFAULT_HOST_HEADER.to_string()),
+                backend_tls: Some("proxy_ssl_server_name on;".to_string()),
+                ..TemplateContext::default()
+            },
+        )
+        .unwrap();
+        // location /, three preset routes, /stream/ and @handle_redirect.
+        assert_eq!(
+            content
+                .matches("        proxy_set_header X-Client-Region eu;\n")
+                .count(),
+            6
+        );
+        assert!(!content.contains("X-Real-IP"));
+        for location in ["location ~ ^/stream/", "location @handle_redirect"] {
+            let block = &content[content.find(location).unwrap()..];
+            let block = &block[..block.find("\n    }\n").unwrap()];
+            assert!(
+                block.contains("proxy_set_header X-Client-Region eu;"),
+                "{}",
+                location
+            );
         }
     }
-    false
-}
 
-fn run_cmd(cmd: &str, args: &[&str], dry_run: bool) -> Result<(), String> {
-    if dry_run {
-        info(&format!("[dry-run] Would run: {} {}", cmd, args.join(" ")));
-        return Ok(());
-    }
-    let status = Command::new(cmd)
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .map_err(|e| format!("Failed to run {}: {e}", cmd))?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(format!("Command failed: {}", cmd))
+    fn split(url: &str) -> (String, Option<String>) {
+        split_backend_path(url).unwrap()
     }
-}
 
-fn read_os_id() -> Result<String, String> {
-    let content = fs::read_to_string("/etc/os-release")
-        .map_err(|e| format!("Failed to read /etc/os-release: {e}"))?;
-    for line in content.lines() {
-        if let Some(value) = line.strip_prefix("ID=") {
-            return Ok(value.trim_matches('"').to_string());
+    #[test]
+    fn split_backend_path_with_and_without_a_trailing_slash() {
+        let origin = "https://emby.example.com:8920".to_string();
+        assert_eq!(
+            split("https://emby.example.com:8920"),
+            (origin.clone(), None)
+        );
+        assert_eq!(
+            split("https://emby.example.com:8920/"),
+            (origin.clone(), None)
+        );
+        for url in [
+            "https://emby.example.com:8920/emby/web",
+            "https://emby.example.com:8920/emby/web/",
+            "https://emby.example.com:8920/emby/web//",
+        ] {
+            assert_eq!(split(url), (origin.clone(), Some("/emby/web".to_string())));
         }
+        assert_eq!(split("emby_backend"), ("emby_backend".to_string(), None));
+        assert!(split_backend_path("https://emby.example.com/emby?x=1").is_err());
+        assert!(split_backend_path("https://emby.example.com/a//b").is_err());
     }
-    Err("OS ID not found in /etc/os-release".to_string())
-}
 
-fn read_os_codename() -> Result<String, String> {
-    let content = fs::read_to_string("/etc/os-release")
-        .map_err(|e| format!("Failed to read /etc/os-release: {e}"))?;
-    for line in content.lines() {
-        if let Some(value) = line.strip_prefix("VERSION_CODENAME=") {
-            return Ok(value.trim_matches('"').to_string());
-        }
+    #[test]
+    fn rewrites_quote_patterns_with_quantifiers() {
+        assert_eq!(
+            render_rewrites(&strings(&["^/old/(.*)$ /new/$1"])).unwrap(),
+            "        rewrite ^/old/(.*)$ /new/$1;\n"
+        );
+        assert_eq!(
+            render_rewrites(&strings(&["^/v{1,2}/(.*)$ /$1"])).unwrap(),
+            "        rewrite \"^/v{1,2}/(.*)$\" /$1;\n"
+        );
+        assert_eq!(quote_rewrite_arg("^/a\"{2}$"), "\"^/a\\\"{2}$\"");
     }
-    let output = Command::new("lsb_release")
-        .arg("-cs")
-        .output()
-        .map_err(|e| format!("Failed to run lsb_release: {e}"))?;
-    if !output.status.success() {
-        return Err("Failed to read OS codename".to_string());
+
+    #[test]
+    fn path_rewrite_for_backend_path_and_strip_prefix() {
+        assert_eq!(render_path_rewrite(None, None), "");
+        assert_eq!(
+            render_path_rewrite(None, Some("/emby")),
+            "        rewrite ^/(.*)$ /emby/$1 break;\n"
+        );
+        assert_eq!(
+            render_path_rewrite(Some("/media.v2"), None),
+            "        rewrite ^/media\\.v2(?:/(.*))?$ /$1 break;\n"
+        );
+        assert_eq!(
+            render_path_rewrite(Some("/media"), Some("/emby")),
+            "        rewrite ^/media(?:/(.*))?$ /emby/$1 break;\n        rewrite ^/(.*)$ /emby/$1 break;\n"
+        );
     }
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-}
 
-fn ensure_linux() -> Result<(), String> {
-    if std::env::consts::OS == "linux" {
-        return Ok(());
+    #[test]
+    fn path_rewrite_from_a_trailing_slash_backend_url() {
+        let (_, with_slash) = split("http://10.0.0.2:8096/emby/");
+        let (_, without_slash) = split("http://10.0.0.2:8096/emby");
+        let prefix = parse_uri_prefix("--strip-prefix", "/media/").unwrap();
+        assert_eq!(
+            render_path_rewrite(Some(&prefix), with_slash.as_deref()),
+            render_path_rewrite(Some(&prefix), without_slash.as_deref())
+        );
+        assert_eq!(
+            render_path_rewrite(Some(&prefix), with_slash.as_deref()),
+            "        rewrite ^/media(?:/(.*))?$ /emby/$1 break;\n        rewrite ^/(.*)$ /emby/$1 break;\n"
+        );
     }
-    let version = read_uname().unwrap_or_else(|| std::env::consts::OS.to_string());
-    Err(format!(
-        "Unsupported platform: not a Linux distribution. System: {}",
-        version
-    ))
-}
 
-fn read_uname() -> Option<String> {
-    let output = Command::new("uname").arg("-a").output().ok()?;
-    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
-}
+    #[test]
+    fn stub_status_resolves_flags_before_env() {
+        let env = crate::modules::env::to_env_map(&[
+            ("STUB_STATUS".to_string(), "/nginx_status/".to_string()),
+            (
+                "STUB_STATUS_ALLOW".to_string(),
+                "10.0.0.0/8, 192.168.1.5".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            resolve_stub_status(None, Vec::new(), &env).unwrap(),
+            "    location = /nginx_status {
+        stub_status;
+        allow 10.0.0.0/8;
+        allow 192.168.1.5;
+        deny all;
+    }
 
-fn ensure_root() -> Result<(), String> {
-    let output = Command::new("id")
-        .arg("-u")
-        .output()
-        .map_err(|e| format!("Failed to check uid: {e}"))?;
-    let uid = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if uid != "0" {
-        return Err("This command must be run as root".to_string());
+"
+        );
+        let block =
+            resolve_stub_status(Some("/metrics".to_string()), strings(&["::1"]), &env).unwrap();
+        assert!(block.starts_with("    location = /metrics {\n"));
+        assert!(block.contains("        allow ::1;\n        deny all;\n"));
+        assert_eq!(
+            resolve_stub_status(None, Vec::new(), &HashMap::new()).unwrap(),
+            ""
+        );
+        assert!(
+            resolve_stub_status(Some("/m".to_string()), strings(&["10.0.0.0/33"]), &env).is_err()
+        );
     }
-    Ok(())
-}
 
-fn print_summary(changes: &[String], elapsed: std::time::Duration) {
-    step("Summary");
-    if changes.is_empty() {
-        info("No changes were made");
-    } else {
-        for change in changes {
-            success(change);
-        }
+    #[test]
+    fn default_server_serves_stub_status_before_the_catch_all() {
+        let stub_status = render_stub_status("/nginx_status", &strings(&["127.0.0.1"])).unwrap();
+        let content = render(
+            NGINX_DEFAULT_TEMPLATE,
+            &TemplateContext {
+                http_listen: Some("    listen 80 default_server;\n".to_string()),
+                https_listen: Some("    listen 443 ssl default_server;\n".to_string()),
+                ssl_certificates: Some(String::new()),
+                hsts: Some(String::new()),
+                default_return: Some("444".to_string()),
+                stub_status: Some(stub_status),
+                server_tokens: Some("off".to_string()),
+                ssl_protocols: Some("TLSv1.2 TLSv1.3".to_string()),
+                ..TemplateContext::default()
+            },
+        )
+        .unwrap();
+        assert!(content.ends_with(
+            "    ssl_protocols TLSv1.2 TLSv1.3;
+
+    location = /nginx_status {
+        stub_status;
+        allow 127.0.0.1;
+        deny all;
+    }
+
+    location / {
+        return 444;
     }
-    let seconds = elapsed.as_secs();
-    let minutes = seconds / 60;
-    let remainder = seconds % 60;
-    info(&format!("Execution time: {}m {}s", minutes, remainder));
 }
+"
+        ));
+        assert_eq!(content.matches("stub_status;").count(), 1);
+    }
 
-fn resolve_cert_paths(
-    cert_path: Option<PathBuf>,
-    key_path: Option<PathBuf>,
-    cert_dir: Option<PathBuf>,
-    domain: Option<String>,
-) -> Result<(PathBuf, PathBuf), String> {
-    match (cert_path, key_path) {
-        (Some(cert_path), Some(key_path)) => Ok((cert_path, key_path)),
-        (None, None) => {
-            let cert_dir =
-                cert_dir.ok_or("cert_dir is required to derive cert paths".to_string())?;
-            let domain = domain.ok_or("domain is required to derive cert paths".to_string())?;
-            Ok((
-                cert_dir.join(format!("{}.cer", domain)),
-                cert_dir.join(format!("{}.key", domain)),
-            ))
-        }
-        _ => Err("Both cert and key paths must be set together".to_string()),
+    #[test]
+    fn caddy_transport_skips_verification_only_when_disabled() {
+        let transport = |ssl_verify| {
+            render_caddy_transport(
+                "https://emby.example.com:8920",
+                false,
+                ssl_verify,
+                "1.1.1.1",
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            transport(None),
+            "\t\ttransport http {\n\t\t\tresolvers 1.1.1.1\n\t\t\ttls_server_name emby.example.com\n\t\t}\n"
+        );
+        assert_eq!(transport(Some("on")), transport(None));
+        assert_eq!(
+            transport(Some("off")),
+            "\t\ttransport http {\n\t\t\tresolvers 1.1.1.1\n\t\t\ttls_server_name emby.example.com\n\t\t\ttls_insecure_skip_verify\n\t\t}\n"
+        );
+        let plain =
+            render_caddy_transport("http://10.0.0.2:8096", false, Some("off"), "[::1] 1.1.1.1")
+                .unwrap();
+        assert_eq!(
+            plain,
+            "\t\ttransport http {\n\t\t\tresolvers ::1 1.1.1.1\n\t\t}\n"
+        );
     }
 }
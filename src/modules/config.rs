@@ -0,0 +1,103 @@
+use crate::modules::error::AppError;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+use toml::{Table, Value};
+
+const STDIN_SENTINEL: &str = "-";
+pub const RESOLVER_PRESET_PREFIX: &str = "RESOLVER_PRESET_";
+pub const PROXY_DOMAIN_PREFIX: &str = "PROXY_DOMAIN_";
+pub const PROXY_DOMAIN_OVERRIDES: [&str; 2] = ["GEO_ALLOW", "ROUTES_FILE"];
+
+pub fn load_config(path: &Path) -> Result<HashMap<String, String>, AppError> {
+    if path == Path::new(STDIN_SENTINEL) {
+        return load_config_stdin();
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+    parse_config(&content)
+        .map_err(|e| AppError::Validation(format!("Invalid config {}: {e}", path.display())))
+}
+
+// `--config -` takes the TOML from stdin, e.g. `docker run -i ... < site.toml`.
+fn load_config_stdin() -> Result<HashMap<String, String>, AppError> {
+    let mut content = String::new();
+    io::stdin()
+        .read_to_string(&mut content)
+        .map_err(|e| AppError::Io(format!("Failed to read config from stdin: {e}")))?;
+    if content.trim().is_empty() {
+        return Err(AppError::MissingInput(
+            "--config - expects TOML on stdin, but stdin was empty".to_string(),
+        ));
+    }
+    parse_config(&content)
+        .map_err(|e| AppError::Validation(format!("Invalid config from stdin: {e}")))
+}
+
+fn parse_config(content: &str) -> Result<HashMap<String, String>, String> {
+    let table: Table = content
+        .parse()
+        .map_err(|e: toml::de::Error| e.message().to_string())?;
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        match value {
+            Value::Table(resolvers) if key == "resolvers" => {
+                for (name, value) in resolvers {
+                    let value = scalar_to_string(&value)
+                        .ok_or(format!("resolvers.{} must be a string", name))?;
+                    values.insert(
+                        format!("{}{}", RESOLVER_PRESET_PREFIX, name.to_uppercase()),
+                        value,
+                    );
+                }
+            }
+            Value::Table(domains) if key == "proxy_domains" => {
+                for (index, value) in domains {
+                    let Value::Table(overrides) = value else {
+                        return Err(format!("proxy_domains.{} must be a table", index));
+                    };
+                    if !index.parse::<u32>().is_ok_and(|n| n > 0) {
+                        return Err(format!(
+                            "proxy_domains.{} must be indexed from 1 in --proxy-domain order",
+                            index
+                        ));
+                    }
+                    for (name, value) in overrides {
+                        let name = name.to_uppercase();
+                        if !PROXY_DOMAIN_OVERRIDES.contains(&name.as_str()) {
+                            return Err(format!(
+                                "proxy_domains.{}.{} is not supported (use {})",
+                                index,
+                                name,
+                                PROXY_DOMAIN_OVERRIDES.join(", ")
+                            ));
+                        }
+                        let value = scalar_to_string(&value)
+                            .ok_or(format!("proxy_domains.{}.{} must be a string", index, name))?;
+                        values.insert(format!("{}{}_{}", PROXY_DOMAIN_PREFIX, index, name), value);
+                    }
+                }
+            }
+            Value::Table(_) => return Err(format!("unknown section [{}]", key)),
+            value => {
+                let value = scalar_to_string(&value)
+                    .ok_or(format!("{} must be a string, number or boolean", key))?;
+                values.insert(key, value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,152 @@
+use crate::modules::error::AppError;
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    process, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const DEFAULT_PROBE_NAME: &str = "cloudflare.com";
+const RCODE_NAMES: [&str; 6] = [
+    "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED",
+];
+
+#[derive(Debug)]
+pub struct Probe {
+    pub latency: Duration,
+    pub rcode: String,
+    pub answers: u16,
+}
+
+impl Probe {
+    // NXDOMAIN still proves the resolver answers; SERVFAIL/REFUSED do not.
+    pub fn usable(&self) -> bool {
+        self.rcode == "NOERROR" || self.rcode == "NXDOMAIN"
+    }
+}
+
+// nginx `resolver` values mix addresses with options such as valid=30s.
+pub fn resolver_addresses(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty() && !token.contains('='))
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn probe_all(
+    addresses: &[String],
+    name: &str,
+    timeout: Duration,
+) -> Vec<Result<Probe, AppError>> {
+    thread::scope(|scope| {
+        let handles = addresses
+            .iter()
+            .map(|address| scope.spawn(move || probe_resolver(address, name, timeout)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(AppError::Io("DNS probe panicked".to_string())))
+            })
+            .collect()
+    })
+}
+
+pub fn probe_resolver(address: &str, name: &str, timeout: Duration) -> Result<Probe, AppError> {
+    let target = parse_address(address)?;
+    let bind = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let io_error = |e: std::io::Error| AppError::Io(e.to_string());
+    let socket = UdpSocket::bind(bind).map_err(io_error)?;
+    socket.connect(target).map_err(io_error)?;
+
+    let id = query_id();
+    let query = build_query(id, name)?;
+    let started = Instant::now();
+    socket.send(&query).map_err(io_error)?;
+    let mut buffer = [0u8; 512];
+    loop {
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(AppError::Io(format!(
+                "no answer within {}ms",
+                timeout.as_millis()
+            )));
+        }
+        socket.set_read_timeout(Some(remaining)).map_err(io_error)?;
+        let len = match socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(io_error(e)),
+        };
+        // Ignore stray datagrams that are not the reply to this query.
+        if len < 12 || u16::from_be_bytes([buffer[0], buffer[1]]) != id || buffer[2] & 0x80 == 0 {
+            continue;
+        }
+        let rcode = usize::from(buffer[3] & 0x0f);
+        return Ok(Probe {
+            latency: started.elapsed(),
+            rcode: RCODE_NAMES
+                .get(rcode)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("RCODE{}", rcode)),
+            answers: u16::from_be_bytes([buffer[6], buffer[7]]),
+        });
+    }
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr, AppError> {
+    if let Ok(ip) = address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    address.parse::<SocketAddr>().map_err(|_| {
+        AppError::Validation(format!(
+            "{} is not an IP address (use ip, ip:port or [ipv6]:port)",
+            address
+        ))
+    })
+}
+
+fn query_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    (nanos ^ process::id()) as u16
+}
+
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>, AppError> {
+    let mut query = Vec::with_capacity(32 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question, no other records.
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(AppError::Validation(format!(
+                "Invalid query name: {}",
+                name
+            )));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    // Root label, then type A and class IN.
+    query.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+    Ok(query)
+}
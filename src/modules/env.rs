@@ -1,29 +1,183 @@
+use crate::modules::{
+    backends::BACKEND_URL_PREFIX,
+    config::{PROXY_DOMAIN_OVERRIDES, PROXY_DOMAIN_PREFIX, RESOLVER_PRESET_PREFIX},
+    dns::{DEFAULT_PROBE_NAME, probe_all, resolver_addresses},
+    error::AppError,
+    summary::record_param,
+};
 use std::{
     collections::HashMap,
-    env,
+    env, fs,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::mpsc,
     thread,
     time::Duration,
 };
 
 const RESOLVER_TIMEOUT_SECS: u64 = 10;
+const PRESET_PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
 const RESOLVER_CLOUDFLARE: &str = "1.1.1.1 1.0.0.1 [2606:4700:4700::1111] [2606:4700:4700::1064]";
 const RESOLVER_TENCENT: &str = "119.29.29.29 182.254.116.116";
 const RESOLVER_ALI: &str = "223.5.5.5 223.6.6.6";
 const RESOLVER_GOOGLE: &str = "8.8.8.8 8.8.4.4";
+const RESOLVER_QUAD9: &str = "9.9.9.9 149.112.112.112 [2620:fe::fe] [2620:fe::9]";
+const RESOLVER_OPENDNS: &str = "208.67.222.222 208.67.220.220 [2620:119:35::35] [2620:119:53::53]";
+const RESOLVER_PRESETS: [(&str, &str, &str); 6] = [
+    ("cloudflare", "Cloudflare", RESOLVER_CLOUDFLARE),
+    ("tencent", "Tencent", RESOLVER_TENCENT),
+    ("aliyun", "Aliyun", RESOLVER_ALI),
+    ("google", "Google", RESOLVER_GOOGLE),
+    ("quad9", "Quad9", RESOLVER_QUAD9),
+    ("opendns", "OpenDNS", RESOLVER_OPENDNS),
+];
+const MULTI_LABEL_SUFFIXES: [&str; 24] = [
+    "ac.jp", "ac.uk", "co.in", "co.jp", "co.kr", "co.nz", "co.uk", "co.za", "com.au", "com.br",
+    "com.cn", "com.hk", "com.sg", "com.tw", "eu.org", "gov.cn", "gov.uk", "ne.jp", "net.au",
+    "net.cn", "or.jp", "org.au", "org.cn", "org.uk",
+];
+const KNOWN_ENV_KEYS: [&str; 55] = [
+    "ACME_BIN",
+    "ACME_CERT_HOME",
+    "ACME_CONFIG_HOME",
+    "ACME_HOME",
+    "ACTIVE_BACKEND",
+    "BACKENDS_FILE",
+    "BACKEND_BLUE",
+    "BACKEND_GREEN",
+    "BACKEND_URL",
+    "CERT_BASE_DIR",
+    "CERT_DIR",
+    "CERT_DIR_NAME",
+    "CERT_FILENAME",
+    "CERT_INPUT_PATH",
+    "CERT_NAME_SCHEME",
+    "CERT_OUTPUT_PATH",
+    "CERT_OWNER",
+    "CF_ACCOUNT_ID",
+    "CF_TOKEN",
+    "CF_ZONE_ID",
+    "CHALLENGE_ALIAS",
+    "DOMAIN",
+    "DOMAIN_ALIAS",
+    "GEOIP_DB",
+    "GEO_ALLOW",
+    "HOST_HEADER",
+    "KEY_FILENAME",
+    "KEY_INPUT_PATH",
+    "KEY_OUTPUT_PATH",
+    "MIN_TLS_VERSION",
+    "MIRROR_APT",
+    "MIRROR_NGINX",
+    "NGINX_BIN",
+    "NGINX_CERT_DIR_NAME",
+    "NGINX_CERT_PATH",
+    "NGINX_DEFAULT_ACTION",
+    "NGINX_DEFAULT_BIND",
+    "NGINX_DEFAULT_OUTPUT",
+    "NGINX_DEFAULT_TEMPLATE",
+    "NGINX_KEY_PATH",
+    "NGINX_SIGNING_RSA_SHA256",
+    "OUTPUT_OWNER",
+    "POST_WRITE_CMD",
+    "PREFERRED_CHAIN",
+    "PROXY_DOMAIN",
+    "PROXY_OUTPUT_DIR",
+    "PROXY_TEMPLATE",
+    "RESOLVER",
+    "ROUTES_FILE",
+    "SCHEMA_VERSION",
+    "STREAM_OUTPUT_DIR",
+    "STRIP_PREFIX",
+    "STUB_STATUS",
+    "STUB_STATUS_ALLOW",
+    "WILDCARD_DOMAIN",
+];
 
-pub fn parse_key_val(s: &str) -> Result<(String, String), String> {
+pub fn parse_key_val(s: &str) -> Result<(String, String), AppError> {
     let mut split = s.splitn(2, '=');
     let key = split.next().unwrap_or("").trim();
     let value = split.next().unwrap_or("").to_string();
     if key.is_empty() {
-        return Err("--env expects KEY=VALUE".to_string());
+        return Err(AppError::Validation("expected KEY=VALUE".to_string()));
     }
     Ok((key.to_string(), value))
 }
 
+pub fn parse_duration(s: &str) -> Result<Duration, AppError> {
+    let invalid = || {
+        AppError::Validation(format!(
+            "expected a duration like 30s, 15m, 12h or 7d, got {}",
+            s
+        ))
+    };
+    let s = s.trim();
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(invalid()),
+    };
+    let amount: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+    amount
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+pub fn check_env_keys(env_overrides: &HashMap<String, String>) -> Result<(), AppError> {
+    let mut unknown: Vec<&String> = env_overrides
+        .keys()
+        .filter(|key| {
+            !KNOWN_ENV_KEYS.contains(&key.as_str())
+                && !key.starts_with(RESOLVER_PRESET_PREFIX)
+                && key
+                    .strip_prefix(BACKEND_URL_PREFIX)
+                    .is_none_or(|n| n.parse::<u32>().is_err())
+                && key
+                    .strip_prefix(PROXY_DOMAIN_PREFIX)
+                    .and_then(|rest| rest.split_once('_'))
+                    .is_none_or(|(n, name)| {
+                        n.parse::<u32>().is_err() || !PROXY_DOMAIN_OVERRIDES.contains(&name)
+                    })
+        })
+        .collect();
+    unknown.sort();
+    let Some(key) = unknown.first() else {
+        return Ok(());
+    };
+    let suggestion = KNOWN_ENV_KEYS
+        .iter()
+        .map(|known| (edit_distance(&key.to_uppercase(), known), known))
+        .filter(|(distance, known)| *distance <= 2.max(known.len() / 4))
+        .min_by_key(|(distance, _)| *distance);
+    Err(AppError::Validation(match suggestion {
+        Some((_, known)) => format!("Unknown env key {} (did you mean {}?)", key, known),
+        None => format!("Unknown env key {}", key),
+    }))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
 pub fn to_env_map(pairs: &[(String, String)]) -> HashMap<String, String> {
     let mut map = HashMap::new();
     for (k, v) in pairs {
@@ -32,28 +186,73 @@ pub fn to_env_map(pairs: &[(String, String)]) -> HashMap<String, String> {
     map
 }
 
+// --env values beat the files; with process_env_wins (--env-file-override) a
+// non-empty process variable does too.
+pub fn apply_env_files(
+    env_overrides: &mut HashMap<String, String>,
+    paths: &[PathBuf],
+    process_env_wins: bool,
+) -> Result<(), AppError> {
+    for (key, value) in load_env_files(paths)? {
+        if process_env_wins && env::var(&key).is_ok_and(|set| !set.trim().is_empty()) {
+            continue;
+        }
+        env_overrides.entry(key).or_insert(value);
+    }
+    Ok(())
+}
+
+// Later files win over earlier ones for the same key.
+pub fn load_env_files(paths: &[PathBuf]) -> Result<Vec<(String, String)>, AppError> {
+    let mut merged: Vec<(String, String)> = Vec::new();
+    for path in paths {
+        let content = fs::read_to_string(path)
+            .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let Some((key, value)) = line
+                .split_once('=')
+                .filter(|(key, _)| !key.trim().is_empty())
+            else {
+                return Err(AppError::Validation(format!(
+                    "Invalid line {} in {}: expected KEY=VALUE",
+                    index + 1,
+                    path.display()
+                )));
+            };
+            let (key, value) = (key.trim().to_string(), unquote(value.trim()).to_string());
+            merged.retain(|(existing, _)| *existing != key);
+            merged.push((key, value));
+        }
+    }
+    Ok(merged)
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+}
+
 pub fn resolve_value(
     cli_value: Option<String>,
     env_overrides: &HashMap<String, String>,
     env_key: &str,
     prompt_label: &str,
     sensitive: bool,
-) -> Result<String, String> {
-    if let Some(value) = cli_value {
-        return Ok(value);
-    }
-    if let Some(value) = env_overrides.get(env_key)
-        && !value.trim().is_empty()
+) -> Result<String, AppError> {
+    let value = match trim_value(cli_value).or_else(|| resolve_from_envs(env_overrides, &[env_key]))
     {
-        return Ok(value.clone());
-    }
-    if let Ok(value) = env::var(env_key)
-        && !value.trim().is_empty()
-    {
-        return Ok(value);
-    }
-
-    prompt_value(prompt_label, sensitive)
+        Some(value) => value,
+        None => prompt_value(prompt_label, sensitive)?.trim().to_string(),
+    };
+    record_param(env_key, &value, sensitive);
+    Ok(value)
 }
 
 pub fn resolve_optional_value(
@@ -62,27 +261,16 @@ pub fn resolve_optional_value(
     env_key: &str,
     prompt_label: &str,
     sensitive: bool,
-) -> Result<Option<String>, String> {
-    if let Some(value) = cli_value {
-        return Ok(Some(value));
-    }
-    if let Some(value) = env_overrides.get(env_key)
-        && !value.trim().is_empty()
-    {
-        return Ok(Some(value.clone()));
-    }
-    if let Ok(value) = env::var(env_key)
-        && !value.trim().is_empty()
+) -> Result<Option<String>, AppError> {
+    let value = match trim_value(cli_value).or_else(|| resolve_from_envs(env_overrides, &[env_key]))
     {
-        return Ok(Some(value));
-    }
-
-    let input = prompt_value(prompt_label, sensitive)?;
-    if input.trim().is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(input))
+        Some(value) => Some(value),
+        None => trim_value(Some(prompt_value(prompt_label, sensitive)?)),
+    };
+    if let Some(value) = &value {
+        record_param(env_key, value, sensitive);
     }
+    Ok(value)
 }
 
 pub fn resolve_path(
@@ -91,28 +279,23 @@ pub fn resolve_path(
     env_key: &str,
     default: &str,
     prompt_label: &str,
-) -> Result<PathBuf, String> {
-    if let Some(value) = cli_value {
-        return Ok(value);
-    }
-    if let Some(value) = env_overrides.get(env_key)
-        && !value.trim().is_empty()
-    {
-        return Ok(PathBuf::from(value));
-    }
-    if let Ok(value) = env::var(env_key)
-        && !value.trim().is_empty()
+) -> Result<PathBuf, AppError> {
+    let value = match trim_path(cli_value)
+        .or_else(|| resolve_from_envs(env_overrides, &[env_key]).map(PathBuf::from))
     {
-        return Ok(PathBuf::from(value));
-    }
-
-    let prompt = format!("{} [{}]", prompt_label, default);
-    let input = prompt_value(&prompt, false)?;
-    if input.trim().is_empty() {
-        Ok(PathBuf::from(default))
-    } else {
-        Ok(PathBuf::from(input))
-    }
+        Some(value) => value,
+        None => {
+            let prompt = format!("{} [{}]", prompt_label, default);
+            let input = prompt_value(&prompt, false)?;
+            if input.trim().is_empty() {
+                PathBuf::from(default)
+            } else {
+                PathBuf::from(input.trim())
+            }
+        }
+    };
+    record_param(env_key, &value.display().to_string(), false);
+    Ok(value)
 }
 
 pub fn resolve_optional_path(
@@ -120,20 +303,27 @@ pub fn resolve_optional_path(
     env_overrides: &HashMap<String, String>,
     env_key: &str,
 ) -> Option<PathBuf> {
-    if let Some(value) = cli_value {
-        return Some(value);
-    }
-    if let Some(value) = env_overrides.get(env_key)
-        && !value.trim().is_empty()
-    {
-        return Some(PathBuf::from(value));
+    let value = trim_path(cli_value)
+        .or_else(|| resolve_from_envs(env_overrides, &[env_key]).map(PathBuf::from));
+    if let Some(value) = &value {
+        record_param(env_key, &value.display().to_string(), false);
     }
-    if let Ok(value) = env::var(env_key)
-        && !value.trim().is_empty()
-    {
-        return Some(PathBuf::from(value));
+    value
+}
+
+fn trim_value(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn trim_path(path: Option<PathBuf>) -> Option<PathBuf> {
+    let path = path?;
+    match path.to_str() {
+        Some(value) if value.trim().is_empty() => None,
+        Some(value) if value.trim() != value => Some(PathBuf::from(value.trim())),
+        _ => Some(path),
     }
-    None
 }
 
 pub fn resolve_cert_dir(
@@ -142,8 +332,9 @@ pub fn resolve_cert_dir(
     env_overrides: &HashMap<String, String>,
     env_keys: &[&str],
     default_name: &str,
-) -> Result<PathBuf, String> {
-    if let Some(dir) = cert_dir {
+    default_root: &Path,
+) -> Result<PathBuf, AppError> {
+    if let Some(dir) = trim_path(cert_dir) {
         return Ok(dir);
     }
     let name = resolve_name_with_default(
@@ -153,7 +344,7 @@ pub fn resolve_cert_dir(
         default_name,
         "certificate directory name",
     )?;
-    Ok(PathBuf::from("/etc/ca-certificates").join(name))
+    Ok(default_root.join(name))
 }
 
 pub fn resolve_name_with_default(
@@ -162,20 +353,23 @@ pub fn resolve_name_with_default(
     env_keys: &[&str],
     default: &str,
     prompt_label: &str,
-) -> Result<String, String> {
-    if let Some(value) = cli_value {
-        return Ok(value);
-    }
-    if let Some(value) = resolve_from_envs(env_overrides, env_keys) {
-        return Ok(value);
-    }
-    let prompt = format!("{} [{}]", prompt_label, default);
-    let input = prompt_value(&prompt, false)?;
-    if input.trim().is_empty() {
-        Ok(default.to_string())
-    } else {
-        Ok(input)
+) -> Result<String, AppError> {
+    let value = match trim_value(cli_value).or_else(|| resolve_from_envs(env_overrides, env_keys)) {
+        Some(value) => value,
+        None => {
+            let prompt = format!("{} [{}]", prompt_label, default);
+            let input = prompt_value(&prompt, false)?;
+            if input.trim().is_empty() {
+                default.to_string()
+            } else {
+                input.trim().to_string()
+            }
+        }
+    };
+    if let Some(key) = env_keys.first() {
+        record_param(key, &value, false);
     }
+    Ok(value)
 }
 
 pub fn resolve_from_envs(
@@ -186,48 +380,112 @@ pub fn resolve_from_envs(
         if let Some(value) = env_overrides.get(*key)
             && !value.trim().is_empty()
         {
-            return Some(value.clone());
+            return Some(value.trim().to_string());
         }
         if let Ok(value) = env::var(key)
             && !value.trim().is_empty()
         {
-            return Some(value);
+            return Some(value.trim().to_string());
         }
     }
     None
 }
 
+pub fn apex_domain(host: &str) -> String {
+    let host = host
+        .trim()
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let labels: Vec<&str> = host.split('.').collect();
+    let suffix = labels[labels.len().saturating_sub(2)..].join(".");
+    let keep = if labels.len() > 2 && MULTI_LABEL_SUFFIXES.contains(&suffix.as_str()) {
+        3
+    } else {
+        2
+    };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
 pub fn resolve_resolvers(
     cli_values: &[String],
     env_overrides: &HashMap<String, String>,
     env_key: &str,
     default_value: &str,
-) -> Result<String, String> {
-    if !cli_values.is_empty() {
-        return Ok(cli_values.join(" "));
-    }
-    if let Some(value) = env_overrides.get(env_key)
-        && !value.trim().is_empty()
-    {
-        return Ok(value.clone());
-    }
-    if let Ok(value) = env::var(env_key)
-        && !value.trim().is_empty()
+) -> Result<String, AppError> {
+    let presets = resolver_presets(env_overrides);
+    let value = if !cli_values.is_empty() {
+        cli_values.join(" ")
+    } else if let Some(value) = resolve_from_envs(env_overrides, &[env_key]) {
+        value
+    } else {
+        select_resolver_with_timeout(&presets, default_value)?
+    };
+    let value = match presets
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(value.trim()))
     {
-        return Ok(value);
-    }
+        Some((_, _, addresses)) => addresses.clone(),
+        None => value,
+    };
+    let value = normalize_resolvers(&value);
+    record_param(env_key, &value, false);
+    Ok(value)
+}
+
+fn normalize_resolvers(value: &str) -> String {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-    select_resolver_with_timeout(default_value)
+pub fn resolver_presets(env_overrides: &HashMap<String, String>) -> Vec<(String, String, String)> {
+    let mut presets: Vec<(String, String, String)> = RESOLVER_PRESETS
+        .iter()
+        .map(|(name, label, addresses)| {
+            (name.to_string(), label.to_string(), addresses.to_string())
+        })
+        .collect();
+    let mut custom: Vec<(String, String)> = env_overrides
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .chain(env::vars())
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(RESOLVER_PRESET_PREFIX)?.to_lowercase();
+            (!name.is_empty() && !value.trim().is_empty()).then_some((name, value))
+        })
+        .collect();
+    custom.sort();
+    for (name, addresses) in custom {
+        if !presets.iter().any(|(existing, _, _)| *existing == name) {
+            presets.push((name.clone(), name, addresses));
+        }
+    }
+    presets
 }
 
-fn select_resolver_with_timeout(default_value: &str) -> Result<String, String> {
+fn select_resolver_with_timeout(
+    presets: &[(String, String, String)],
+    default_value: &str,
+) -> Result<String, AppError> {
+    let custom_choice = presets.len() + 1;
+    let reachable = probe_presets(presets);
     println!("Select DNS resolver (default: Cloudflare):");
-    println!("  1) Cloudflare");
-    println!("  2) Tencent");
-    println!("  3) Aliyun");
-    println!("  4) Google");
-    println!("  5) Custom");
-    println!("Enter choice [1-5] within {}s: ", RESOLVER_TIMEOUT_SECS);
+    for (index, (_, label, _)) in presets.iter().enumerate() {
+        let mark = if reachable[index] {
+            ""
+        } else {
+            " (unreachable)"
+        };
+        println!("  {}) {}{}", index + 1, label, mark);
+    }
+    println!("  {}) Custom", custom_choice);
+    println!(
+        "Enter choice [1-{}] within {}s: ",
+        custom_choice, RESOLVER_TIMEOUT_SECS
+    );
 
     let input = read_line_with_timeout(Duration::from_secs(RESOLVER_TIMEOUT_SECS))?;
     let choice = input.unwrap_or_default();
@@ -236,12 +494,9 @@ fn select_resolver_with_timeout(default_value: &str) -> Result<String, String> {
         return Ok(default_value.to_string());
     }
 
-    match trimmed {
-        "1" => Ok(RESOLVER_CLOUDFLARE.to_string()),
-        "2" => Ok(RESOLVER_TENCENT.to_string()),
-        "3" => Ok(RESOLVER_ALI.to_string()),
-        "4" => Ok(RESOLVER_GOOGLE.to_string()),
-        "5" => {
+    match trimmed.parse::<usize>() {
+        Ok(choice) if (1..custom_choice).contains(&choice) => Ok(presets[choice - 1].2.clone()),
+        Ok(choice) if choice == custom_choice => {
             let custom = prompt_value("Custom resolver (space-separated)", false)?;
             if custom.trim().is_empty() {
                 Ok(default_value.to_string())
@@ -253,7 +508,71 @@ fn select_resolver_with_timeout(default_value: &str) -> Result<String, String> {
     }
 }
 
-fn read_line_with_timeout(timeout: Duration) -> Result<Option<String>, String> {
+// A preset counts as reachable when any of its addresses answers.
+fn probe_presets(presets: &[(String, String, String)]) -> Vec<bool> {
+    let targets = presets
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (_, _, addresses))| {
+            resolver_addresses(addresses)
+                .into_iter()
+                .map(move |address| (index, address))
+        })
+        .collect::<Vec<_>>();
+    let addresses = targets
+        .iter()
+        .map(|(_, address)| address.clone())
+        .collect::<Vec<_>>();
+    let mut reachable = vec![false; presets.len()];
+    for ((index, _), result) in targets.iter().zip(probe_all(
+        &addresses,
+        DEFAULT_PROBE_NAME,
+        PRESET_PROBE_TIMEOUT,
+    )) {
+        if result.is_ok_and(|probe| probe.usable()) {
+            reachable[*index] = true;
+        }
+    }
+    reachable
+}
+
+pub fn select_many_with_timeout(
+    label: &str,
+    options: &[&str],
+    timeout: Duration,
+) -> Result<Vec<bool>, AppError> {
+    let mut selected = vec![true; options.len()];
+    loop {
+        println!("{}:", label);
+        for (index, option) in options.iter().enumerate() {
+            let mark = if selected[index] { "x" } else { " " };
+            println!("  {}) [{}] {}", index + 1, mark, option);
+        }
+        println!(
+            "Enter numbers to toggle (e.g., 1 3), or press Enter to continue, within {}s: ",
+            timeout.as_secs()
+        );
+
+        let Some(input) = read_line_with_timeout(timeout)? else {
+            return Ok(selected);
+        };
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            return Ok(selected);
+        }
+        for token in trimmed.split(|c: char| c.is_whitespace() || c == ',') {
+            match token.parse::<usize>() {
+                Ok(choice) if (1..=options.len()).contains(&choice) => {
+                    selected[choice - 1] = !selected[choice - 1];
+                }
+                _ if token.is_empty() => {}
+                _ => println!("Ignoring invalid choice: {}", token),
+            }
+        }
+    }
+}
+
+fn read_line_with_timeout(timeout: Duration) -> Result<Option<String>, AppError> {
     let (tx, rx) = mpsc::channel();
     thread::spawn(move || {
         let mut input = String::new();
@@ -264,22 +583,186 @@ fn read_line_with_timeout(timeout: Duration) -> Result<Option<String>, String> {
     match rx.recv_timeout(timeout) {
         Ok(input) => Ok(Some(input)),
         Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
-        Err(_) => Err("Failed to read input".to_string()),
+        Err(_) => Err(AppError::Io("Failed to read input".to_string())),
     }
 }
 
-fn prompt_value(label: &str, sensitive: bool) -> Result<String, String> {
+fn prompt_value(label: &str, sensitive: bool) -> Result<String, AppError> {
     if sensitive {
         let prompt = format!("{}: ", label);
-        rpassword::prompt_password(prompt).map_err(|e| format!("Prompt failed: {e}"))
+        rpassword::prompt_password(prompt).map_err(|e| AppError::Io(format!("Prompt failed: {e}")))
     } else {
         let mut stdout = io::stdout();
-        write!(stdout, "{}: ", label).map_err(|e| format!("Prompt failed: {e}"))?;
-        stdout.flush().map_err(|e| format!("Prompt failed: {e}"))?;
+        write!(stdout, "{}: ", label).map_err(|e| AppError::Io(format!("Prompt failed: {e}")))?;
+        stdout
+            .flush()
+            .map_err(|e| AppError::Io(format!("Prompt failed: {e}")))?;
         let mut input = String::new();
         io::stdin()
             .read_line(&mut input)
-            .map_err(|e| format!("Prompt failed: {e}"))?;
+            .map_err(|e| AppError::Io(format!("Prompt failed: {e}")))?;
         Ok(input.trim().to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("emby-proxy-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn env_files_layer_under_cli_and_process_env() {
+        let dir = temp_dir("env-files");
+        let files = [
+            (
+                "base.env",
+                "DOMAIN=base.example.com\nEPC_TEST_LAYERED=base\nHSTS=off\n",
+            ),
+            (
+                "site.env",
+                "export DOMAIN=\"site.example.com\"\nEPC_TEST_LAYERED=site\n",
+            ),
+            (
+                "local.env",
+                "# local tweaks\nDOMAIN='local.example.com'\nRESOLVER=1.1.1.1\n",
+            ),
+        ]
+        .map(|(name, content)| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        });
+        // Only this test touches EPC_TEST_LAYERED.
+        unsafe { env::set_var("EPC_TEST_LAYERED", "process") };
+
+        let mut overrides = to_env_map(&[("RESOLVER".to_string(), "9.9.9.9".to_string())]);
+        apply_env_files(&mut overrides, &files, false).unwrap();
+        assert_eq!(overrides["DOMAIN"], "local.example.com");
+        assert_eq!(overrides["HSTS"], "off");
+        assert_eq!(overrides["RESOLVER"], "9.9.9.9");
+        assert_eq!(overrides["EPC_TEST_LAYERED"], "site");
+
+        let mut overrides = HashMap::new();
+        apply_env_files(&mut overrides, &files, true).unwrap();
+        assert_eq!(overrides["HSTS"], "off");
+        assert!(!overrides.contains_key("EPC_TEST_LAYERED"));
+        assert_eq!(
+            resolve_from_envs(&overrides, &["EPC_TEST_LAYERED"]).as_deref(),
+            Some("process")
+        );
+    }
+
+    #[test]
+    fn apex_domain_handles_tricky_hosts() {
+        for (host, apex) in [
+            ("example.com", "example.com"),
+            ("emby.example.com", "example.com"),
+            ("a.b.c.example.com", "example.com"),
+            ("*.example.com", "example.com"),
+            ("example.com.", "example.com"),
+            ("_acme-challenge.Emby.Example.COM.", "example.com"),
+            (" emby.example.com ", "example.com"),
+            ("example.co.uk", "example.co.uk"),
+            ("emby.example.co.uk", "example.co.uk"),
+            ("*.media.example.com.au", "example.com.au"),
+            ("deep.emby.example.com.cn.", "example.com.cn"),
+            ("co.uk", "co.uk"),
+            ("localhost", "localhost"),
+        ] {
+            assert_eq!(apex_domain(host), apex, "{}", host);
+        }
+    }
+
+    #[test]
+    fn resolver_lists_accept_mixed_separators() {
+        assert_eq!(
+            normalize_resolvers(" 1.1.1.1,8.8.8.8\n[2606:4700:4700::1111] ,, 9.9.9.9\t\n"),
+            "1.1.1.1 8.8.8.8 [2606:4700:4700::1111] 9.9.9.9"
+        );
+        let env = to_env_map(&[(
+            "EPC_TEST_RESOLVER".to_string(),
+            "223.5.5.5,\n223.6.6.6 [2400:3200::1]".to_string(),
+        )]);
+        assert_eq!(
+            resolve_resolvers(&[], &env, "EPC_TEST_RESOLVER", "1.1.1.1").unwrap(),
+            "223.5.5.5 223.6.6.6 [2400:3200::1]"
+        );
+        let cli = [
+            "8.8.8.8,8.8.4.4".to_string(),
+            "[2001:4860:4860::8888]".to_string(),
+        ];
+        assert_eq!(
+            resolve_resolvers(&cli, &env, "EPC_TEST_RESOLVER", "1.1.1.1").unwrap(),
+            "8.8.8.8 8.8.4.4 [2001:4860:4860::8888]"
+        );
+    }
+
+    #[test]
+    fn resolved_values_are_trimmed() {
+        let empty = HashMap::new();
+        assert_eq!(
+            resolve_value(
+                Some(" example.com ".to_string()),
+                &empty,
+                "DOMAIN",
+                "Domain",
+                false
+            )
+            .unwrap(),
+            "example.com"
+        );
+        let env = to_env_map(&[(
+            "EPC_TEST_DOMAIN".to_string(),
+            "\texample.org \n".to_string(),
+        )]);
+        assert_eq!(
+            resolve_value(None, &env, "EPC_TEST_DOMAIN", "Domain", false).unwrap(),
+            "example.org"
+        );
+        assert_eq!(
+            resolve_optional_value(
+                Some("  ".to_string()),
+                &env,
+                "EPC_TEST_DOMAIN",
+                "Domain",
+                false
+            )
+            .unwrap()
+            .as_deref(),
+            Some("example.org")
+        );
+    }
+
+    #[test]
+    fn resolved_paths_are_trimmed_but_not_rewritten() {
+        let empty = HashMap::new();
+        assert_eq!(
+            resolve_optional_path(
+                Some(PathBuf::from(" /etc/my certs/ ")),
+                &empty,
+                "EPC_TEST_DIR"
+            ),
+            Some(PathBuf::from("/etc/my certs/"))
+        );
+        assert_eq!(
+            resolve_optional_path(
+                Some(PathBuf::from("./certs/../certs")),
+                &empty,
+                "EPC_TEST_DIR"
+            ),
+            Some(PathBuf::from("./certs/../certs"))
+        );
+        let env = to_env_map(&[("EPC_TEST_DIR".to_string(), " /srv/certs ".to_string())]);
+        assert_eq!(
+            resolve_optional_path(Some(PathBuf::from(" ")), &env, "EPC_TEST_DIR"),
+            Some(PathBuf::from("/srv/certs"))
+        );
+    }
+}
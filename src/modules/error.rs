@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("This command must be run as root")]
+    NotRoot,
+    #[error("{0}")]
+    MissingInput(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    ExternalCommand(String),
+    #[error("{0}")]
+    Drift(String),
+    #[error("{0}")]
+    Warnings(String),
+}
+
+impl AppError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::NotRoot => 3,
+            AppError::MissingInput(_) => 4,
+            AppError::Validation(_) => 5,
+            AppError::Unsupported(_) => 6,
+            AppError::Io(_) => 7,
+            AppError::ExternalCommand(_) => 8,
+            AppError::Drift(_) => 9,
+            AppError::Warnings(_) => 10,
+        }
+    }
+}
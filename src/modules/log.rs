@@ -1,11 +1,63 @@
+use std::{
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
 const COLOR_GREEN: &str = "\x1b[32m";
+const COLOR_YELLOW: &str = "\x1b[33m";
 const COLOR_BLUE: &str = "\x1b[34m";
 const COLOR_CYAN: &str = "\x1b[36m";
 const COLOR_BOLD: &str = "\x1b[1m";
+const COLOR_DIM: &str = "\x1b[2m";
 const COLOR_RESET: &str = "\x1b[0m";
 
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_STEP: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+type WarnHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+static FAIL_FAST: OnceLock<WarnHandler> = OnceLock::new();
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+// The handler runs right after the first warning is printed and is expected
+// not to return.
+pub fn set_fail_fast(handler: impl Fn(&str) + Send + Sync + 'static) {
+    let _ = FAIL_FAST.set(Box::new(handler));
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
 pub fn step(message: &str) {
+    finish_step();
     println!("{}{}==> {}{}", COLOR_CYAN, COLOR_BOLD, message, COLOR_RESET);
+    *CURRENT_STEP.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some((message.to_string(), Instant::now()));
+}
+
+pub fn finish_step() {
+    let current = CURRENT_STEP
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if let Some((message, started)) = current
+        && is_verbose()
+    {
+        println!(
+            "{}    {} took {:.2}s{}",
+            COLOR_DIM,
+            message,
+            started.elapsed().as_secs_f64(),
+            COLOR_RESET
+        );
+    }
 }
 
 pub fn info(message: &str) {
@@ -15,9 +67,75 @@ pub fn info(message: &str) {
     );
 }
 
+pub fn debug(message: &str) {
+    if is_verbose() {
+        println!("{}    {}{}", COLOR_DIM, message, COLOR_RESET);
+    }
+}
+
 pub fn success(message: &str) {
     println!(
         "{}{}    => {}{}{}",
         COLOR_GREEN, COLOR_BOLD, COLOR_GREEN, message, COLOR_RESET
     );
 }
+
+pub fn warn(message: &str) {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+    println!(
+        "{}{}    => {}{}{}",
+        COLOR_YELLOW, COLOR_BOLD, COLOR_YELLOW, message, COLOR_RESET
+    );
+    if let Some(handler) = FAIL_FAST.get() {
+        handler(message);
+    }
+}
+
+pub fn warning_count() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| {
+            rows.iter()
+                .filter_map(|row| row.get(index))
+                .map(|cell| cell.chars().count())
+                .fold(header.chars().count(), usize::max)
+        })
+        .collect();
+    let border = format!(
+        "{}+{}+{}",
+        COLOR_DIM,
+        widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+"),
+        COLOR_RESET
+    );
+    let line = |cells: Vec<&str>, bold: bool| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| {
+                if bold {
+                    format!(" {}{:width$}{} ", COLOR_BOLD, cell, COLOR_RESET)
+                } else {
+                    format!(" {:width$} ", cell)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        println!("|{}|", cells);
+    };
+    println!("{}", border);
+    line(headers.to_vec(), true);
+    println!("{}", border);
+    for row in rows {
+        line(row.iter().map(String::as_str).collect(), false);
+    }
+    println!("{}", border);
+}
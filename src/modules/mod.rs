@@ -1,5 +1,13 @@
+pub mod backends;
 pub mod cli;
 pub mod commands;
+pub mod config;
+pub mod dns;
 pub mod env;
+pub mod error;
 pub mod log;
+pub mod render;
+pub mod routes;
+pub mod runner;
+pub mod summary;
 pub mod templates;
@@ -0,0 +1,450 @@
+use crate::modules::{
+    error::AppError,
+    templates::{apply_custom_values, leftover_placeholders},
+};
+
+#[derive(Debug, Default)]
+pub struct TemplateContext {
+    pub geoip: Option<String>,
+    pub upstream: Option<String>,
+    pub http_listen: Option<String>,
+    pub https_listen: Option<String>,
+    pub routes: Option<String>,
+    pub path_rewrite: Option<String>,
+    pub forward_headers: Option<String>,
+    pub ssl_certificates: Option<String>,
+    pub hsts: Option<String>,
+    pub stub_status: Option<String>,
+    pub geo_gate: Option<String>,
+    pub transport: Option<String>,
+    pub ssl_ciphers: Option<String>,
+    pub ssl_protocols: Option<String>,
+    pub proxy_domain: Option<String>,
+    pub server_tokens: Option<String>,
+    pub backend_url: Option<String>,
+    pub default_return: Option<String>,
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub resolver: Option<String>,
+    pub host_header: Option<String>,
+    pub backend_tls: Option<String>,
+    pub upstreams: Option<String>,
+    pub custom_values: Vec<(String, String)>,
+}
+
+impl TemplateContext {
+    // Block placeholders sit on a line of their own; the bool marks them so an
+    // empty block also drops the line break after it.
+    fn substitutions(&self) -> [(&'static str, bool, Option<&String>); 24] {
+        [
+            ("{{GEOIP}}", true, self.geoip.as_ref()),
+            ("{{UPSTREAM}}", true, self.upstream.as_ref()),
+            ("{{HTTP_LISTEN}}", true, self.http_listen.as_ref()),
+            ("{{HTTPS_LISTEN}}", true, self.https_listen.as_ref()),
+            ("{{ROUTES}}", true, self.routes.as_ref()),
+            ("{{PATH_REWRITE}}", true, self.path_rewrite.as_ref()),
+            ("{{FORWARD_HEADERS}}", true, self.forward_headers.as_ref()),
+            ("{{SSL_CERTIFICATES}}", true, self.ssl_certificates.as_ref()),
+            ("{{HSTS}}", true, self.hsts.as_ref()),
+            ("{{STUB_STATUS}}", true, self.stub_status.as_ref()),
+            ("{{GEO_GATE}}", true, self.geo_gate.as_ref()),
+            ("{{TRANSPORT}}", true, self.transport.as_ref()),
+            ("{{SSL_CIPHERS}}", false, self.ssl_ciphers.as_ref()),
+            ("{{SSL_PROTOCOLS}}", false, self.ssl_protocols.as_ref()),
+            ("{{PROXY_DOMAIN}}", false, self.proxy_domain.as_ref()),
+            ("{{SERVER_TOKENS}}", false, self.server_tokens.as_ref()),
+            ("{{BACKEND_URL}}", false, self.backend_url.as_ref()),
+            ("{{DEFAULT_RETURN}}", false, self.default_return.as_ref()),
+            ("{{CERT_PATH}}", false, self.cert_path.as_ref()),
+            ("{{KEY_PATH}}", false, self.key_path.as_ref()),
+            ("{{RESOLVER}}", false, self.resolver.as_ref()),
+            ("{{HOST_HEADER}}", false, self.host_header.as_ref()),
+            ("{{BACKEND_TLS}}", false, self.backend_tls.as_ref()),
+            ("{{UPSTREAMS}}", false, self.upstreams.as_ref()),
+        ]
+    }
+}
+
+pub fn render(template: &str, context: &TemplateContext) -> Result<String, AppError> {
+    let content = context.substitutions().into_iter().fold(
+        template.to_string(),
+        |content, (placeholder, block, value)| match value {
+            Some(value) if block => content
+                .replace(&format!("{}\n", placeholder), value)
+                .replace(placeholder, value.trim_end_matches('\n')),
+            Some(value) => content.replace(placeholder, value),
+            None => content,
+        },
+    );
+    let content = apply_custom_values(content, &context.custom_values);
+    let leftover = leftover_placeholders(&content);
+    if !leftover.is_empty() {
+        return Err(AppError::Validation(format!(
+            "Rendered config has unresolved placeholders: {}",
+            leftover.join(", ")
+        )));
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::{
+        routes::{emby_preset_routes, render_routes},
+        templates::{
+            APACHE_PROXY_TEMPLATE, CADDY_PROXY_TEMPLATE, NGINX_DEFAULT_TEMPLATE,
+            NGINX_PROXY_TEMPLATE, NGINX_STREAM_TEMPLATE,
+        },
+    };
+
+    fn value(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    fn context() -> TemplateContext {
+        TemplateContext {
+            geoip: value(""),
+            upstream: value(""),
+            http_listen: value("    listen 80;\n"),
+            https_listen: value("    listen 443 ssl;\n"),
+            routes: value(""),
+            path_rewrite: value(""),
+            forward_headers: value("        proxy_set_header X-Real-IP $remote_addr;\n"),
+            ssl_certificates: value(
+                "    ssl_certificate /certs/example.com.cer;\n    ssl_certificate_key /certs/example.com.key;\n",
+            ),
+            hsts: value(""),
+            stub_status: value(""),
+            geo_gate: value(""),
+            transport: value(""),
+            ssl_ciphers: value("ECDHE-ECDSA-AES128-GCM-SHA256"),
+            ssl_protocols: value("TLSv1.2 TLSv1.3"),
+            proxy_domain: value("proxy.example.com"),
+            server_tokens: value("off"),
+            backend_url: value("https://emby.example.com"),
+            default_return: value("444"),
+            cert_path: value("/certs/example.com.cer"),
+            key_path: value("/certs/example.com.key"),
+            resolver: value("1.1.1.1 8.8.8.8"),
+            host_header: value("$proxy_host"),
+            backend_tls: value("proxy_ssl_server_name on;"),
+            upstreams: value("https://emby.example.com"),
+            custom_values: Vec::new(),
+        }
+    }
+
+    fn unresolved(template: &str, context: &TemplateContext) -> String {
+        match render(template, context) {
+            Err(AppError::Validation(message)) => message,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nginx_proxy_template_resolves_every_placeholder() {
+        assert!(render(NGINX_PROXY_TEMPLATE, &context()).is_ok());
+        let context = TemplateContext {
+            resolver: None,
+            ..context()
+        };
+        assert_eq!(
+            unresolved(NGINX_PROXY_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{RESOLVER}}"
+        );
+    }
+
+    #[test]
+    fn nginx_route_template_resolves_every_placeholder() {
+        let context = TemplateContext {
+            routes: Some(render_routes(&emby_preset_routes())),
+            ..context()
+        };
+        assert!(render(NGINX_PROXY_TEMPLATE, &context).is_ok());
+        let context = TemplateContext {
+            path_rewrite: None,
+            ..context
+        };
+        assert_eq!(
+            unresolved(NGINX_PROXY_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{PATH_REWRITE}}"
+        );
+    }
+
+    #[test]
+    fn nginx_stream_template_resolves_every_placeholder() {
+        assert!(render(NGINX_STREAM_TEMPLATE, &context()).is_ok());
+        let context = TemplateContext {
+            upstream: None,
+            ..context()
+        };
+        assert_eq!(
+            unresolved(NGINX_STREAM_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{UPSTREAM}}"
+        );
+    }
+
+    #[test]
+    fn nginx_default_template_resolves_every_placeholder() {
+        assert!(render(NGINX_DEFAULT_TEMPLATE, &context()).is_ok());
+        let context = TemplateContext {
+            default_return: None,
+            server_tokens: None,
+            ..context()
+        };
+        assert_eq!(
+            unresolved(NGINX_DEFAULT_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{SERVER_TOKENS}}, {{DEFAULT_RETURN}}"
+        );
+    }
+
+    #[test]
+    fn caddy_template_resolves_every_placeholder() {
+        assert!(render(CADDY_PROXY_TEMPLATE, &context()).is_ok());
+        let context = TemplateContext {
+            transport: None,
+            ..context()
+        };
+        assert_eq!(
+            unresolved(CADDY_PROXY_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{TRANSPORT}}"
+        );
+    }
+
+    #[test]
+    fn apache_template_resolves_every_placeholder() {
+        assert!(render(APACHE_PROXY_TEMPLATE, &context()).is_ok());
+        let context = TemplateContext {
+            host_header: None,
+            ..context()
+        };
+        assert_eq!(
+            unresolved(APACHE_PROXY_TEMPLATE, &context),
+            "Rendered config has unresolved placeholders: {{HOST_HEADER}}"
+        );
+    }
+
+    #[test]
+    fn block_placeholders_match_without_a_trailing_newline() {
+        let context = TemplateContext {
+            hsts: value("add_header Strict-Transport-Security \"max-age=63072000\" always;\n"),
+            geo_gate: value(""),
+            ..TemplateContext::default()
+        };
+        assert_eq!(
+            render("a\n{{HSTS}}", &context).unwrap(),
+            "a\nadd_header Strict-Transport-Security \"max-age=63072000\" always;"
+        );
+        assert_eq!(
+            render(
+                "{{HSTS}} # hardening\n{{GEO_GATE}}\nb\n{{GEO_GATE}}",
+                &context
+            )
+            .unwrap(),
+            "add_header Strict-Transport-Security \"max-age=63072000\" always; # hardening\nb\n"
+        );
+    }
+
+    #[test]
+    fn custom_values_fill_template_placeholders() {
+        let context = TemplateContext {
+            custom_values: vec![("REGION".to_string(), "eu".to_string())],
+            ..TemplateContext::default()
+        };
+        assert_eq!(
+            render("region {{REGION}};", &context).unwrap(),
+            "region eu;"
+        );
+        assert_eq!(
+            unresolved("region {{ZONE}};", &context),
+            "Rendered config has unresolved placeholders: {{ZONE}}"
+        );
+    }
+
+    #[test]
+    fn renders_nginx_default_config() {
+        assert_eq!(
+            render(NGINX_DEFAULT_TEMPLATE, &context()).unwrap(),
+            "server {
+    listen 80;
+
+    server_name _;
+    server_tokens off;
+    return 444;
+}
+
+server {
+    listen 443 ssl;
+    http2 on;
+
+    server_name _;
+    server_tokens off;
+
+    ssl_certificate /certs/example.com.cer;
+    ssl_certificate_key /certs/example.com.key;
+
+    ssl_protocols TLSv1.2 TLSv1.3;
+
+    location / {
+        return 444;
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn renders_nginx_stream_config() {
+        let context = TemplateContext {
+            upstream: value("upstream emby_backend {\n    server 10.0.0.2:443;\n}\n\n"),
+            backend_url: value("emby_backend"),
+            ..context()
+        };
+        assert_eq!(
+            render(NGINX_STREAM_TEMPLATE, &context).unwrap(),
+            "upstream emby_backend {
+    server 10.0.0.2:443;
+}
+
+server {
+    listen 443 ssl;
+
+    ssl_preread on;
+
+    resolver 1.1.1.1 8.8.8.8;
+    resolver_timeout 5s;
+
+    proxy_pass emby_backend;
+    proxy_connect_timeout 10s;
+    proxy_timeout 1h;
+}
+"
+        );
+    }
+
+    #[test]
+    fn renders_nginx_proxy_config() {
+        let context = TemplateContext {
+            routes: Some(render_routes(&emby_preset_routes()[2..])),
+            path_rewrite: value("        rewrite ^/(.*)$ /emby/$1 break;\n"),
+            hsts: value("    add_header Strict-Transport-Security \"max-age=63072000\" always;\n"),
+            ..context()
+        };
+        let content = render(NGINX_PROXY_TEMPLATE, &context).unwrap();
+        assert!(
+            content.starts_with("server {\n    listen 80;\n\n    server_name proxy.example.com;\n")
+        );
+        assert!(content.contains(
+            "    ssl_certificate_key /certs/example.com.key;
+    add_header Strict-Transport-Security \"max-age=63072000\" always;
+
+    ssl_protocols TLSv1.2 TLSv1.3;
+    ssl_ciphers ECDHE-ECDSA-AES128-GCM-SHA256;
+"
+        ));
+        assert!(
+            content.contains(
+                "    location ^~ /web/ {\n        set $website https://emby.example.com;\n"
+            )
+        );
+        assert!(content.contains(
+            "        proxy_intercept_errors off;\n        error_page 301 302 307 = @handle_redirect;\n    }\n"
+        ));
+        assert!(content.contains(
+            "    location / {
+        set $website https://emby.example.com;
+        rewrite ^/(.*)$ /emby/$1 break;
+        proxy_pass $website;
+
+        proxy_http_version 1.1;
+        proxy_set_header Host $proxy_host;
+        proxy_set_header X-Real-IP $remote_addr;
+        proxy_ssl_server_name on;
+"
+        ));
+    }
+
+    #[test]
+    fn renders_caddy_config() {
+        let context = TemplateContext {
+            host_header: value("{upstream_hostport}"),
+            hsts: value("\theader Strict-Transport-Security \"max-age=63072000\"\n"),
+            transport: value(
+                "\t\ttransport http {\n\t\t\tresolvers 1.1.1.1\n\t\t\ttls_server_name emby.example.com\n\t\t}\n",
+            ),
+            ..context()
+        };
+        assert_eq!(
+            render(CADDY_PROXY_TEMPLATE, &context).unwrap(),
+            "proxy.example.com {
+\ttls /certs/example.com.cer /certs/example.com.key
+\theader Strict-Transport-Security \"max-age=63072000\"
+\treverse_proxy https://emby.example.com {
+\t\theader_up Host {upstream_hostport}
+\t\theader_up X-Real-IP {remote_host}
+\t\ttransport http {
+\t\t\tresolvers 1.1.1.1
+\t\t\ttls_server_name emby.example.com
+\t\t}
+\t}
+}
+"
+        );
+    }
+
+    #[test]
+    fn renders_apache_config() {
+        let context = TemplateContext {
+            ssl_certificates: value(
+                "    SSLCertificateFile /certs/example.com.cer\n    SSLCertificateKeyFile /certs/example.com.key\n",
+            ),
+            host_header: value("    ProxyPreserveHost Off"),
+            backend_tls: value("    SSLProxyEngine on"),
+            ..context()
+        };
+        assert_eq!(
+            render(APACHE_PROXY_TEMPLATE, &context).unwrap(),
+            "<VirtualHost *:80>
+    ServerName proxy.example.com
+
+    Redirect permanent / https://proxy.example.com/
+</VirtualHost>
+
+<VirtualHost *:443>
+    ServerName proxy.example.com
+
+    SSLEngine on
+    SSLCertificateFile /certs/example.com.cer
+    SSLCertificateKeyFile /certs/example.com.key
+
+    ProxyRequests Off
+    ProxyPreserveHost Off
+    SSLProxyEngine on
+    ProxyPass / https://emby.example.com/ upgrade=websocket
+    ProxyPassReverse / https://emby.example.com/
+
+    RequestHeader set X-Forwarded-Proto \"https\"
+    RequestHeader set X-Forwarded-Port \"443\"
+</VirtualHost>
+"
+        );
+    }
+
+    // Both generated nginx configs now hide the version in every server block
+    // unless --server-tokens on is passed.
+    #[test]
+    fn nginx_configs_hide_server_tokens_by_default() {
+        for template in [NGINX_PROXY_TEMPLATE, NGINX_DEFAULT_TEMPLATE] {
+            let content = render(template, &context()).unwrap();
+            assert_eq!(content.matches("    server_tokens off;\n").count(), 2);
+            assert!(!content.contains("server_tokens on;"));
+
+            let context = TemplateContext {
+                server_tokens: value("on"),
+                ..context()
+            };
+            let content = render(template, &context).unwrap();
+            assert_eq!(content.matches("    server_tokens on;\n").count(), 2);
+        }
+    }
+}
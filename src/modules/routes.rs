@@ -0,0 +1,130 @@
+use crate::modules::{error::AppError, templates::NGINX_ROUTE_TEMPLATE};
+use std::{fs, path::Path};
+
+const ROUTE_DEFAULTS: [(&str, &str); 9] = [
+    ("proxy_buffering", "on"),
+    ("proxy_buffer_size", "2m"),
+    ("proxy_buffers", "32 2m"),
+    ("proxy_busy_buffers_size", "4m"),
+    ("proxy_connect_timeout", "60s"),
+    ("proxy_send_timeout", "60s"),
+    ("proxy_read_timeout", "60s"),
+    ("proxy_intercept_errors", "on"),
+    ("error_page", "301 302 307 = @handle_redirect"),
+];
+
+const EMBY_PRESET: [(&str, &str); 3] = [
+    (
+        "/embywebsocket",
+        "proxy_buffering off; proxy_send_timeout 3600s; proxy_read_timeout 3600s;",
+    ),
+    (
+        "/Videos/",
+        "proxy_buffering off; proxy_request_buffering off; proxy_read_timeout 3600s;",
+    ),
+    ("/web/", "proxy_intercept_errors off;"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub path: String,
+    pub directives: Vec<(String, String)>,
+}
+
+pub fn resolve_routes(
+    routes_file: Option<&Path>,
+    emby_preset: bool,
+) -> Result<Vec<Route>, AppError> {
+    let mut routes = if emby_preset {
+        emby_preset_routes()
+    } else {
+        Vec::new()
+    };
+    if let Some(path) = routes_file {
+        let content = fs::read_to_string(path)
+            .map_err(|e| AppError::Io(format!("Failed to read {}: {e}", path.display())))?;
+        for route in parse_routes(&content)? {
+            routes.retain(|existing| existing.path != route.path);
+            routes.push(route);
+        }
+    }
+    Ok(routes)
+}
+
+pub fn emby_preset_routes() -> Vec<Route> {
+    EMBY_PRESET
+        .iter()
+        .map(|(path, directives)| Route {
+            path: path.to_string(),
+            directives: parse_directives(directives),
+        })
+        .collect()
+}
+
+pub fn render_routes(routes: &[Route]) -> String {
+    routes
+        .iter()
+        .map(|route| {
+            let mut directives: Vec<(String, String)> = ROUTE_DEFAULTS
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            for (key, value) in &route.directives {
+                match directives.iter_mut().find(|(existing, _)| existing == key) {
+                    Some(entry) => entry.1 = value.clone(),
+                    None => directives.push((key.clone(), value.clone())),
+                }
+            }
+            let directives = directives
+                .iter()
+                .map(|(key, value)| {
+                    if value.is_empty() {
+                        format!("{};", key)
+                    } else {
+                        format!("{} {};", key, value)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n        ");
+            NGINX_ROUTE_TEMPLATE
+                .replace("{{ROUTE_PATH}}", &route.path)
+                .replace("{{ROUTE_DIRECTIVES}}", &directives)
+        })
+        .collect()
+}
+
+fn parse_routes(content: &str) -> Result<Vec<Route>, AppError> {
+    let mut routes = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (path, directives) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if !path.starts_with('/') {
+            return Err(AppError::Validation(format!(
+                "Invalid route on line {}: path must start with '/'",
+                index + 1
+            )));
+        }
+        routes.push(Route {
+            path: path.to_string(),
+            directives: parse_directives(directives),
+        });
+    }
+    Ok(routes)
+}
+
+fn parse_directives(directives: &str) -> Vec<(String, String)> {
+    directives
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| {
+            let (key, value) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            (key.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,262 @@
+use crate::modules::summary::trace_command;
+use std::{
+    io::{self, Write},
+    process::{Command, ExitStatus, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub trait CommandRunner: Sync {
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+    fn status_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<ExitStatus>;
+    fn output_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<Output>;
+    fn status_with_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<ExitStatus>;
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        let result = cmd.status();
+        trace_command(cmd, &result);
+        result
+    }
+
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let result = cmd.output();
+        trace_command(
+            cmd,
+            &result
+                .as_ref()
+                .map(|output| output.status)
+                .map_err(clone_error),
+        );
+        result
+    }
+
+    fn status_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<ExitStatus> {
+        let result = spawn_with_input(cmd, input);
+        trace_command(cmd, &result);
+        result
+    }
+
+    fn output_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<Output> {
+        let result = output_with_input(cmd, input);
+        trace_command(
+            cmd,
+            &result
+                .as_ref()
+                .map(|output| output.status)
+                .map_err(clone_error),
+        );
+        result
+    }
+
+    fn status_with_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<ExitStatus> {
+        let result = spawn_with_timeout(cmd, timeout);
+        trace_command(cmd, &result);
+        result
+    }
+}
+
+// stdin is fed from its own thread so a child that fills its stdout pipe
+// before reading all of its input cannot deadlock against us.
+fn output_with_input(cmd: &mut Command, input: &[u8]) -> io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+    thread::scope(|scope| {
+        let writer = scope.spawn(move || match stdin {
+            Some(mut stdin) => stdin.write_all(input),
+            None => Ok(()),
+        });
+        let output = child.wait_with_output()?;
+        match writer.join() {
+            // A child may exit without reading everything it was sent.
+            Ok(Err(e)) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            Err(_) => Err(io::Error::other("stdin writer panicked")),
+            _ => Ok(output),
+        }
+    })
+}
+
+fn spawn_with_input(cmd: &mut Command, input: &[u8]) -> io::Result<ExitStatus> {
+    let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin.write_all(input)?;
+    }
+    child.wait()
+}
+
+fn spawn_with_timeout(cmd: &mut Command, timeout: Duration) -> io::Result<ExitStatus> {
+    let mut child = cmd.spawn()?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {}s", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+fn clone_error(error: &io::Error) -> io::Error {
+    io::Error::new(error.kind(), error.to_string())
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::CommandRunner;
+    use std::{
+        io,
+        os::unix::process::ExitStatusExt,
+        process::{Command, ExitStatus, Output},
+        sync::Mutex,
+        time::Duration,
+    };
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Recorded {
+        pub argv: Vec<String>,
+        pub envs: Vec<(String, String)>,
+        pub input: Option<String>,
+    }
+
+    // Records every command instead of running it. `id -u` reports root so
+    // commands get past ensure_root; everything else succeeds with no output
+    // unless a canned stdout was registered for the program.
+    #[derive(Default)]
+    pub struct RecordingRunner {
+        recorded: Mutex<Vec<Recorded>>,
+        stdout: Vec<(String, String)>,
+    }
+
+    impl RecordingRunner {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with_stdout(mut self, program: &str, stdout: &str) -> Self {
+            self.stdout.push((program.to_string(), stdout.to_string()));
+            self
+        }
+
+        pub fn recorded(&self) -> Vec<Recorded> {
+            self.recorded
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        }
+
+        // argv of every call whose program path ends with `program`.
+        pub fn argvs(&self, program: &str) -> Vec<Vec<String>> {
+            self.recorded()
+                .into_iter()
+                .filter(|recorded| recorded.argv[0].ends_with(program))
+                .map(|recorded| recorded.argv)
+                .collect()
+        }
+
+        fn record(&self, cmd: &Command, input: Option<&[u8]>) -> String {
+            let argv: Vec<String> = std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect();
+            let envs = cmd
+                .get_envs()
+                .filter_map(|(key, value)| {
+                    Some((
+                        key.to_string_lossy().to_string(),
+                        value?.to_string_lossy().to_string(),
+                    ))
+                })
+                .collect();
+            let program = argv[0].clone();
+            self.recorded
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(Recorded {
+                    argv,
+                    envs,
+                    input: input.map(|input| String::from_utf8_lossy(input).to_string()),
+                });
+            match program.as_str() {
+                "id" => "0\n".to_string(),
+                _ => self
+                    .stdout
+                    .iter()
+                    .find(|(name, _)| program.ends_with(name.as_str()))
+                    .map(|(_, stdout)| stdout.clone())
+                    .unwrap_or_default(),
+            }
+        }
+    }
+
+    fn output(stdout: String) -> Output {
+        Output {
+            status: ExitStatus::from_raw(0),
+            stdout: stdout.into_bytes(),
+            stderr: Vec::new(),
+        }
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+            self.record(cmd, None);
+            Ok(ExitStatus::from_raw(0))
+        }
+
+        fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+            Ok(output(self.record(cmd, None)))
+        }
+
+        fn status_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<ExitStatus> {
+            self.record(cmd, Some(input));
+            Ok(ExitStatus::from_raw(0))
+        }
+
+        fn output_with_input(&self, cmd: &mut Command, input: &[u8]) -> io::Result<Output> {
+            Ok(output(self.record(cmd, Some(input))))
+        }
+
+        fn status_with_timeout(&self, cmd: &mut Command, _: Duration) -> io::Result<ExitStatus> {
+            self.record(cmd, None);
+            Ok(ExitStatus::from_raw(0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_with_input_survives_output_larger_than_the_pipe_buffer() {
+        // cat echoes its input, so 1 MiB in means 1 MiB out; writing all of
+        // stdin before draining stdout would block forever.
+        let input = vec![b'x'; 1 << 20];
+        let output = output_with_input(&mut Command::new("cat"), &input).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), input.len());
+    }
+
+    #[test]
+    fn output_with_input_tolerates_a_child_that_ignores_stdin() {
+        let input = vec![b'x'; 1 << 20];
+        let output = output_with_input(Command::new("sh").args(["-c", "echo ok"]), &input).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+    }
+}
@@ -0,0 +1,100 @@
+use crate::modules::error::AppError;
+use serde_json::{Map, Value, json};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    process::{Command, ExitStatus},
+    sync::Mutex,
+};
+
+static PARAMS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+static CHANGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static TRACE: Mutex<Option<File>> = Mutex::new(None);
+
+pub fn start_trace(path: &Path) -> Result<(), AppError> {
+    let file = File::create(path)
+        .map_err(|e| AppError::Io(format!("Failed to create {}: {e}", path.display())))?;
+    *TRACE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+pub fn trace_event(event: Value) {
+    let mut trace = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = trace.as_mut() {
+        let _ = writeln!(file, "{}", event);
+    }
+}
+
+pub fn trace_command(cmd: &Command, result: &io::Result<ExitStatus>) {
+    let args: Vec<String> = cmd
+        .get_args()
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect();
+    let (exit_code, error) = match result {
+        Ok(status) => (status.code(), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    trace_event(json!({
+        "event": "command",
+        "program": cmd.get_program().to_string_lossy(),
+        "args": args,
+        "exit_code": exit_code,
+        "error": error,
+    }));
+}
+
+pub fn record_param(key: &str, value: &str, sensitive: bool) {
+    let value = if sensitive {
+        "***".to_string()
+    } else {
+        value.to_string()
+    };
+    trace_event(json!({ "event": "param", "key": key, "value": value }));
+    let mut params = PARAMS.lock().unwrap_or_else(|e| e.into_inner());
+    match params.iter_mut().find(|(existing, _)| existing == key) {
+        Some(entry) => entry.1 = value,
+        None => params.push((key.to_string(), value)),
+    }
+}
+
+pub fn record_change(change: impl Into<String>) {
+    let change = change.into();
+    trace_event(json!({ "event": "change", "target": change }));
+    CHANGES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(change);
+}
+
+pub fn change_count() -> usize {
+    CHANGES.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+pub fn write_summary(
+    path: &Path,
+    command: &str,
+    dry_run: bool,
+    result: &Result<(), AppError>,
+) -> Result<(), AppError> {
+    let params: Map<String, Value> = PARAMS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+        .collect();
+    let changes = CHANGES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let summary = json!({
+        "command": command,
+        "dry_run": dry_run,
+        "status": if result.is_ok() { "success" } else { "failed" },
+        "exit_code": result.as_ref().err().map_or(0, AppError::exit_code),
+        "error": result.as_ref().err().map(ToString::to_string),
+        "parameters": params,
+        "changes": changes,
+    });
+    let content = serde_json::to_string_pretty(&summary)
+        .map_err(|e| AppError::Io(format!("Failed to serialize summary: {e}")))?;
+    fs::write(path, content + "\n")
+        .map_err(|e| AppError::Io(format!("Failed to write {}: {e}", path.display())))
+}
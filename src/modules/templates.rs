@@ -1,2 +1,81 @@
+use crate::modules::error::AppError;
+
+pub const APACHE_PROXY_TEMPLATE: &str = include_str!("../../assets/apache_proxy.conf.tmpl");
+pub const CADDY_PROXY_TEMPLATE: &str = include_str!("../../assets/caddy_proxy.caddy.tmpl");
 pub const NGINX_DEFAULT_TEMPLATE: &str = include_str!("../../assets/nginx_default.conf.tmpl");
 pub const NGINX_PROXY_TEMPLATE: &str = include_str!("../../assets/nginx_proxy.conf.tmpl");
+pub const NGINX_ROUTE_TEMPLATE: &str = include_str!("../../assets/nginx_route.conf.tmpl");
+pub const NGINX_STREAM_TEMPLATE: &str = include_str!("../../assets/nginx_stream.conf.tmpl");
+
+const RESERVED_PLACEHOLDERS: [&str; 26] = [
+    "BACKEND_TLS",
+    "BACKEND_URL",
+    "CERT_PATH",
+    "DEFAULT_RETURN",
+    "FORWARD_HEADERS",
+    "GEOIP",
+    "GEO_GATE",
+    "HOST_HEADER",
+    "HSTS",
+    "HTTPS_LISTEN",
+    "HTTP_LISTEN",
+    "KEY_PATH",
+    "PATH_REWRITE",
+    "PROXY_DOMAIN",
+    "RESOLVER",
+    "ROUTES",
+    "ROUTE_DIRECTIVES",
+    "ROUTE_PATH",
+    "SERVER_TOKENS",
+    "SSL_CERTIFICATES",
+    "SSL_CIPHERS",
+    "SSL_PROTOCOLS",
+    "STUB_STATUS",
+    "TRANSPORT",
+    "UPSTREAM",
+    "UPSTREAMS",
+];
+
+pub fn check_custom_values(values: &[(String, String)]) -> Result<(), AppError> {
+    for (key, _) in values {
+        if !key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(AppError::Validation(format!(
+                "--set key {} must use A-Z, 0-9 and _ only",
+                key
+            )));
+        }
+        if RESERVED_PLACEHOLDERS.contains(&key.as_str()) {
+            return Err(AppError::Validation(format!(
+                "--set key {} collides with the built-in {{{{{}}}}} placeholder",
+                key, key
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn apply_custom_values(content: String, values: &[(String, String)]) -> String {
+    values.iter().fold(content, |content, (key, value)| {
+        content.replace(&format!("{{{{{}}}}}", key), value)
+    })
+}
+
+pub fn leftover_placeholders(content: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let token = format!("{{{{{}}}}}", &after[..end]);
+        if !found.contains(&token) {
+            found.push(token);
+        }
+        rest = &after[end + 2..];
+    }
+    found
+}